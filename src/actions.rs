@@ -0,0 +1,79 @@
+//! Workflow commands for surfacing messages in the GitHub Actions UI
+//!
+//! See <https://docs.github.com/en/actions/reference/workflow-commands-for-github-actions>
+//!
+//! The runner reads commands from stderr too, so they are printed there to keep
+//! the result on stdout valid with --format json
+
+use std::path::Path;
+
+use error_stack::{Result, ResultExt};
+use tokio::io::AsyncWriteExt;
+
+use crate::{output::warning, Error};
+
+/// Check if magnesis is running inside GitHub Actions
+pub fn is_github_actions() -> bool {
+    std::env::var("GITHUB_ACTIONS").is_ok_and(|v| v == "true")
+}
+
+/// Print a notice annotation
+pub fn notice(message: &str) {
+    eprintln!("{}", command("notice", message));
+}
+
+/// Print an error annotation
+pub fn error(message: &str) {
+    eprintln!("{}", command("error", message));
+}
+
+fn command(name: &str, message: &str) -> String {
+    format!("::{} title=magnesis::{}", name, escape_data(message))
+}
+
+/// Escape the message so it stays on one line and is not parsed as a command
+fn escape_data(message: &str) -> String {
+    message
+        .replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+/// Append the Markdown to the job summary, from `GITHUB_STEP_SUMMARY`
+pub async fn write_step_summary(markdown: &str) -> Result<(), Error> {
+    let Some(path) = std::env::var_os("GITHUB_STEP_SUMMARY") else {
+        warning!("GITHUB_STEP_SUMMARY is not set, not writing the step summary");
+        return Ok(());
+    };
+    let write = async {
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await?;
+        file.write_all(markdown.as_bytes()).await?;
+        // the write finishes in the background otherwise
+        file.flush().await
+    };
+    write
+        .await
+        .change_context(Error::StepSummary)
+        .attach_printable_lazy(|| format!("path: {}", Path::new(&path).display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command() {
+        assert_eq!(
+            command("error", "failed to download\n50% done\r"),
+            "::error title=magnesis::failed to download%0A50%25 done%0D"
+        );
+        assert_eq!(
+            command("notice", "downloaded 2 artifacts"),
+            "::notice title=magnesis::downloaded 2 artifacts"
+        );
+    }
+}
@@ -0,0 +1,1512 @@
+use std::{
+    collections::{hash_map::Entry, HashMap, HashSet},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::{
+    checksum::sha256_hex,
+    extract::{extract, save_normalized_zip, save_with_index, ExtractOptions, ExtractedFile},
+    github::{
+        error_for_status, get_check_suite_runs, get_run, get_run_attempt, get_run_jobs,
+        warn_if_deprecated, Api, Job, Schema,
+    },
+    glob::glob_match,
+    output::{inherit, inherit_blocking, progress},
+    retry::{RetryPolicy, Status},
+    signed_url::{parse_rfc3339, SignedUrl},
+    timings::ArtifactTiming,
+    Error,
+};
+use error_stack::{report, Report, Result, ResultExt};
+use reqwest::{header::LOCATION, Response};
+use tokio::{
+    sync::{OnceCell, Semaphore},
+    task::JoinSet,
+};
+use tokio_util::sync::CancellationToken;
+
+/// What to do when an artifact was deleted after it was listed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum OnDeleted {
+    /// Stop with an error
+    #[default]
+    Fail,
+    /// Print a warning and continue with the other artifacts
+    Skip,
+}
+
+/// Check if the download failed because the artifact or its run was deleted
+pub fn is_deleted(err: &Report<Error>) -> bool {
+    err.frames()
+        .any(|frame| matches!(frame.downcast_ref::<Error>(), Some(Error::Deleted)))
+}
+
+/// Number of artifacts to request per page when listing
+const PER_PAGE: u64 = 100;
+
+/// Get all artifacts in the repo
+pub async fn get_artifacts(api: &Arc<Api>, repo: &str, jobs: usize) -> Result<Artifacts, Error> {
+    let url = api.url(&format!("repos/{}/actions/artifacts", repo));
+    get_artifact_pages(api, &url, jobs).await
+}
+
+/// Get all artifacts from a listing endpoint
+///
+/// The first page tells how many artifacts there are, then the remaining pages
+/// are fetched with up to `jobs` requests at the same time
+async fn get_artifact_pages(api: &Arc<Api>, url: &str, jobs: usize) -> Result<Artifacts, Error> {
+    let url = |page: u64| format!("{}?per_page={}&page={}", url, PER_PAGE, page);
+    let first: Artifacts = api.get_json(&url(1)).await?;
+    let total_count = first.total_count;
+    let pages = total_count.unwrap_or_default().div_ceil(PER_PAGE);
+
+    let permits = Arc::new(Semaphore::new(jobs.max(1)));
+    let mut handles = JoinSet::new();
+    for page in 2..=pages {
+        let api = Arc::clone(api);
+        let permits = Arc::clone(&permits);
+        let url = url(page);
+        handles.spawn(inherit(async move {
+            let _permit = permits.acquire().await.change_context(Error::Request)?;
+            let artifacts: Artifacts = api
+                .get_json(&url)
+                .await
+                .attach_printable_lazy(|| format!("page: {}", page))?;
+            Ok::<_, Report<Error>>((page, artifacts.artifacts))
+        }));
+    }
+    let mut rest = Vec::new();
+    while let Some(result) = handles.join_next().await {
+        rest.push(result.change_context(Error::Request)??);
+    }
+    rest.sort_by_key(|(page, _)| *page);
+
+    // artifacts created while listing shift the pages, so one can show up twice
+    let mut seen = HashSet::new();
+    let artifacts = first
+        .artifacts
+        .into_iter()
+        .chain(rest.into_iter().flat_map(|(_, artifacts)| artifacts))
+        .filter(|artifact| seen.insert(artifact.id))
+        .collect();
+
+    Ok(Artifacts {
+        total_count,
+        artifacts,
+    })
+}
+
+/// Get the artifacts in the repo created less than `max_age` ago, newest first
+///
+/// The API lists the newest artifacts first, so pages are fetched one at a time
+/// until an older artifact shows up
+pub async fn get_artifacts_since(
+    api: &Api,
+    repo: &str,
+    max_age: Duration,
+) -> Result<Vec<Artifact>, Error> {
+    let mut seen = HashSet::new();
+    let mut artifacts = Vec::new();
+    for page in 1.. {
+        let listed: Artifacts = api
+            .get_json(&api.url(&format!(
+                "repos/{}/actions/artifacts?per_page={}&page={}",
+                repo, PER_PAGE, page
+            )))
+            .await
+            .attach_printable_lazy(|| format!("page: {}", page))?;
+        let len = listed.artifacts.len() as u64;
+        let mut done = len < PER_PAGE;
+        for artifact in listed.artifacts {
+            if artifact.age().is_some_and(|age| age > max_age) {
+                done = true;
+                continue;
+            }
+            if seen.insert(artifact.id) {
+                artifacts.push(artifact);
+            }
+        }
+        if done {
+            break;
+        }
+    }
+    Ok(artifacts)
+}
+
+/// Get the artifacts uploaded by the workflow run
+pub async fn get_run_artifacts(
+    api: &Arc<Api>,
+    repo: &str,
+    run_id: u64,
+    jobs: usize,
+) -> Result<Artifacts, Error> {
+    let url = api.url(&format!("repos/{}/actions/runs/{}/artifacts", repo, run_id));
+    get_artifact_pages(api, &url, jobs)
+        .await
+        .attach_printable_lazy(|| format!("run: {}", run_id))
+}
+
+/// Get the artifacts from all workflow runs in the check suite,
+/// along with the commit the check suite ran on
+pub async fn get_check_suite_artifacts(
+    api: &Arc<Api>,
+    repo: &str,
+    check_suite: u64,
+    jobs: usize,
+) -> Result<(Vec<Artifact>, String), Error> {
+    let runs = get_check_suite_runs(api, repo, check_suite).await?;
+    let Some(rev) = runs.first().map(|run| run.head_sha.clone()) else {
+        return Err(report!(Error::GetWorkflowRuns)).attach_printable(format!(
+            "no workflow runs found for check suite {}",
+            check_suite
+        ));
+    };
+    let mut artifacts = Vec::new();
+    for run in runs {
+        artifacts.extend(get_run_artifacts(api, repo, run.id, jobs).await?.artifacts);
+    }
+
+    Ok((artifacts, rev))
+}
+
+/// Keep the artifacts uploaded by a job whose name matches one of the globs
+///
+/// The artifacts API doesn't link an artifact to the job that uploaded it,
+/// so an artifact is considered uploaded by a job of its run if it was
+/// created while the job was running
+pub async fn filter_by_job(
+    api: &Api,
+    repo: &str,
+    artifacts: Vec<Artifact>,
+    patterns: &[String],
+) -> Result<Vec<Artifact>, Error> {
+    let mut jobs = HashMap::new();
+    for artifact in &artifacts {
+        let Some(run_id) = artifact.workflow_run.id else {
+            continue;
+        };
+        if let Entry::Vacant(entry) = jobs.entry(run_id) {
+            let run_jobs = get_run_jobs(api, repo, run_id).await?;
+            entry.insert(
+                run_jobs
+                    .into_iter()
+                    .filter(|job| patterns.iter().any(|p| glob_match(p, &job.name)))
+                    .collect::<Vec<_>>(),
+            );
+        }
+    }
+    Ok(select_by_jobs(artifacts, &jobs))
+}
+
+/// Keep the artifacts uploaded by one of the jobs, keyed by run ID
+fn select_by_jobs(artifacts: Vec<Artifact>, jobs: &HashMap<u64, Vec<Job>>) -> Vec<Artifact> {
+    artifacts
+        .into_iter()
+        .filter(|artifact| {
+            let run_jobs = artifact
+                .workflow_run
+                .id
+                .and_then(|run_id| jobs.get(&run_id));
+            run_jobs.is_some_and(|run_jobs| run_jobs.iter().any(|job| artifact.is_uploaded_by(job)))
+        })
+        .collect()
+}
+
+/// When an attempt of a workflow run was running
+#[derive(Debug)]
+struct AttemptWindow {
+    attempt: u64,
+    started_at: String,
+    /// When the next attempt started, `None` for the latest attempt
+    next_started_at: Option<String>,
+}
+
+/// Keep the artifacts uploaded by the attempt of their workflow run, for --attempt
+///
+/// Like for jobs, the artifacts API doesn't say which attempt uploaded an artifact,
+/// so an artifact is considered uploaded by the attempt if it was created after the
+/// attempt started and before the next one started
+pub async fn filter_by_attempt(
+    api: &Api,
+    repo: &str,
+    artifacts: Vec<Artifact>,
+    attempt: u64,
+) -> Result<Vec<Artifact>, Error> {
+    let mut windows = HashMap::new();
+    for artifact in &artifacts {
+        let Some(run_id) = artifact.workflow_run.id else {
+            continue;
+        };
+        let Entry::Vacant(entry) = windows.entry(run_id) else {
+            continue;
+        };
+        let latest = get_run(api, repo, run_id).await?.run_attempt.unwrap_or(1);
+        if attempt > latest {
+            progress!("run {} only has {} attempts, skipping it", run_id, latest);
+            continue;
+        }
+        let started_at = get_run_attempt(api, repo, run_id, attempt)
+            .await?
+            .run_started_at;
+        let next_started_at = if attempt < latest {
+            get_run_attempt(api, repo, run_id, attempt + 1)
+                .await?
+                .run_started_at
+        } else {
+            None
+        };
+        if let Some(started_at) = started_at {
+            entry.insert(AttemptWindow {
+                attempt,
+                started_at,
+                next_started_at,
+            });
+        }
+    }
+    Ok(select_by_attempt(artifacts, &windows))
+}
+
+/// Keep the artifacts created in the attempt window of their run, keyed by run ID
+fn select_by_attempt(
+    artifacts: Vec<Artifact>,
+    windows: &HashMap<u64, AttemptWindow>,
+) -> Vec<Artifact> {
+    artifacts
+        .into_iter()
+        .filter_map(|mut artifact| {
+            let window = windows.get(&artifact.workflow_run.id?)?;
+            let created_at = artifact.created_at.as_ref()?;
+            // timestamps from the API can be compared as strings, see `is_uploaded_by`
+            let in_window = created_at >= &window.started_at
+                && window
+                    .next_started_at
+                    .as_ref()
+                    .is_none_or(|next_started_at| created_at < next_started_at);
+            if !in_window {
+                return None;
+            }
+            artifact.workflow_run.run_attempt = Some(window.attempt);
+            Some(artifact)
+        })
+        .collect()
+}
+
+/// Sort the artifacts by name, and by ID for artifacts with the same name
+pub fn sort_artifacts(artifacts: &mut [Artifact]) {
+    artifacts.sort_by(|a, b| a.name.cmp(&b.name).then(a.id.cmp(&b.id)));
+}
+
+/// Get the artifacts uploaded by the workflow run, along with the commit it ran on
+pub async fn get_workflow_run_artifacts(
+    api: &Arc<Api>,
+    repo: &str,
+    run_id: u64,
+    jobs: usize,
+) -> Result<(Vec<Artifact>, String), Error> {
+    let run = get_run(api, repo, run_id).await?;
+    let artifacts = get_run_artifacts(api, repo, run.id, jobs).await?.artifacts;
+
+    Ok((artifacts, run.head_sha))
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct Artifacts {
+    total_count: Option<u64>,
+    artifacts: Vec<Artifact>,
+}
+
+impl Schema for Artifacts {
+    fn missing_fields(&self) -> Vec<String> {
+        let mut missing = Vec::new();
+        if self.total_count.is_none() {
+            missing.push("total_count".to_string());
+        }
+        for (i, artifact) in self.artifacts.iter().enumerate() {
+            if artifact.created_at.is_none() {
+                missing.push(format!("artifacts[{}].created_at", i));
+            }
+            if artifact.workflow_run.id.is_none() {
+                missing.push(format!("artifacts[{}].workflow_run.id", i));
+            }
+        }
+        missing
+    }
+}
+
+impl Artifacts {
+    pub fn into_filtered_by_rev(self, rev: &str) -> Vec<Artifact> {
+        self.artifacts
+            .into_iter()
+            .filter(|artifact| artifact.workflow_run.head_sha == rev)
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct Artifact {
+    pub id: u64,
+    pub name: String,
+    pub archive_download_url: String,
+    /// RFC 3339 timestamp in UTC
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<String>,
+    /// Digest of the archive, like `sha256:...`. Not set for older artifacts
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub digest: Option<String>,
+    /// Size of the archive
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size_in_bytes: Option<u64>,
+    pub workflow_run: WorkflowRun,
+    /// Other fields from the API, kept for --metadata-only
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+/// Archive downloaded once for all artifacts with its digest
+type SharedArchive = Arc<OnceCell<Arc<Vec<u8>>>>;
+
+/// Archives shared between artifacts with the same digest, for --dedup-downloads
+#[derive(Debug, Default)]
+pub struct DedupDownloads {
+    /// The archive for each digest, and how many artifacts still need it
+    archives: Mutex<HashMap<String, (SharedArchive, usize)>>,
+}
+
+impl DedupDownloads {
+    /// Track the digests that more than one of the artifacts have
+    pub fn new(artifacts: &[&Artifact]) -> Self {
+        let mut counts = HashMap::<_, usize>::new();
+        for digest in artifacts
+            .iter()
+            .filter_map(|artifact| artifact.digest.as_ref())
+        {
+            *counts.entry(digest.clone()).or_default() += 1;
+        }
+        let archives = counts
+            .into_iter()
+            .filter(|(_, count)| *count > 1)
+            .map(|(digest, count)| (digest, (Default::default(), count)))
+            .collect();
+        Self {
+            archives: Mutex::new(archives),
+        }
+    }
+
+    /// Get the archive cell for the digest, if it's shared
+    ///
+    /// The last artifact to take it removes it, so the archive is freed after it's extracted
+    fn take(&self, digest: &str) -> Option<SharedArchive> {
+        let mut archives = self.archives.lock().unwrap();
+        let (cell, remaining) = archives.get_mut(digest)?;
+        *remaining -= 1;
+        let cell = Arc::clone(cell);
+        if *remaining == 0 {
+            archives.remove(digest);
+        }
+        Some(cell)
+    }
+}
+
+/// Result of downloading and extracting an artifact
+#[derive(Debug)]
+pub struct Downloaded {
+    pub timing: ArtifactTiming,
+    /// Size of the archive
+    pub size: u64,
+    /// SHA-256 of the archive
+    pub sha256: String,
+    pub files: Vec<ExtractedFile>,
+    /// SHA-256 of the normalized archive, for --normalized-zip
+    pub normalized_zip: Option<String>,
+}
+
+impl Artifact {
+    /// Label at the end of the name, like `beta` in `app@beta`
+    pub fn label(&self) -> Option<&str> {
+        self.split_label().map(|(_, label)| label)
+    }
+
+    /// Name without the label
+    pub fn display_name(&self) -> &str {
+        self.split_label().map_or(&self.name, |(name, _)| name)
+    }
+
+    /// How long ago the artifact was uploaded, from `created_at`
+    pub fn age(&self) -> Option<Duration> {
+        let created_at = parse_rfc3339(self.created_at.as_deref()?)?;
+        Some(
+            SystemTime::now()
+                .duration_since(created_at)
+                .unwrap_or_default(),
+        )
+    }
+
+    fn split_label(&self) -> Option<(&str, &str)> {
+        self.name
+            .rsplit_once('@')
+            .filter(|(name, label)| !name.is_empty() && !label.is_empty())
+    }
+
+    /// Check if the artifact was created while the job was running
+    ///
+    /// The API returns timestamps in the same format and time zone,
+    /// so they can be compared as strings
+    fn is_uploaded_by(&self, job: &Job) -> bool {
+        let (Some(created_at), Some(started_at)) = (&self.created_at, &job.started_at) else {
+            return false;
+        };
+        created_at >= started_at
+            && job
+                .completed_at
+                .as_ref()
+                .is_none_or(|completed_at| created_at <= completed_at)
+    }
+
+    /// Download and extract the artifact, retrying the download with `retry`
+    ///
+    /// `signed_url` is the download URL from [`Artifact::resolve_download_url`] if it was
+    /// requested before the download was queued. It's requested again if it would
+    /// expire before the download is done
+    ///
+    /// When `cancel` is cancelled, the download stops with [`Error::Cancelled`]
+    /// without writing anything. Extraction that already started is finished,
+    /// so the output never has a partially extracted artifact
+    pub async fn download(
+        &self,
+        api: &Api,
+        signed_url: Option<SignedUrl>,
+        out_dir: PathBuf,
+        options: Arc<ExtractOptions>,
+        retry: RetryPolicy,
+        cancel: &CancellationToken,
+    ) -> Result<Downloaded, Error> {
+        self.download_internal(api, signed_url, out_dir, options, retry, cancel)
+            .await
+            .change_context(Error::DownloadArtifact)
+            .attach_printable_lazy(|| format!("artifact: {}", self.name))
+            .attach_printable_lazy(|| format!("url: {}", self.archive_download_url))
+    }
+
+    /// Get the signed URL the API redirects to for downloading the artifact
+    pub async fn resolve_download_url(&self, api: &Api) -> Result<SignedUrl, Error> {
+        let response = api
+            .no_redirect_client()
+            .get(&self.archive_download_url)
+            .send()
+            .await
+            .change_context(Error::Request)?;
+        api.check_pin(&response)?;
+        warn_if_deprecated(&response);
+        if response.status() == 410 {
+            return Err(report!(Error::Expired));
+        }
+        if !response.status().is_redirection() {
+            // served directly, for example by a mirror from --download-rewrite
+            return Ok(SignedUrl::new(self.archive_download_url.clone()));
+        }
+        let location = response
+            .headers()
+            .get(LOCATION)
+            .and_then(|location| location.to_str().ok())
+            .and_then(|location| response.url().join(location).ok())
+            .ok_or_else(|| report!(Error::Request))
+            .attach_printable("redirect without a valid location")?;
+        Ok(SignedUrl::new(location.to_string()))
+    }
+
+    async fn download_internal(
+        &self,
+        api: &Api,
+        signed_url: Option<SignedUrl>,
+        out_dir: PathBuf,
+        options: Arc<ExtractOptions>,
+        retry: RetryPolicy,
+        cancel: &CancellationToken,
+    ) -> Result<Downloaded, Error> {
+        let start = Instant::now();
+        let shared = match (&options.dedup, &self.digest) {
+            (Some(dedup), Some(digest)) => dedup.take(digest),
+            _ => None,
+        };
+        let bytes = match shared {
+            Some(cell) => {
+                let mut fetched = false;
+                let bytes = cell
+                    .get_or_try_init(|| async {
+                        fetched = true;
+                        self.fetch_zip(api, signed_url, &options, retry, cancel)
+                            .await
+                            .map(Arc::new)
+                    })
+                    .await?;
+                if !fetched {
+                    progress!("reusing archive with the same digest for `{}`", self.name);
+                }
+                Arc::clone(bytes)
+            }
+            None => Arc::new(
+                self.fetch_zip(api, signed_url, &options, retry, cancel)
+                    .await?,
+            ),
+        };
+        let download = start.elapsed();
+        let size = bytes.len() as u64;
+        let sha256 = sha256_hex(&bytes);
+        self.verify_digest(&sha256)?;
+        if cancel.is_cancelled() {
+            return Err(report!(Error::Cancelled));
+        }
+
+        progress!("extracting `{}`", self.name);
+        let start = Instant::now();
+        let expected_files = options.expected_files_for(&self.name);
+        let created_at = self.created_at.clone().unwrap_or_default();
+        let name = self.name.clone();
+        let (files, normalized_zip) = tokio::task::spawn_blocking(inherit_blocking(move || {
+            if options.zip_index {
+                let files = save_with_index(&bytes, &out_dir, &name, &options, expected_files)?;
+                return Ok((files, None));
+            }
+            let files = extract(&bytes, &out_dir, &options, expected_files, &created_at)?;
+            let normalized_zip = options
+                .normalized_zip
+                .as_ref()
+                .map(|dir| save_normalized_zip(&bytes, dir, &name, options.sink()))
+                .transpose()?;
+            Ok::<_, Report<Error>>((files, normalized_zip))
+        }))
+        .await
+        .change_context(Error::Extract)??;
+        let extract = start.elapsed();
+        progress!("downloaded `{}`", self.name);
+
+        Ok(Downloaded {
+            timing: ArtifactTiming {
+                name: self.name.clone(),
+                download,
+                extract,
+            },
+            size,
+            sha256,
+            files,
+            normalized_zip,
+        })
+    }
+
+    /// Check the SHA-256 of the archive against the digest from the API, if it has one
+    fn verify_digest(&self, sha256: &str) -> Result<(), Error> {
+        let Some(digest) = &self.digest else {
+            return Ok(());
+        };
+        // other algorithms can't be checked
+        let Some(expected) = digest.strip_prefix("sha256:") else {
+            return Ok(());
+        };
+        if !expected.eq_ignore_ascii_case(sha256) {
+            return Err(report!(Error::Digest))
+                .attach_printable(format!("expected: {}", digest))
+                .attach_printable(format!("actual: sha256:{}", sha256));
+        }
+        Ok(())
+    }
+
+    /// Get the archive from the shared cache, or download it
+    async fn fetch_zip(
+        &self,
+        api: &Api,
+        signed_url: Option<SignedUrl>,
+        options: &ExtractOptions,
+        retry: RetryPolicy,
+        cancel: &CancellationToken,
+    ) -> Result<Vec<u8>, Error> {
+        let cached = match &options.shared_cache {
+            Some(cache) => cache.read_zip(self.id).await,
+            None => None,
+        };
+        match cached {
+            Some(bytes) => {
+                progress!("using cached archive for `{}`", self.name);
+                Ok(bytes)
+            }
+            None => {
+                // only the download is cancelled, so the cache never has a partial archive
+                let bytes = self.download_zip(api, signed_url, retry, cancel).await?;
+                if let Some(cache) = &options.shared_cache {
+                    cache.save_zip(self.id, &bytes).await?;
+                }
+                Ok(bytes)
+            }
+        }
+    }
+
+    /// Download the archive, retrying on network and server errors
+    async fn download_zip(
+        &self,
+        api: &Api,
+        signed_url: Option<SignedUrl>,
+        retry: RetryPolicy,
+        cancel: &CancellationToken,
+    ) -> Result<Vec<u8>, Error> {
+        // only the first attempt can use the URL from before the download was queued
+        let queued_url = Mutex::new(signed_url);
+        let bytes = retry
+            .run(
+                &api.redacted_url(&self.archive_download_url),
+                cancel,
+                || async {
+                    let queued_url = queued_url.lock().unwrap_or_else(|e| e.into_inner()).take();
+                    let signed_url = match queued_url {
+                        Some(signed_url) if !signed_url.expires_soon() => signed_url,
+                        Some(_) => {
+                            progress!("download URL for `{}` expires soon, refreshing", self.name);
+                            self.resolve_download_url(api).await?
+                        }
+                        None => self.resolve_download_url(api).await?,
+                    };
+                    let mut response = self.request_zip(api, &signed_url).await?;
+                    // the signed URL can still be rejected, for example if the clock is off.
+                    // requesting the API again gives a fresh one
+                    if response.status() == 403 && signed_url.url != self.archive_download_url {
+                        progress!("download URL for `{}` expired, refreshing", self.name);
+                        let signed_url = self.resolve_download_url(api).await?;
+                        response = self.request_zip(api, &signed_url).await?;
+                    }
+
+                    if response.status() == 410 {
+                        return Err(report!(Error::Expired));
+                    } else if response.status() == 404 {
+                        return Err(report!(Error::Deleted));
+                    }
+                    let response = error_for_status(response).await?;
+                    if response.status() != 200 {
+                        return Err(report!(Error::Request))
+                            .attach_printable(Status(response.status()));
+                    }
+
+                    response.bytes().await.change_context(Error::Request)
+                },
+            )
+            .await?;
+        Ok(bytes.into())
+    }
+
+    async fn request_zip(&self, api: &Api, signed_url: &SignedUrl) -> Result<Response, Error> {
+        // the token is only for the API, not the storage the URL is signed for
+        let response = if signed_url.url == self.archive_download_url {
+            api.send(api.get(&signed_url.url)).await?
+        } else {
+            api.storage_client()
+                .get(&signed_url.url)
+                .send()
+                .await
+                .change_context(Error::Request)?
+        };
+        warn_if_deprecated(&response);
+        Ok(response)
+    }
+}
+
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct WorkflowRun {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<u64>,
+    pub head_sha: String,
+    /// Attempt of the run that uploaded the artifact. The artifacts API doesn't have it,
+    /// so it's only set for --attempt
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub run_attempt: Option<u64>,
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    use crate::{
+        extract::OnConflict,
+        github::ApiOptions,
+        test_util::{mock_api, zip_file, InFlight, MockServer, Response, TempDir},
+    };
+
+    #[tokio::test]
+    async fn test_download_refreshes_expired_url() {
+        let redirects = AtomicUsize::new(0);
+        let server = MockServer::start(move |request| match request.path.as_str() {
+            "/download" => {
+                let n = redirects.fetch_add(1, Ordering::SeqCst) + 1;
+                Response::new(302).header("Location", &format!("/signed/{}", n))
+            }
+            // the first signed URL has expired
+            "/signed/1" => Response::new(403),
+            _ => Response::new(200).body(zip_file(&[("a.txt", b"a")])),
+        })
+        .await;
+        let artifact: Artifact = serde_json::from_value(serde_json::json!({
+            "id": 1,
+            "name": "app",
+            "archive_download_url": server.url("/download"),
+            "workflow_run": { "head_sha": "abc" },
+        }))
+        .unwrap();
+        let api = Api::new("token".to_string(), ApiOptions::default()).unwrap();
+        let options = Arc::new(ExtractOptions {
+            on_conflict: OnConflict::Fail,
+            ..Default::default()
+        });
+
+        let dir = TempDir::new();
+        artifact
+            .download(
+                &api,
+                None,
+                dir.join("app"),
+                options,
+                RetryPolicy::default(),
+                &CancellationToken::new(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read_to_string(dir.join("app/a.txt")).unwrap(), "a");
+        let paths = server
+            .requests()
+            .into_iter()
+            .map(|request| request.path)
+            .collect::<Vec<_>>();
+        assert_eq!(paths, ["/download", "/signed/1", "/download", "/signed/2"]);
+    }
+
+    #[test]
+    fn test_metadata_keeps_api_fields() {
+        let value = serde_json::json!({
+            "id": 1,
+            "name": "app",
+            "archive_download_url": "https://example.com",
+            "size_in_bytes": 1024,
+            "expired": false,
+            "workflow_run": { "head_sha": "abc", "head_branch": "main" },
+        });
+        let artifact: Artifact = serde_json::from_value(value.clone()).unwrap();
+        assert_eq!(artifact.size_in_bytes, Some(1024));
+        assert_eq!(artifact.extra["expired"], false);
+        assert_eq!(serde_json::to_value(&artifact).unwrap(), value);
+    }
+
+    #[tokio::test]
+    async fn test_filter_by_job() {
+        let server = MockServer::start(|request| match request.path.as_str() {
+            "/repos/foo/bar/actions/runs/1/jobs?per_page=100" => Response::json(
+                r#"{"jobs":[
+                    {"name":"build","started_at":"2024-01-01T00:00:00Z","completed_at":"2024-01-01T00:10:00Z"},
+                    {"name":"test","started_at":"2024-01-01T00:10:01Z","completed_at":"2024-01-01T00:20:00Z"}
+                ]}"#,
+            ),
+            "/repos/foo/bar/actions/runs/2/jobs?per_page=100" => Response::json(
+                r#"{"jobs":[
+                    {"name":"build","started_at":"2024-01-01T00:00:00Z","completed_at":"2024-01-01T00:10:00Z"}
+                ]}"#,
+            ),
+            _ => Response::new(404),
+        })
+        .await;
+        let artifact = |name: &str, run_id: u64, created_at: &str| -> Artifact {
+            serde_json::from_value(serde_json::json!({
+                "id": 1,
+                "name": name,
+                "archive_download_url": "",
+                "created_at": created_at,
+                "workflow_run": { "id": run_id, "head_sha": "abc" },
+            }))
+            .unwrap()
+        };
+        let artifacts = vec![
+            artifact("build-1", 1, "2024-01-01T00:05:00Z"),
+            artifact("build-2", 1, "2024-01-01T00:09:00Z"),
+            artifact("test", 1, "2024-01-01T00:15:00Z"),
+            artifact("other", 2, "2024-01-01T00:05:00Z"),
+        ];
+
+        let filtered = filter_by_job(
+            &mock_api(&server),
+            "foo/bar",
+            artifacts,
+            &["build".to_string()],
+        )
+        .await
+        .unwrap();
+        let names = filtered
+            .iter()
+            .map(|artifact| artifact.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["build-1", "build-2", "other"]);
+        // the jobs of each run are only fetched once
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2, "{:?}", requests);
+    }
+
+    #[test]
+    fn test_select_by_jobs() {
+        let artifact = |name: &str, run_id: u64, created_at: &str| -> Artifact {
+            serde_json::from_value(serde_json::json!({
+                "id": 1,
+                "name": name,
+                "archive_download_url": "",
+                "created_at": created_at,
+                "workflow_run": { "id": run_id, "head_sha": "abc" },
+            }))
+            .unwrap()
+        };
+        let job = |started_at: &str, completed_at: Option<&str>| Job {
+            name: "build".to_string(),
+            started_at: Some(started_at.to_string()),
+            completed_at: completed_at.map(str::to_string),
+            check_run_url: None,
+        };
+        let artifacts = vec![
+            artifact("during", 1, "2024-01-01T00:05:00Z"),
+            artifact("after", 1, "2024-01-01T00:20:00Z"),
+            artifact("other-run", 2, "2024-01-01T00:05:00Z"),
+            artifact("running", 3, "2024-01-01T01:00:00Z"),
+        ];
+        let jobs = HashMap::from([
+            (
+                1,
+                vec![job("2024-01-01T00:00:00Z", Some("2024-01-01T00:10:00Z"))],
+            ),
+            (2, vec![]),
+            (3, vec![job("2024-01-01T00:30:00Z", None)]),
+        ]);
+        let names = select_by_jobs(artifacts, &jobs)
+            .into_iter()
+            .map(|artifact| artifact.name)
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["during", "running"]);
+    }
+
+    #[test]
+    fn test_sort_artifacts() {
+        let mut artifacts = [(3, "docs"), (2, "app"), (1, "app")]
+            .iter()
+            .map(|(id, name)| {
+                serde_json::from_value::<Artifact>(serde_json::json!({
+                    "id": id,
+                    "name": name,
+                    "archive_download_url": "",
+                    "workflow_run": { "head_sha": "abc" },
+                }))
+                .unwrap()
+            })
+            .collect::<Vec<_>>();
+        sort_artifacts(&mut artifacts);
+        let ids = artifacts
+            .iter()
+            .map(|artifact| artifact.id)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, [1, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn test_download_refreshes_expiring_url() {
+        let server = MockServer::start(|request| {
+            if request.path == "/download" {
+                return Response::new(302).header("Location", "/fresh?se=2999-01-01T00%3A00%3A00Z");
+            }
+            // the token is not sent to the storage
+            assert_eq!(request.header("authorization"), None);
+            Response::new(200).body(zip_file(&[("a.txt", b"a")]))
+        })
+        .await;
+        let artifact: Artifact = serde_json::from_value(serde_json::json!({
+            "id": 1,
+            "name": "app",
+            "archive_download_url": server.url("/download"),
+            "workflow_run": { "head_sha": "abc" },
+        }))
+        .unwrap();
+        let api = Api::new("token".to_string(), ApiOptions::default()).unwrap();
+        let options = Arc::new(ExtractOptions {
+            on_conflict: OnConflict::Fail,
+            ..Default::default()
+        });
+
+        // the download was queued until the URL is about to expire
+        let expires_at = std::time::SystemTime::now() + std::time::Duration::from_secs(10);
+        let queued_url = SignedUrl {
+            url: server.url("/stale"),
+            expires_at: Some(expires_at),
+        };
+        let dir = TempDir::new();
+        artifact
+            .download(
+                &api,
+                Some(queued_url),
+                dir.join("app"),
+                options,
+                RetryPolicy::default(),
+                &CancellationToken::new(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read_to_string(dir.join("app/a.txt")).unwrap(), "a");
+        let paths = server
+            .requests()
+            .into_iter()
+            .map(|request| request.path)
+            .collect::<Vec<_>>();
+        assert_eq!(paths, ["/download", "/fresh?se=2999-01-01T00%3A00%3A00Z"]);
+    }
+
+    #[tokio::test]
+    async fn test_strict_schema() {
+        let server = MockServer::start(|_| {
+            Response::json(
+                r#"{"artifacts":[{"id":1,"name":"app","archive_download_url":"","workflow_run":{"head_sha":"abc"}}]}"#,
+            )
+        })
+        .await;
+        let url = server.url("/repos/foo/bar/actions/artifacts");
+
+        let api = Api::new("token".to_string(), ApiOptions::default()).unwrap();
+        let artifacts: Artifacts = api.get_json(&url).await.unwrap();
+        assert_eq!(artifacts.total_count, None);
+        assert_eq!(artifacts.artifacts[0].name, "app");
+
+        let options = ApiOptions {
+            strict_schema: true,
+            ..Default::default()
+        };
+        let api = Api::new("token".to_string(), options).unwrap();
+        let err = api.get_json::<Artifacts>(&url).await.unwrap_err();
+        let err = format!("{:?}", err);
+        assert!(
+            err.contains("missing fields: total_count, artifacts[0].created_at, artifacts[0].workflow_run.id"),
+            "{}",
+            err
+        );
+    }
+
+    #[tokio::test]
+    async fn test_download_deleted() {
+        let server = MockServer::start(|request| match request.path.as_str() {
+            "/deleted" => Response::new(404),
+            _ => Response::new(410),
+        })
+        .await;
+        let api = Api::new("token".to_string(), ApiOptions::default()).unwrap();
+        let dir = TempDir::new();
+        let download = |path: &str| {
+            let artifact: Artifact = serde_json::from_value(serde_json::json!({
+                "id": 1,
+                "name": "app",
+                "archive_download_url": server.url(path),
+                "workflow_run": { "head_sha": "abc" },
+            }))
+            .unwrap();
+            let out_dir = dir.join("app");
+            let api = &api;
+            async move {
+                artifact
+                    .download(
+                        api,
+                        None,
+                        out_dir,
+                        Default::default(),
+                        RetryPolicy::default(),
+                        &CancellationToken::new(),
+                    )
+                    .await
+                    .unwrap_err()
+            }
+        };
+
+        let err = download("/deleted").await;
+        assert!(is_deleted(&err));
+        // expired is reported separately
+        let err = download("/expired").await;
+        assert!(!is_deleted(&err));
+    }
+
+    #[tokio::test]
+    async fn test_dedup_downloads() {
+        let zip = zip_file(&[("a.txt", b"a")]);
+        let digest = format!("sha256:{}", sha256_hex(&zip));
+        let server = MockServer::start(move |_| Response::new(200).body(zip.clone())).await;
+        let artifact = |id: u64, name: &str| -> Artifact {
+            serde_json::from_value(serde_json::json!({
+                "id": id,
+                "name": name,
+                "archive_download_url": server.url(&format!("/download/{}", id)),
+                "digest": digest,
+                "workflow_run": { "head_sha": "abc" },
+            }))
+            .unwrap()
+        };
+        let (linux, windows) = (artifact(1, "app-linux"), artifact(2, "app-windows"));
+        let api = Api::new("token".to_string(), ApiOptions::default()).unwrap();
+        let options = Arc::new(ExtractOptions {
+            dedup: Some(DedupDownloads::new(&[&linux, &windows])),
+            ..Default::default()
+        });
+
+        let dir = TempDir::new();
+        let cancel = CancellationToken::new();
+        let (a, b) = tokio::join!(
+            linux.download(
+                &api,
+                None,
+                dir.join("linux"),
+                Arc::clone(&options),
+                RetryPolicy::default(),
+                &cancel
+            ),
+            windows.download(
+                &api,
+                None,
+                dir.join("windows"),
+                Arc::clone(&options),
+                RetryPolicy::default(),
+                &cancel
+            ),
+        );
+        a.unwrap();
+        b.unwrap();
+        assert!(dir.join("linux/a.txt").exists());
+        assert!(dir.join("windows/a.txt").exists());
+        let paths = server
+            .requests()
+            .into_iter()
+            .map(|request| request.path)
+            .collect::<HashSet<_>>();
+        assert_eq!(paths.len(), 1);
+        // freed after the last artifact
+        assert!(options.dedup.as_ref().unwrap().take(&digest).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_filter_by_attempt() {
+        let server = MockServer::start(|request| {
+            let run = |id: u64, attempt: u64, started_at: &str| {
+                Response::json(format!(
+                    r#"{{"id":{},"head_sha":"abc","run_attempt":{},"run_started_at":"{}"}}"#,
+                    id, attempt, started_at
+                ))
+            };
+            match request.path.as_str() {
+                "/repos/foo/bar/actions/runs/1" => run(1, 3, "2024-01-01T02:00:00Z"),
+                "/repos/foo/bar/actions/runs/1/attempts/2" => run(1, 2, "2024-01-01T01:00:00Z"),
+                "/repos/foo/bar/actions/runs/1/attempts/3" => run(1, 3, "2024-01-01T02:00:00Z"),
+                // not rerun
+                "/repos/foo/bar/actions/runs/2" => run(2, 1, "2024-01-01T00:00:00Z"),
+                _ => Response::new(404),
+            }
+        })
+        .await;
+        let artifact = |name: &str, run_id: u64, created_at: &str| -> Artifact {
+            serde_json::from_value(serde_json::json!({
+                "id": 1,
+                "name": name,
+                "archive_download_url": "",
+                "created_at": created_at,
+                "workflow_run": { "id": run_id, "head_sha": "abc" },
+            }))
+            .unwrap()
+        };
+        let artifacts = vec![
+            artifact("first", 1, "2024-01-01T00:30:00Z"),
+            artifact("second", 1, "2024-01-01T01:30:00Z"),
+            artifact("third", 1, "2024-01-01T02:30:00Z"),
+            artifact("other", 2, "2024-01-01T00:30:00Z"),
+        ];
+
+        let filtered = filter_by_attempt(&mock_api(&server), "foo/bar", artifacts, 2)
+            .await
+            .unwrap();
+        let selected = filtered
+            .iter()
+            .map(|artifact| (artifact.name.as_str(), artifact.workflow_run.run_attempt))
+            .collect::<Vec<_>>();
+        assert_eq!(selected, [("second", Some(2))]);
+        let mut paths = server
+            .requests()
+            .into_iter()
+            .map(|request| request.path)
+            .collect::<Vec<_>>();
+        paths.sort();
+        assert_eq!(
+            paths,
+            [
+                "/repos/foo/bar/actions/runs/1",
+                "/repos/foo/bar/actions/runs/1/attempts/2",
+                "/repos/foo/bar/actions/runs/1/attempts/3",
+                "/repos/foo/bar/actions/runs/2",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_select_by_attempt() {
+        let artifact = |name: &str, run_id: u64, created_at: &str| -> Artifact {
+            serde_json::from_value(serde_json::json!({
+                "id": 1,
+                "name": name,
+                "archive_download_url": "",
+                "created_at": created_at,
+                "workflow_run": { "id": run_id, "head_sha": "abc" },
+            }))
+            .unwrap()
+        };
+        let window =
+            |attempt: u64, started_at: &str, next_started_at: Option<&str>| AttemptWindow {
+                attempt,
+                started_at: started_at.to_string(),
+                next_started_at: next_started_at.map(str::to_string),
+            };
+        let artifacts = vec![
+            artifact("before", 1, "2024-01-01T00:00:00Z"),
+            artifact("first", 1, "2024-01-01T00:05:00Z"),
+            artifact("rerun", 1, "2024-01-01T01:05:00Z"),
+            artifact("latest", 2, "2024-01-01T03:00:00Z"),
+            artifact("no-attempt", 3, "2024-01-01T00:05:00Z"),
+        ];
+        let windows = HashMap::from([
+            (
+                1,
+                window(1, "2024-01-01T00:01:00Z", Some("2024-01-01T01:00:00Z")),
+            ),
+            (2, window(1, "2024-01-01T02:00:00Z", None)),
+        ]);
+        let selected = select_by_attempt(artifacts, &windows);
+        let names = selected
+            .iter()
+            .map(|artifact| artifact.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["first", "latest"]);
+        assert!(selected
+            .iter()
+            .all(|artifact| artifact.workflow_run.run_attempt == Some(1)));
+    }
+
+    #[tokio::test]
+    async fn test_verify_digest() {
+        let zip = zip_file(&[("a.txt", b"a")]);
+        let sha256 = sha256_hex(&zip);
+        let server = MockServer::start(move |_| Response::new(200).body(zip.clone())).await;
+        let artifact = |name: &str, digest: Option<String>| -> Artifact {
+            serde_json::from_value(serde_json::json!({
+                "id": 1,
+                "name": name,
+                "archive_download_url": server.url("/download"),
+                "digest": digest,
+                "workflow_run": { "head_sha": "abc" },
+            }))
+            .unwrap()
+        };
+        let api = Api::new("token".to_string(), ApiOptions::default()).unwrap();
+        let options = Arc::new(ExtractOptions::default());
+        let dir = TempDir::new();
+        let download = |artifact: Artifact| {
+            let (api, options, out_dir) = (&api, Arc::clone(&options), dir.join(&artifact.name));
+            async move {
+                artifact
+                    .download(
+                        api,
+                        None,
+                        out_dir,
+                        options,
+                        RetryPolicy::default(),
+                        &CancellationToken::new(),
+                    )
+                    .await
+            }
+        };
+
+        let matching = format!("sha256:{}", sha256.to_uppercase());
+        download(artifact("matching", Some(matching)))
+            .await
+            .unwrap();
+        assert!(dir.join("matching/a.txt").exists());
+        // nothing to check against
+        download(artifact("none", None)).await.unwrap();
+        download(artifact("other", Some("sha512:abc".to_string())))
+            .await
+            .unwrap();
+
+        let wrong = format!("sha256:{}", "0".repeat(64));
+        let err = download(artifact("wrong", Some(wrong))).await.unwrap_err();
+        let err = format!("{:?}", err);
+        assert!(err.contains("failed to verify artifact digest"), "{}", err);
+        assert!(
+            err.contains(&format!("actual: sha256:{}", sha256)),
+            "{}",
+            err
+        );
+        assert!(!dir.join("wrong/a.txt").exists());
+    }
+
+    #[test]
+    fn test_label() {
+        let artifact = |name: &str| -> Artifact {
+            serde_json::from_value(serde_json::json!({
+                "id": 1,
+                "name": name,
+                "archive_download_url": "",
+                "workflow_run": { "head_sha": "abc" },
+            }))
+            .unwrap()
+        };
+        let labelled = artifact("app@linux");
+        assert_eq!(labelled.label(), Some("linux"));
+        assert_eq!(labelled.display_name(), "app");
+        // only the last `@` separates the label
+        let labelled = artifact("user@host@beta");
+        assert_eq!(labelled.label(), Some("beta"));
+        assert_eq!(labelled.display_name(), "user@host");
+
+        for name in ["app", "@linux", "app@"] {
+            let artifact = artifact(name);
+            assert_eq!(artifact.label(), None);
+            assert_eq!(artifact.display_name(), name);
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_get_artifacts_pages() {
+        let in_flight = Arc::new(InFlight::default());
+        let server = {
+            let in_flight = Arc::clone(&in_flight);
+            MockServer::start(move |request| {
+                let Some(page) = request
+                    .path
+                    .strip_prefix("/repos/foo/bar/actions/artifacts?per_page=100&page=")
+                    .and_then(|page| page.parse::<u64>().ok())
+                else {
+                    return Response::new(404);
+                };
+                let listing = || {
+                    // 250 artifacts
+                    let ids = (page - 1) * 100..(page * 100).min(250);
+                    let artifacts = ids
+                        .map(|id| {
+                            serde_json::json!({
+                                "id": id,
+                                "name": format!("app-{}", id),
+                                "archive_download_url": "",
+                                "workflow_run": { "head_sha": "abc" },
+                            })
+                        })
+                        .collect::<Vec<_>>();
+                    let listing = serde_json::json!({
+                        "total_count": 250,
+                        "artifacts": artifacts,
+                    });
+                    Response::json(listing.to_string())
+                };
+                if page == 1 {
+                    listing()
+                } else {
+                    in_flight.answer(listing)
+                }
+            })
+            .await
+        };
+        let api = mock_api(&server);
+
+        let artifacts = get_artifacts(&api, "foo/bar", 4).await.unwrap();
+        let ids = artifacts
+            .artifacts
+            .iter()
+            .map(|artifact| artifact.id)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, (0..250).collect::<Vec<_>>());
+        // pages 2 and 3 are fetched at the same time
+        assert_eq!(in_flight.max(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_get_artifacts_since() {
+        let server = MockServer::start(|request| {
+            // newest first, and full pages so only the old artifacts stop the listing
+            let ids = match request.path.as_str() {
+                "/repos/foo/bar/actions/artifacts?per_page=100&page=1" => 0..100,
+                "/repos/foo/bar/actions/artifacts?per_page=100&page=2" => 100..200,
+                _ => return Response::new(404),
+            };
+            let artifacts = ids
+                .map(|id| {
+                    // "in the future" is never too old
+                    let created_at = if id <= 100 {
+                        "2999-01-01T00:00:00Z"
+                    } else {
+                        "2024-01-01T00:00:00Z"
+                    };
+                    serde_json::json!({
+                        "id": id,
+                        "name": format!("app-{}", id),
+                        "archive_download_url": "",
+                        "created_at": created_at,
+                        "workflow_run": { "head_sha": "abc" },
+                    })
+                })
+                .collect::<Vec<_>>();
+            let listing = serde_json::json!({ "total_count": 1000, "artifacts": artifacts });
+            Response::json(listing.to_string())
+        })
+        .await;
+
+        let artifacts =
+            get_artifacts_since(&mock_api(&server), "foo/bar", Duration::from_secs(86400))
+                .await
+                .unwrap();
+        let ids = artifacts
+            .iter()
+            .map(|artifact| artifact.id)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, (0..=100).collect::<Vec<_>>());
+        // the third page isn't requested
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_check_suite_artifacts() {
+        let server = MockServer::start(|request| {
+            let listing = |ids: &[u64]| {
+                let artifacts = ids
+                    .iter()
+                    .map(|id| {
+                        serde_json::json!({
+                            "id": id,
+                            "name": format!("app-{}", id),
+                            "archive_download_url": "",
+                            "workflow_run": { "head_sha": "abc" },
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                let listing = serde_json::json!({
+                    "total_count": ids.len(),
+                    "artifacts": artifacts,
+                });
+                Response::json(listing.to_string())
+            };
+            match request.path.as_str() {
+                "/repos/foo/bar/actions/runs?check_suite_id=7&per_page=100&page=1" => {
+                    Response::json(
+                        r#"{"total_count":2,"workflow_runs":[
+                            {"id":1,"head_sha":"abc"},
+                            {"id":2,"head_sha":"abc"}
+                        ]}"#,
+                    )
+                }
+                "/repos/foo/bar/actions/runs?check_suite_id=8&per_page=100&page=1" => {
+                    Response::json(r#"{"total_count":0,"workflow_runs":[]}"#)
+                }
+                "/repos/foo/bar/actions/runs/1/artifacts?per_page=100&page=1" => listing(&[10]),
+                "/repos/foo/bar/actions/runs/2/artifacts?per_page=100&page=1" => listing(&[20, 21]),
+                _ => Response::new(404),
+            }
+        })
+        .await;
+        let api = mock_api(&server);
+
+        let (artifacts, rev) = get_check_suite_artifacts(&api, "foo/bar", 7, 1)
+            .await
+            .unwrap();
+        assert_eq!(rev, "abc");
+        let ids = artifacts
+            .iter()
+            .map(|artifact| artifact.id)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, [10, 20, 21]);
+
+        // a check suite without workflow runs, like one from another app
+        let err = get_check_suite_artifacts(&api, "foo/bar", 8, 1)
+            .await
+            .unwrap_err();
+        assert!(
+            format!("{:?}", err).contains("no workflow runs found for check suite 8"),
+            "{:?}",
+            err
+        );
+    }
+
+    #[tokio::test]
+    async fn test_download_retry_and_cancel() {
+        let requests = AtomicUsize::new(0);
+        let server = MockServer::start(move |_| {
+            // the first request fails, the ones after succeed
+            if requests.fetch_add(1, Ordering::SeqCst) == 0 {
+                Response::new(502)
+            } else {
+                Response::new(200).body(zip_file(&[("a.txt", b"a")]))
+            }
+        })
+        .await;
+        let artifact: Artifact = serde_json::from_value(serde_json::json!({
+            "id": 1,
+            "name": "app",
+            "archive_download_url": server.url("/download"),
+            "workflow_run": { "head_sha": "abc" },
+        }))
+        .unwrap();
+        let api = Api::new("token".to_string(), ApiOptions::default()).unwrap();
+        let dir = TempDir::new();
+        let retry = RetryPolicy {
+            retries: 1,
+            ..Default::default()
+        };
+
+        let cancel = CancellationToken::new();
+        artifact
+            .download(
+                &api,
+                None,
+                dir.join("app"),
+                Default::default(),
+                retry.clone(),
+                &cancel,
+            )
+            .await
+            .unwrap();
+        assert!(dir.join("app/a.txt").exists());
+        assert_eq!(server.requests().len(), 2);
+
+        cancel.cancel();
+        let err = artifact
+            .download(
+                &api,
+                None,
+                dir.join("cancelled"),
+                Default::default(),
+                retry,
+                &cancel,
+            )
+            .await
+            .unwrap_err();
+        assert!(format!("{:?}", err).contains("cancelled"), "{:?}", err);
+        assert!(!dir.join("cancelled").exists());
+    }
+
+    #[test]
+    fn test_age() {
+        let artifact = |created_at: Option<&str>| -> Artifact {
+            serde_json::from_value(serde_json::json!({
+                "id": 1,
+                "name": "app",
+                "archive_download_url": "",
+                "created_at": created_at,
+                "workflow_run": { "head_sha": "abc" },
+            }))
+            .unwrap()
+        };
+        let age = artifact(Some("2024-01-01T00:00:00Z")).age().unwrap();
+        assert!(age > Duration::from_secs(90 * 86400));
+        // uploaded "in the future" with clock skew
+        let age = artifact(Some("2999-01-01T00:00:00Z")).age().unwrap();
+        assert_eq!(age, Duration::ZERO);
+        assert!(artifact(None).age().is_none());
+        assert!(artifact(Some("yesterday")).age().is_none());
+    }
+}
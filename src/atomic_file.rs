@@ -0,0 +1,72 @@
+//! Replacing files in one step, for the manifest, state, inventory and caches
+//!
+//! The content is written to a temporary file next to the file, then renamed over it.
+//! Readers see either the old or the new file, even if magnesis is interrupted or
+//! another pull writes the same file at the same time.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// Replace the file with the content
+pub async fn write(path: &Path, content: Vec<u8>) -> io::Result<()> {
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || write_blocking(&path, &content, None))
+        .await
+        .map_err(io::Error::other)?
+}
+
+/// Replace the file with the content, with the Unix permissions `mode` if set
+pub fn write_blocking(path: &Path, content: &[u8], mode: Option<u32>) -> io::Result<()> {
+    let temp_path = temp_path(path);
+    let write = || {
+        fs::write(&temp_path, content)?;
+        #[cfg(unix)]
+        if let Some(mode) = mode {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&temp_path, fs::Permissions::from_mode(mode))?;
+        }
+        #[cfg(not(unix))]
+        let _ = mode;
+        fs::rename(&temp_path, path)
+    };
+    let result = write();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+/// Unique path next to `path` to write to before renaming
+fn temp_path(path: &Path) -> PathBuf {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".{}.{}.tmp", std::process::id(), count));
+    path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_util::{temp_files, TempDir};
+
+    #[tokio::test]
+    async fn test_write() {
+        let dir = TempDir::new();
+        let path = dir.join("file.json");
+        write(&path, b"old".to_vec()).await.unwrap();
+        write(&path, b"new".to_vec()).await.unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        assert!(temp_files(&dir.join("")).is_empty());
+
+        // the temporary file is removed if it can't be renamed
+        fs::create_dir(dir.join("dir")).unwrap();
+        fs::write(dir.join("dir/file"), "").unwrap();
+        assert!(write(&dir.join("dir"), b"new".to_vec()).await.is_err());
+        assert!(temp_files(&dir.join("")).is_empty());
+    }
+}
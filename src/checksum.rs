@@ -0,0 +1,103 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use error_stack::{Result, ResultExt};
+use sha2::{Digest, Sha256};
+use tokio::fs;
+
+use crate::Error;
+
+/// Name of the file recording the hashes of extracted files, in `sha256sum` format
+pub const SHA256SUMS: &str = "SHA256SUMS";
+
+/// Get the lowercase hex SHA-256 of the bytes
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Read `SHA256SUMS` in the output directory, if it exists,
+/// as a map from the path of each file (joined with the output directory) to its hash
+pub async fn read_sums(output: &Path) -> Result<HashMap<PathBuf, String>, Error> {
+    let path = output.join(SHA256SUMS);
+    if !path.exists() {
+        return Ok(HashMap::new());
+    }
+    let content = fs::read_to_string(&path)
+        .await
+        .change_context(Error::Checksum)
+        .attach_printable_lazy(|| format!("path: {}", path.display()))?;
+    let sums = content
+        .lines()
+        .filter_map(|line| {
+            let (hash, file) = line.split_once(' ')?;
+            // `sha256sum` marks binary mode with `*`
+            let file = file.trim_start_matches([' ', '*']);
+            Some((output.join(file), hash.to_string()))
+        })
+        .collect();
+    Ok(sums)
+}
+
+/// Write `SHA256SUMS` in the output directory for the files, sorted by path
+pub async fn write_sums(output: &Path, files: &[(PathBuf, String)]) -> Result<(), Error> {
+    let mut entries = files
+        .iter()
+        .filter_map(|(path, hash)| {
+            let relative = path.strip_prefix(output).ok()?;
+            let relative = relative
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            Some((relative, hash))
+        })
+        .collect::<Vec<_>>();
+    entries.sort();
+    let content = entries
+        .into_iter()
+        .map(|(relative, hash)| format!("{}  {}\n", hash, relative))
+        .collect::<String>();
+    let path = output.join(SHA256SUMS);
+    fs::write(&path, content)
+        .await
+        .change_context(Error::Checksum)
+        .attach_printable_lazy(|| format!("path: {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_util::TempDir;
+
+    #[tokio::test]
+    async fn test_write_read_sums() {
+        let dir = TempDir::new();
+        let output = dir.join("");
+        assert!(read_sums(&output).await.unwrap().is_empty());
+
+        let files = vec![
+            (dir.join("b/c.txt"), sha256_hex(b"c")),
+            (dir.join("a.txt"), sha256_hex(b"a")),
+        ];
+        write_sums(&output, &files).await.unwrap();
+        let content = std::fs::read_to_string(dir.join(SHA256SUMS)).unwrap();
+        assert_eq!(
+            content,
+            format!(
+                "{}  a.txt\n{}  b/c.txt\n",
+                sha256_hex(b"a"),
+                sha256_hex(b"c")
+            )
+        );
+        assert_eq!(
+            read_sums(&output).await.unwrap(),
+            files.into_iter().collect::<HashMap<_, _>>()
+        );
+    }
+}
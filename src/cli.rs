@@ -0,0 +1,559 @@
+//! Options on the command line
+
+use std::{path::PathBuf, time::Duration};
+
+use crate::{
+    artifact::OnDeleted,
+    extract::{parse_mode, ExpectFiles, LineEnding, OnCaseCollision, OnConflict, SymlinkPolicy},
+    github::parse_fingerprint,
+    memory::parse_size,
+    output::Format,
+    owner::Owner,
+    retention::parse_duration,
+    url::{parse_repo, GitHubUrl, UrlRewrite},
+};
+
+/// Pull artifacts from GitHub Actions
+#[derive(Debug, Clone, clap::Parser)]
+#[clap(version)]
+pub struct Cli {
+    /// Path to the output directory.
+    #[clap(short, long, default_value = "dist")]
+    pub output: String,
+
+    /// Repo to use, default to deriving from the origin remote
+    ///
+    /// Either `OWNER/REPO` or a GitHub URL
+    #[clap(long, value_parser = parse_repo)]
+    pub repo: Option<String>,
+
+    /// Warn if --repo is different from the repo of the origin remote, so pulling
+    /// from another repo is intentional. With --strict, this is an error
+    #[clap(long, requires = "repo")]
+    pub verify_repo: bool,
+
+    /// Pull from the repo, workflow run or pull request in this GitHub URL
+    ///
+    /// For example, `https://github.com/OWNER/REPO/actions/runs/123` pulls the
+    /// artifacts of run 123
+    #[clap(long, value_name = "URL", conflicts_with_all = ["repo", "pr", "check_suite", "remote_rev", "verify_reachable"])]
+    pub from_url: Option<GitHubUrl>,
+
+    /// Revision (commit/branch) to use
+    #[clap(long, default_value = "HEAD")]
+    pub rev: String,
+
+    /// Pull the artifacts of both revisions and print which files were added, removed
+    /// or changed from the first to the second
+    ///
+    /// Each revision is pulled into a subdirectory of the output named after it
+    #[clap(long, num_args = 2, value_names = ["REV_A", "REV_B"], conflicts_with_all = ["rev", "pr", "check_suite", "from_url", "state_dir", "newer_than_file", "metadata_only", "list", "list_workflows", "plan"])]
+    pub compare: Option<Vec<String>>,
+
+    /// Pull the artifacts of the heads of these branches, each into a subdirectory of the output
+    /// named after the branch
+    ///
+    /// Use with --remote-rev to resolve the heads on GitHub instead of the local git repo
+    #[clap(long, value_name = "BRANCH", value_delimiter = ',', conflicts_with_all = ["rev", "compare", "pr", "check_suite", "from_url", "state_dir", "newer_than_file", "metadata_only", "list", "list_workflows", "plan"])]
+    pub branches: Option<Vec<String>>,
+
+    /// Resolve --rev with the GitHub API instead of the local git repo
+    ///
+    /// This accepts short SHAs, branches and tags of the remote repo
+    /// and works without a local clone
+    #[clap(long)]
+    pub remote_rev: bool,
+
+    /// Check that the resolved revision is a commit that exists in the local repo
+    #[clap(long, conflicts_with_all = ["remote_rev", "check_suite"])]
+    pub verify_reachable: bool,
+
+    /// Pull artifacts for the head commit of this pull request
+    ///
+    /// If the pull request is from a fork and no artifacts are found in
+    /// the repo, the runs in the fork are checked
+    #[clap(long, value_name = "NUMBER", conflicts_with_all = ["remote_rev", "check_suite", "verify_reachable"])]
+    pub pr: Option<u64>,
+
+    /// Seconds to wait for a git command before giving up
+    #[clap(long, value_name = "SECONDS", default_value_t = 30)]
+    pub git_timeout: u64,
+
+    /// Always run git to derive the repo and HEAD, instead of using the cached result
+    #[clap(long)]
+    pub no_cache: bool,
+
+    /// Pull artifacts from the workflow runs of this check suite, instead of by revision
+    #[clap(long, value_name = "ID", conflicts_with = "remote_rev")]
+    pub check_suite: Option<u64>,
+
+    /// Print how long each phase took at the end
+    #[clap(long)]
+    pub trace_timings: bool,
+
+    /// Print the remaining API rate limit at the end, from the headers of the last
+    /// responses (or the rate limit API if there were none)
+    #[clap(long)]
+    pub print_rate_limit: bool,
+
+    /// Print the downloaded and extracted size of each artifact at the end
+    #[clap(long)]
+    pub output_size_report: bool,
+
+    /// Write a CycloneDX JSON inventory of the downloaded artifacts to the path
+    ///
+    /// Each artifact is a component with the SHA-256 of its archive, the repo,
+    /// the commit and the download URL
+    #[clap(long, value_name = "PATH", conflicts_with_all = ["compare", "branches", "metadata_only", "list", "list_workflows", "plan"])]
+    pub sbom: Option<PathBuf>,
+
+    /// TOML file with rules mapping artifact names to destinations in the output
+    #[clap(long)]
+    pub layout_file: Option<String>,
+
+    /// Merge artifacts with the same name prefix before the last separator into one directory
+    ///
+    /// For example, with `--merge-prefix -`, `app-linux` and `app-win` are both
+    /// extracted to `app`, and `my-app-linux` to `my-app`
+    #[clap(long, value_name = "SEPARATOR")]
+    pub merge_prefix: Option<String>,
+
+    /// What to do when an extracted file already exists
+    #[clap(long, value_enum, default_value_t)]
+    pub on_conflict: OnConflict,
+
+    /// What to do when extracted files differ only in case, which collide on
+    /// case-insensitive file systems like on Windows and macOS
+    #[clap(long, value_enum, default_value_t)]
+    pub on_case_collision: OnCaseCollision,
+
+    /// What to do when a symlink in an artifact can't be created, for example
+    /// on Windows without the privilege to create symlinks
+    #[clap(long, value_enum, default_value_t)]
+    pub symlink_policy: SymlinkPolicy,
+
+    /// Save each artifact as `NAME.zip` with `NAME.index.txt` listing the files in it,
+    /// instead of extracting it
+    #[clap(long, conflicts_with_all = ["normalize_eol", "shared_cache"])]
+    pub zip_index: bool,
+
+    /// Also save each artifact as `NAME.zip` in this directory, repacked with the files sorted
+    /// and fixed timestamps and permissions, so the same files always give the same archive
+    ///
+    /// The directory is `OUTPUT.normalized` next to the output if not given.
+    /// Existing archives are not overwritten.
+    /// With --manifest, the SHA-256 of each repacked archive is recorded in it
+    #[clap(long, value_name = "DIR", num_args = 0..=1, conflicts_with = "zip_index")]
+    pub normalized_zip: Option<Option<PathBuf>>,
+
+    /// Only extract the files under this directory in each artifact, with the directory
+    /// removed from their paths. For example, `--inner-path dist` extracts `dist/app.js`
+    /// as `app.js` and skips everything outside `dist`
+    #[clap(long, value_name = "DIR", conflicts_with = "zip_index")]
+    pub inner_path: Option<PathBuf>,
+
+    /// Stop extracting an artifact with more entries than this, which could be a zip bomb
+    #[clap(long, value_name = "N", default_value_t = 1_000_000)]
+    pub max_entries: usize,
+
+    /// Stop extracting an artifact that expands to more than this size, like `64G`,
+    /// which could be a zip bomb
+    #[clap(long, value_name = "SIZE", value_parser = parse_size, default_value = "64G")]
+    pub max_uncompressed: u64,
+
+    /// Convert line endings of text files (selected with --text-glob) when extracting
+    #[clap(long, value_enum, requires = "text_glob")]
+    pub normalize_eol: Option<LineEnding>,
+
+    /// Glob matched against paths inside the artifact to select text files for --normalize-eol
+    #[clap(long)]
+    pub text_glob: Vec<String>,
+
+    /// Permissions (octal, like `644`) for extracted files on Unix, instead of the ones
+    /// stored in the artifact
+    #[clap(long, value_name = "MODE", value_parser = parse_mode)]
+    pub file_mode: Option<u32>,
+
+    /// Permissions (octal, like `755`) for extracted directories on Unix
+    #[clap(long, value_name = "MODE", value_parser = parse_mode)]
+    pub dir_mode: Option<u32>,
+
+    /// Change the owner of the output and everything in it to `UID:GID` after the pull,
+    /// on Unix. For example, so the user of a container can use the files when
+    /// pulling into a mounted volume as root
+    ///
+    /// Can't be used with --shared-cache, since the hard links share the owner
+    /// with the files in the cache
+    #[clap(long, value_name = "UID:GID", conflicts_with = "shared_cache")]
+    pub owner: Option<Owner>,
+
+    /// Keep downloaded archives and extracted files in this directory, shared between pulls,
+    /// and hard link the files into the output instead of writing copies
+    ///
+    /// Artifacts already in the cache are not downloaded again
+    #[clap(long, value_name = "DIR")]
+    pub shared_cache: Option<PathBuf>,
+
+    /// Download artifacts with the same digest (identical content uploaded more than once)
+    /// only once, and extract the archive for each of them
+    #[clap(long)]
+    pub dedup_downloads: bool,
+
+    /// Only download artifacts whose digest isn't in this `metadata.json` (from --metadata-only)
+    /// or `manifest.json`, and add the downloaded ones to it
+    ///
+    /// The file is created if it doesn't exist. Artifacts without a digest are always downloaded
+    ///
+    /// Can't be used with --compare or --branches, since the revisions are pulled at the same time
+    #[clap(long, value_name = "FILE", conflicts_with_all = ["metadata_only", "compare", "branches"])]
+    pub against: Option<PathBuf>,
+
+    /// Fail if any artifact doesn't have a digest (older artifacts don't)
+    ///
+    /// The digest of every artifact that has one is checked after downloading
+    #[clap(long)]
+    pub require_digest: bool,
+
+    /// What to do when an artifact is not found when downloading, because it or
+    /// its workflow run was deleted after listing
+    #[clap(long, value_enum, default_value_t)]
+    pub on_deleted: OnDeleted,
+
+    /// Fail if an artifact doesn't contain exactly this many files
+    ///
+    /// Use `N` for all artifacts, or `NAME=N` for a specific artifact. Can be repeated.
+    /// With --inner-path, only the files in it are counted
+    #[clap(long, value_name = "[NAME=]N")]
+    pub expect_files: Vec<ExpectFiles>,
+
+    /// Succeed without downloading anything if no workflow ran on the revision
+    #[clap(long)]
+    pub tolerate_missing: bool,
+
+    /// If no artifacts are found for the revision, keep listing again for up to this
+    /// many seconds before giving up
+    #[clap(long, value_name = "SECONDS", default_value_t = 0)]
+    pub retry_empty: u64,
+
+    /// Only pull artifacts whose name matches this glob. Can be repeated
+    #[clap(long, value_name = "GLOB")]
+    pub name: Vec<String>,
+
+    /// Only pull artifacts with this label, from a name like `NAME@LABEL`. Can be repeated
+    #[clap(long, value_name = "LABEL")]
+    pub label: Vec<String>,
+
+    /// Only pull artifacts uploaded by a job whose name matches this glob. Can be repeated
+    ///
+    /// An artifact is matched to a job of its workflow run by when it was created,
+    /// since the API doesn't link artifacts to jobs
+    #[clap(long, value_name = "GLOB")]
+    pub job: Vec<String>,
+
+    /// Only pull artifacts uploaded by this attempt of their workflow run, starting at 1
+    ///
+    /// By default, the artifacts of the latest attempt are pulled, which includes the
+    /// artifacts of jobs from earlier attempts that weren't rerun. Like --job, an
+    /// artifact is matched to an attempt by when it was created
+    #[clap(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    pub attempt: Option<u64>,
+
+    /// Only pull artifacts containing a file whose path matches this glob. Can be repeated
+    ///
+    /// Only the list of files is downloaded to check this, not the whole artifact
+    #[clap(long, value_name = "GLOB")]
+    pub contains: Vec<String>,
+
+    /// Only pull artifacts from workflow runs whose title matches this glob
+    ///
+    /// The title of a manually dispatched run can be set from its inputs with
+    /// `run-name` in the workflow
+    #[clap(long, value_name = "GLOB")]
+    pub title_match: Option<String>,
+
+    /// Only pull artifacts whose workflow run's head commit changed a file matching
+    /// this glob. Can be repeated
+    ///
+    /// This needs an API request for each commit the artifacts are from
+    #[clap(long, value_name = "GLOB")]
+    pub changed_path: Vec<String>,
+
+    /// Only pull artifacts this program exits successfully for. It's run for each artifact,
+    /// with the artifact's JSON from the API on stdin
+    ///
+    /// The output of the program is discarded, and errors are shown on stderr
+    #[clap(long, value_name = "PATH")]
+    pub select_script: Option<PathBuf>,
+
+    /// Only pull artifacts created after the timestamp in this file, and update it to
+    /// the newest artifact's creation time after a successful pull
+    ///
+    /// If the file doesn't exist, all artifacts are pulled
+    #[clap(long, value_name = "PATH")]
+    pub newer_than_file: Option<PathBuf>,
+
+    /// Warn for each artifact uploaded longer than this ago, like `80d`, since artifacts
+    /// expire after 90 days by default. Re-run the workflow to upload them again
+    #[clap(long, value_name = "DURATION", value_parser = parse_duration)]
+    pub warn_age: Option<Duration>,
+
+    /// List the artifacts created in the repo less than this ago, like `2d`, for any
+    /// revision, grouped by workflow and commit, without downloading
+    #[clap(long, value_name = "DURATION", value_parser = parse_duration, conflicts_with_all = ["list", "list_workflows", "plan", "print_layout_hash", "probe", "compare", "branches", "pr", "check_suite", "from_url"])]
+    pub list_since: Option<Duration>,
+
+    /// List the artifacts for the revision with their index, without downloading
+    #[clap(long)]
+    pub list: bool,
+
+    /// List the workflows that uploaded artifacts for the revision, with the number
+    /// of artifacts from each, without downloading
+    #[clap(long, conflicts_with_all = ["list", "plan"])]
+    pub list_workflows: bool,
+
+    /// Check the setup without listing or downloading: resolve the repo, and print
+    /// the rate limit and scopes of the token
+    #[clap(long, conflicts_with_all = ["list", "list_workflows", "plan", "compare", "branches"])]
+    pub probe: bool,
+
+    /// Print what would be pulled and where, without downloading
+    #[clap(long, conflicts_with = "list")]
+    pub plan: bool,
+
+    /// Print a hash of what --plan would print (the repo, revision, filters and where
+    /// each artifact goes), without downloading, for keying a cache on it
+    #[clap(long, conflicts_with_all = ["list", "list_workflows", "plan", "probe", "compare", "branches"])]
+    pub print_layout_hash: bool,
+
+    /// Format of the result of --list, --list-workflows and --plan
+    ///
+    /// With `jsonl`, a line is also printed for each artifact when it's downloaded.
+    /// Progress messages are printed to stderr unless the format is `text`
+    #[clap(long, value_enum, default_value_t)]
+    pub format: Format,
+
+    /// Don't print progress messages. Results and errors are still printed
+    #[clap(short, long)]
+    pub quiet: bool,
+
+    /// Fail if there are any warnings, after the pull is done
+    #[clap(long)]
+    pub strict: bool,
+
+    /// Only download the artifacts at these 1-based indices, as shown by --list
+    #[clap(long, value_delimiter = ',')]
+    pub index: Vec<usize>,
+
+    /// Make the output read-only after the pull is done
+    ///
+    /// A frozen output is made writable again when it's replaced by the next pull.
+    /// Can't be used with --shared-cache, since the hard links share the permissions
+    /// with the files in the cache
+    #[clap(long, conflicts_with = "shared_cache")]
+    pub freeze: bool,
+
+    /// With --freeze, keep the files magnesis writes (like `manifest.json`) and the output
+    /// directory itself writable
+    #[clap(long, requires = "freeze")]
+    pub freeze_keep_metadata: bool,
+
+    /// Write a `.gitignore` in the output directory so git ignores the downloaded files
+    #[clap(long)]
+    pub gitignore: bool,
+
+    /// Only write files that changed since the last pull into the output, and delete
+    /// files that are no longer in the artifacts
+    ///
+    /// The output directory is not cleared. Hashes of the files are kept in
+    /// `SHA256SUMS` in the output directory
+    #[clap(long, conflicts_with = "resume")]
+    pub only_changed: bool,
+
+    /// Write the metadata of the artifacts from the API to `metadata.json` in the output
+    /// directory, without downloading them
+    #[clap(long)]
+    pub metadata_only: bool,
+
+    /// Write `manifest.json` to the output directory, recording the pulled artifacts
+    #[clap(long)]
+    pub manifest: bool,
+
+    /// Continue an interrupted pull, skipping artifacts already complete in `manifest.json`
+    ///
+    /// The output directory is not cleared. Implies --manifest
+    #[clap(long)]
+    pub resume: bool,
+
+    /// Run this shell command after all artifacts are extracted, to validate the output
+    ///
+    /// The output directory is passed in the `MAGNESIS_OUTPUT_DIR` environment variable.
+    /// The pull fails if the command exits with a non-zero status
+    #[clap(long, value_name = "CMD")]
+    pub verify_cmd: Option<String>,
+
+    /// Append `KEY=VALUE` lines about the pull to this file when it's done, to source it
+    /// in a shell or pass it to `$GITHUB_ENV`
+    ///
+    /// The variables are `MAGNESIS_OUTPUT_DIR`, `MAGNESIS_REPO`, `MAGNESIS_REV`
+    /// and `MAGNESIS_ARTIFACT_COUNT` (the number of artifacts downloaded)
+    #[clap(long, value_name = "PATH", conflicts_with_all = ["compare", "branches", "metadata_only", "list", "list_workflows", "plan", "probe"])]
+    pub emit_env: Option<PathBuf>,
+
+    /// POST the status of the pull as JSON to this URL before downloading,
+    /// after each artifact is downloaded, and at the end
+    ///
+    /// Failing to post only prints a warning
+    #[clap(long, value_name = "URL")]
+    pub progress_webhook: Option<String>,
+
+    /// Before pulling, remove the directories next to the output that were pulled
+    /// with this option longer than this ago, like `7d`
+    ///
+    /// Only directories with the `.magnesis` marker this option writes are removed.
+    /// Supports `s`, `m`, `h`, `d` and `w`
+    #[clap(long, value_name = "DURATION", value_parser = parse_duration)]
+    pub clean_older_than: Option<Duration>,
+
+    /// Pull into a new directory next to the output, and only replace the output with it
+    /// after everything is extracted (and --verify-cmd passed), so the output is never
+    /// partially updated
+    #[clap(long, conflicts_with_all = ["resume", "only_changed", "state_dir", "metadata_only"])]
+    pub atomic_output: bool,
+
+    /// Save the state of the pull in this directory, so an interrupted pull continues
+    /// where it left off when run again
+    ///
+    /// The revision and the artifacts found are saved, so they are not resolved or listed
+    /// again, and downloaded artifacts are skipped. The output directory is not cleared
+    /// when continuing, and continuing with a different --rev, --pr, --check-suite
+    /// or --from-url fails. The state is deleted when the pull finishes
+    #[clap(long, value_name = "PATH", conflicts_with = "only_changed")]
+    pub state_dir: Option<PathBuf>,
+
+    /// Print the result as workflow commands, so they show up as annotations in GitHub Actions
+    ///
+    /// Enabled automatically when running in GitHub Actions
+    #[clap(long)]
+    pub github_actions: bool,
+
+    /// Append a Markdown table of the pulled artifacts to the job summary,
+    /// the file named by `GITHUB_STEP_SUMMARY`
+    #[clap(long)]
+    pub step_summary: bool,
+
+    /// Also save the summaries of the jobs in the workflow runs of the pulled artifacts
+    /// into `summaries/` in the output, as `RUN_ID-JOB.md`. These are the outputs of
+    /// the check runs of the jobs. Jobs without a summary are skipped
+    #[clap(long)]
+    pub with_summaries: bool,
+
+    /// Number of API requests and downloads to run at the same time
+    #[clap(short, long, default_value_t = 8)]
+    pub jobs: usize,
+
+    /// Download fewer artifacts at the same time so their archives add up to at most
+    /// this size, like `2G`
+    ///
+    /// Each archive is in memory while it's extracted. An artifact larger than this
+    /// is downloaded on its own
+    #[clap(long, value_name = "SIZE", value_parser = parse_size)]
+    pub max_memory: Option<u64>,
+
+    /// Number of times to retry downloading an artifact on network or server errors
+    ///
+    /// Attempts that take longer than --download-timeout are retried too
+    #[clap(long, default_value_t = 2)]
+    pub retries: u32,
+
+    /// Give up on an attempt to download an artifact after this many seconds,
+    /// and retry it if there are --retries left
+    #[clap(long, value_name = "SECONDS")]
+    pub download_timeout: Option<u64>,
+
+    /// Stop with an error if the whole run takes longer than this many seconds,
+    /// including all retries
+    #[clap(long, value_name = "SECONDS")]
+    pub deadline: Option<u64>,
+
+    /// Number of times to retry listing artifacts and other API calls
+    #[clap(long, default_value_t = 2)]
+    pub list_retries: u32,
+
+    /// Append a line to this file for every retried request, for diagnosing flaky networks
+    ///
+    /// Each line has the timestamp, the URL, the attempt, the reason and the delay, separated by tabs
+    #[clap(long, value_name = "PATH")]
+    pub retry_log_file: Option<PathBuf>,
+
+    /// Replace the start of each artifact's download URL, to download through a mirror
+    ///
+    /// For example, `https://api.github.com=https://mirror.example.com/github`.
+    /// The token is sent to the mirror
+    #[clap(long, value_name = "FROM=TO")]
+    pub download_rewrite: Option<UrlRewrite>,
+
+    /// Base URL of the API, for GitHub Enterprise Server. For example, `https://HOST/api/v3`
+    ///
+    /// The API of github.com is used if not given
+    #[clap(long, value_name = "URL")]
+    pub api_url: Option<String>,
+
+    /// SHA-256 fingerprint (hex, colons are optional) of the API server's certificate.
+    /// Stop if the server presents a different certificate
+    ///
+    /// It's checked before the token is sent. The pin has to be updated every time
+    /// the certificate is renewed, or all pulls fail until it is
+    #[clap(long, value_name = "SHA256", value_parser = parse_fingerprint)]
+    pub pin_cert: Option<String>,
+
+    /// Don't verify TLS certificates. Only use this for testing, for example
+    /// behind a proxy with a self-signed certificate
+    #[clap(long)]
+    pub insecure: bool,
+
+    /// Trust this root certificate (PEM or DER) in addition to the system ones
+    #[clap(long, value_name = "PATH")]
+    pub ca_cert: Option<String>,
+
+    /// Get the API token from the OIDC provider in GitHub Actions, instead of GITHUB_TOKEN
+    ///
+    /// The workflow needs the `id-token: write` permission
+    #[clap(long)]
+    pub oidc: bool,
+
+    /// Audience to request the OIDC token for
+    #[clap(long, requires = "oidc")]
+    pub oidc_audience: Option<String>,
+
+    /// Exchange the OIDC token for an access token with this token broker
+    ///
+    /// The OIDC token is sent as a bearer token in a POST request,
+    /// and the response should be JSON like `{"token": "..."}`
+    #[clap(long, value_name = "URL", requires = "oidc")]
+    pub oidc_exchange: Option<String>,
+
+    /// Fail if fields that magnesis can do without are missing from API responses,
+    /// instead of using defaults for them
+    #[clap(long)]
+    pub strict_schema: bool,
+
+    /// Save the raw API responses to this directory, for debugging and bug reports
+    #[clap(long)]
+    pub dump_raw: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    #[test]
+    fn test_shared_cache_conflicts() {
+        for flag in [&["--freeze"][..], &["--owner", "1000:1000"]] {
+            let args = ["magnesis", "-o", "out", "--shared-cache", "cache"];
+            let result = Cli::try_parse_from(args.iter().chain(flag));
+            assert!(result.is_err());
+        }
+    }
+}
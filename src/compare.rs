@@ -0,0 +1,163 @@
+//! Comparing the files pulled for two revisions, for --compare
+
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use error_stack::{Report, Result, ResultExt};
+
+use crate::{
+    checksum::sha256_hex,
+    freeze::METADATA_FILES,
+    output::{print_json, print_json_line, Format},
+    Error,
+};
+
+/// Files that differ between two output directories, by their paths relative to them
+#[derive(Debug, Default, serde::Serialize)]
+pub struct Diff {
+    pub added: Vec<PathBuf>,
+    pub removed: Vec<PathBuf>,
+    pub changed: Vec<PathBuf>,
+}
+
+impl Diff {
+    /// Compare the files in the directories by their hashes
+    pub async fn new(a: PathBuf, b: PathBuf) -> Result<Self, Error> {
+        tokio::task::spawn_blocking(move || {
+            let a = hash_files(&a).attach_printable_lazy(|| format!("path: {}", a.display()))?;
+            let b = hash_files(&b).attach_printable_lazy(|| format!("path: {}", b.display()))?;
+            let mut diff = Self::default();
+            for (path, hash) in &a {
+                match b.get(path) {
+                    None => diff.removed.push(path.clone()),
+                    Some(other) if other != hash => diff.changed.push(path.clone()),
+                    Some(_) => {}
+                }
+            }
+            diff.added = b.into_keys().filter(|path| !a.contains_key(path)).collect();
+            Ok::<_, Report<io::Error>>(diff)
+        })
+        .await
+        .change_context(Error::Compare)?
+        .change_context(Error::Compare)
+    }
+
+    pub fn print(&self, format: Format) {
+        #[derive(serde::Serialize)]
+        struct DiffItem<'a> {
+            status: &'static str,
+            path: &'a Path,
+        }
+        let items = [
+            ('A', "added", &self.added),
+            ('D', "removed", &self.removed),
+            ('M', "changed", &self.changed),
+        ]
+        .into_iter()
+        .flat_map(|(letter, status, paths)| paths.iter().map(move |path| (letter, status, path)));
+        match format {
+            Format::Json => print_json(self),
+            Format::Jsonl => {
+                items.for_each(|(_, status, path)| print_json_line(&DiffItem { status, path }))
+            }
+            Format::Text => {
+                for (letter, _, path) in items {
+                    println!("{}  {}", letter, path.display());
+                }
+                println!(
+                    "{} added, {} removed, {} changed",
+                    self.added.len(),
+                    self.removed.len(),
+                    self.changed.len()
+                );
+            }
+        }
+    }
+}
+
+/// Get the SHA-256 of every file under the directory, by the path relative to it
+///
+/// The metadata files magnesis writes are left out, since they differ between
+/// any two pulls. Symlinks are not followed, and are compared by their targets
+fn hash_files(dir: &Path) -> io::Result<BTreeMap<PathBuf, String>> {
+    let mut hashes = BTreeMap::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(current) = pending.pop() {
+        for entry in fs::read_dir(&current)? {
+            let entry = entry?;
+            let path = entry.path();
+            let relative = path.strip_prefix(dir).unwrap_or(&path).to_path_buf();
+            if relative
+                .to_str()
+                .is_some_and(|name| METADATA_FILES.contains(&name))
+            {
+                continue;
+            }
+            let file_type = entry.file_type()?;
+            let hash = if file_type.is_symlink() {
+                let target = fs::read_link(&path)?;
+                format!("symlink:{}", target.display())
+            } else if file_type.is_dir() {
+                pending.push(path);
+                continue;
+            } else {
+                sha256_hex(&fs::read(&path)?)
+            };
+            hashes.insert(relative, hash);
+        }
+    }
+    Ok(hashes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{checksum::SHA256SUMS, manifest::Manifest, test_util::TempDir};
+
+    #[tokio::test]
+    async fn test_diff() {
+        let dir = TempDir::new();
+        let write = |path: &str, content: &str| {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        };
+        write("a/same.txt", "1");
+        write("a/app/changed.txt", "1");
+        write("a/app/removed.txt", "1");
+        write("b/same.txt", "1");
+        write("b/app/changed.txt", "2");
+        write("b/app/added.txt", "1");
+        // metadata is different for every pull
+        write(&format!("a/{}", Manifest::FILE_NAME), "a");
+        write(&format!("b/{}", Manifest::FILE_NAME), "b");
+        write(&format!("a/{}", SHA256SUMS), "a");
+        write("b/.magnesis", "");
+
+        let diff = Diff::new(dir.join("a"), dir.join("b")).await.unwrap();
+        assert_eq!(diff.added, [Path::new("app/added.txt")]);
+        assert_eq!(diff.removed, [Path::new("app/removed.txt")]);
+        assert_eq!(diff.changed, [Path::new("app/changed.txt")]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlinks_not_followed() {
+        let temp = TempDir::new();
+        let dir = temp.join("out");
+        fs::create_dir_all(dir.join("real")).unwrap();
+        fs::write(dir.join("real/file.txt"), "content").unwrap();
+        std::os::unix::fs::symlink("real", dir.join("link")).unwrap();
+
+        let hashes = hash_files(&dir).unwrap();
+        assert_eq!(
+            hashes.keys().collect::<Vec<_>>(),
+            [Path::new("link"), Path::new("real/file.txt")]
+        );
+        assert_eq!(hashes[Path::new("link")], "symlink:real");
+    }
+}
@@ -0,0 +1,130 @@
+//! Options for using magnesis as a library, with the same checks as the command line
+
+use std::path::Path;
+
+use clap::Parser;
+use error_stack::{report, Result};
+
+use crate::{cli::Cli, Error};
+
+/// Validated options for [`run`](crate::run), made with a [`ConfigBuilder`]
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub(crate) cli: Cli,
+}
+
+impl Config {
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder::default()
+    }
+}
+
+/// Builder for [`Config`]
+///
+/// The options are checked like the command line arguments when building,
+/// so invalid values and options that can't be used together are errors.
+/// Options that aren't set have the same defaults as on the command line.
+#[derive(Debug, Clone, Default)]
+pub struct ConfigBuilder {
+    args: Vec<String>,
+}
+
+impl ConfigBuilder {
+    /// Repo to use, either `OWNER/REPO` or a GitHub URL, like --repo
+    pub fn repo(self, repo: impl Into<String>) -> Self {
+        self.arg("--repo").arg(repo)
+    }
+
+    /// Revision (commit/branch) to use, like --rev
+    pub fn rev(self, rev: impl Into<String>) -> Self {
+        self.arg("--rev").arg(rev)
+    }
+
+    /// Pull request to pull the head commit of, like --pr
+    pub fn pr(self, number: u64) -> Self {
+        self.arg("--pr").arg(number.to_string())
+    }
+
+    /// Check suite to pull the workflow runs of, like --check-suite
+    pub fn check_suite(self, id: u64) -> Self {
+        self.arg("--check-suite").arg(id.to_string())
+    }
+
+    /// Path to the output directory, like --output
+    pub fn output(self, path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref().display().to_string();
+        self.arg("--output").arg(path)
+    }
+
+    /// Number of API requests and downloads to run at the same time, like --jobs
+    pub fn jobs(self, jobs: usize) -> Self {
+        self.arg("--jobs").arg(jobs.to_string())
+    }
+
+    /// Only pull artifacts whose name matches the glob, like --name. Can be repeated
+    pub fn filter_name(self, glob: impl Into<String>) -> Self {
+        self.arg("--name").arg(glob)
+    }
+
+    /// Add a command line argument, for options without a setter
+    pub fn arg(mut self, arg: impl Into<String>) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    pub fn build(self) -> Result<Config, Error> {
+        let args = std::iter::once("magnesis".to_string()).chain(self.args);
+        let cli = Cli::try_parse_from(args).map_err(|err| {
+            // the first line has the problem, the rest is the usage
+            let message = err.to_string();
+            let message = message.lines().next().unwrap_or_default();
+            report!(Error::Config)
+                .attach_printable(message.trim_start_matches("error: ").to_string())
+        })?;
+        Ok(Config { cli })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build() {
+        let config = Config::builder()
+            .repo("https://github.com/foo/bar")
+            .rev("main")
+            .output("out/artifacts")
+            .jobs(2)
+            .filter_name("app-*")
+            .filter_name("docs")
+            .build()
+            .unwrap();
+        let cli = config.cli;
+        assert_eq!(cli.repo.as_deref(), Some("foo/bar"));
+        assert_eq!(cli.rev, "main");
+        assert_eq!(cli.output, "out/artifacts");
+        assert_eq!(cli.jobs, 2);
+        assert_eq!(cli.name, ["app-*", "docs"]);
+
+        // same defaults as the command line
+        let cli = Config::builder().build().unwrap().cli;
+        assert_eq!(cli.rev, "HEAD");
+        assert_eq!(cli.output, "dist");
+        assert_eq!(cli.jobs, 8);
+    }
+
+    #[test]
+    fn test_build_errors() {
+        let message = |builder: ConfigBuilder| format!("{:?}", builder.build().unwrap_err());
+
+        let err = message(Config::builder().pr(1).check_suite(2));
+        assert!(err.contains("cannot be used with"), "{}", err);
+        let err = message(Config::builder().repo("https://github.com/foo"));
+        assert!(err.contains("--repo"), "{}", err);
+        let err = message(Config::builder().arg("--jobs").arg("many"));
+        assert!(err.contains("--jobs"), "{}", err);
+        let err = message(Config::builder().arg("--no-such-option"));
+        assert!(err.contains("--no-such-option"), "{}", err);
+    }
+}
@@ -0,0 +1,350 @@
+//! Downloading the artifacts into the output, at the same time, and recording
+//! each one in the manifest, state and inventory as soon as it's done
+
+use std::{path::Path, sync::Arc};
+
+use error_stack::{report, Report, Result, ResultExt};
+use tokio::{sync::Semaphore, task::JoinSet};
+
+use crate::{
+    artifact::{is_deleted, Artifact, DedupDownloads, OnDeleted},
+    checksum::write_sums,
+    cli::Cli,
+    extract::ExtractOptions,
+    github::Api,
+    inventory::Inventory,
+    layout::Layout,
+    manifest::Manifest,
+    memory::MemoryBudget,
+    output::{inherit, print_json_line, progress, warning, Format},
+    output_dir::remove_deleted_files,
+    sbom::Component,
+    size_report::{ArtifactSize, SizeReport},
+    state::State,
+    timings::ArtifactTiming,
+    webhook::{Webhook, WebhookEvent},
+    Error,
+};
+
+/// What is pulled, and where to
+pub struct Pull<'a> {
+    pub repo: &'a str,
+    pub rev: &'a str,
+    pub output: &'a Path,
+    pub layout: &'a Layout,
+}
+
+/// Where the pull records the artifacts that are done
+#[derive(Default)]
+pub struct Records {
+    pub manifest: Option<Manifest>,
+    /// Saved in the --state-dir
+    pub state: Option<State>,
+    pub inventory: Option<Inventory>,
+    pub webhook: Option<Webhook>,
+}
+
+impl Records {
+    fn is_complete(&self, id: u64) -> bool {
+        self.manifest
+            .as_ref()
+            .is_some_and(|manifest| manifest.is_complete(id))
+            || self
+                .state
+                .as_ref()
+                .is_some_and(|state| state.complete.contains(&id))
+    }
+}
+
+/// What was downloaded, for the reports after the pull
+#[derive(Default)]
+pub struct Downloads {
+    pub timings: Vec<ArtifactTiming>,
+    pub sizes: SizeReport,
+    /// For --sbom
+    pub components: Vec<Component>,
+    /// Name and reason of the artifacts not downloaded, for --step-summary
+    pub skipped: Vec<(String, &'static str)>,
+}
+
+/// Download and extract the artifacts that are not complete yet
+///
+/// Up to --jobs artifacts are downloaded at the same time. If there are sums
+/// from the previous pull, the files that are gone since are removed
+pub async fn download_artifacts(
+    api: &Arc<Api>,
+    cli: &Cli,
+    pull: &Pull<'_>,
+    artifacts: Vec<Artifact>,
+    mut extract_options: ExtractOptions,
+    records: &mut Records,
+) -> Result<Downloads, Error> {
+    let mut downloads = Downloads::default();
+    if cli.dedup_downloads {
+        let pending = artifacts
+            .iter()
+            .filter(|artifact| !records.is_complete(artifact.id))
+            .collect::<Vec<_>>();
+        extract_options.dedup = Some(DedupDownloads::new(&pending));
+    }
+    let extract_options = Arc::new(extract_options);
+    let permits = Arc::new(Semaphore::new(cli.jobs.max(1)));
+    let memory = cli.max_memory.map(|max| Arc::new(MemoryBudget::new(max)));
+    let mut handles = JoinSet::new();
+
+    let mut pending = Vec::new();
+    for artifact in artifacts {
+        if records.is_complete(artifact.id) {
+            progress!("skipping `{}`, already downloaded", artifact.name);
+            downloads
+                .skipped
+                .push((artifact.name.clone(), "already downloaded"));
+        } else {
+            pending.push(artifact);
+        }
+    }
+
+    // the receiver should hear about the start before any artifact finishes
+    let total = pending.len();
+    if let Some(webhook) = &records.webhook {
+        let event = WebhookEvent::Start {
+            repo: pull.repo,
+            rev: pull.rev,
+            total,
+        };
+        webhook.post(&event).await;
+    }
+
+    if let (Some(dir), Some(state)) = (&cli.state_dir, &mut records.state) {
+        for artifact in &pending {
+            let attempt = state.add_attempt(artifact.id);
+            if attempt > 1 {
+                progress!(
+                    "`{}` was not finished by {} earlier pulls",
+                    artifact.name,
+                    attempt - 1
+                );
+            }
+        }
+        state.save(dir).await?;
+    }
+
+    let on_deleted = cli.on_deleted;
+    for artifact in pending {
+        let api = Arc::clone(api);
+        let permits = Arc::clone(&permits);
+        let memory = memory.clone();
+        let out_dir = pull
+            .output
+            .join(pull.layout.destination(&artifact.name, pull.rev));
+        let path = out_dir.clone();
+        let extract_options = Arc::clone(&extract_options);
+        let retry = api.download_retry();
+        let cancel = api.cancel().clone();
+        handles.spawn(inherit(async move {
+            let signed_url = artifact
+                .resolve_download_url(&api)
+                .await
+                .change_context(Error::DownloadArtifact)
+                .attach_printable_lazy(|| format!("artifact: {}", artifact.name))?;
+            let _permit = permits
+                .acquire()
+                .await
+                .change_context(Error::DownloadArtifact)?;
+            let _memory = match &memory {
+                Some(memory) => Some(
+                    memory
+                        .reserve(artifact.size_in_bytes.unwrap_or_default())
+                        .await?,
+                ),
+                None => None,
+            };
+            progress!("downloading `{}`", artifact.name);
+            let downloaded = match artifact
+                .download(
+                    &api,
+                    Some(signed_url),
+                    out_dir,
+                    extract_options,
+                    retry,
+                    &cancel,
+                )
+                .await
+            {
+                Err(err) if on_deleted == OnDeleted::Skip && is_deleted(&err) => {
+                    warning!(
+                        "skipping `{}`, it or its workflow run was deleted",
+                        artifact.name
+                    );
+                    return Ok((artifact, path, None));
+                }
+                result => result?,
+            };
+            Ok::<_, Report<Error>>((artifact, path, Some(downloaded)))
+        }));
+    }
+
+    let mut sums = Vec::new();
+    while let Some(result) = handles.join_next().await {
+        let (artifact, path, downloaded) = result.change_context(Error::DownloadArtifact)??;
+        let Some(downloaded) = downloaded else {
+            downloads.skipped.push((artifact.name.clone(), "deleted"));
+            continue;
+        };
+        let id = artifact.id;
+        if cli.format == Format::Jsonl {
+            #[derive(serde::Serialize)]
+            struct DownloadedItem<'a> {
+                id: u64,
+                name: &'a str,
+                path: &'a Path,
+                files: usize,
+            }
+            print_json_line(&DownloadedItem {
+                id,
+                name: &downloaded.timing.name,
+                path: &path,
+                files: downloaded.files.len(),
+            });
+        }
+        downloads.sizes.artifacts.push(ArtifactSize {
+            name: downloaded.timing.name.clone(),
+            downloaded: downloaded.size,
+            extracted: downloaded.files.iter().map(|file| file.size).sum(),
+        });
+        if cli.sbom.is_some() {
+            downloads.components.push(Component::new(
+                id,
+                &artifact.name,
+                &downloaded.sha256,
+                pull.repo,
+                &artifact.workflow_run.head_sha,
+                &artifact.archive_download_url,
+            ));
+        }
+        downloads.timings.push(downloaded.timing);
+        if let Some(webhook) = &records.webhook {
+            let event = WebhookEvent::Artifact {
+                id,
+                name: &artifact.name,
+                completed: downloads.timings.len(),
+                total,
+            };
+            webhook.post(&event).await;
+        }
+        sums.extend(
+            downloaded
+                .files
+                .into_iter()
+                .filter_map(|file| Some((file.path, file.sha256?))),
+        );
+        if let Some(inventory) = &mut records.inventory {
+            inventory.add(&artifact, pull.layout.destination(&artifact.name, pull.rev))?;
+            inventory.save().await?;
+        }
+        if let Some(manifest) = &mut records.manifest {
+            manifest.set_complete(id, downloaded.normalized_zip.clone());
+            manifest.save(pull.output).await?;
+        }
+        if let (Some(dir), Some(state)) = (&cli.state_dir, &mut records.state) {
+            state.complete.insert(id);
+            state.save(dir).await?;
+        }
+    }
+
+    if let Some(previous_sums) = &extract_options.previous_sums {
+        remove_deleted_files(previous_sums, &sums).await?;
+        write_sums(pull.output, &sums).await?;
+    }
+    Ok(downloads)
+}
+
+/// Check every artifact has a digest, for --require-digest
+pub fn check_digests(artifacts: &[Artifact]) -> Result<(), Error> {
+    let missing = artifacts
+        .iter()
+        .filter(|artifact| artifact.digest.is_none())
+        .map(|artifact| format!("`{}`", artifact.name))
+        .collect::<Vec<_>>();
+    if missing.is_empty() {
+        return Ok(());
+    }
+    Err(report!(Error::Digest))
+        .attach_printable(format!(
+            "artifacts without a digest: {}",
+            missing.join(", ")
+        ))
+        .attach_printable("run without --require-digest to download them anyway")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    use clap::Parser;
+
+    use crate::test_util::{artifacts, mock_api, zip_file, MockServer, Response, TempDir};
+
+    #[test]
+    fn test_check_digests() {
+        let mut all = artifacts(&["a", "b", "c"]);
+        all[1].digest = Some("sha256:abc".to_string());
+        let err = format!("{:?}", check_digests(&all).unwrap_err());
+        assert!(
+            err.contains("artifacts without a digest: `a`, `c`"),
+            "{}",
+            err
+        );
+        for artifact in &mut all {
+            artifact.digest = Some("sha256:abc".to_string());
+        }
+        assert!(check_digests(&all).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_download_timings() {
+        let server = MockServer::start(|request| {
+            if request.path.starts_with("/download/") {
+                std::thread::sleep(Duration::from_millis(20));
+                Response::new(200).body(zip_file(&[("a.txt", b"a")]))
+            } else {
+                Response::new(404)
+            }
+        })
+        .await;
+        let mut all = artifacts(&["a", "b"]);
+        for artifact in &mut all {
+            artifact.archive_download_url = server.url(&format!("/download/{}", artifact.id));
+        }
+        let cli = Cli::try_parse_from(["magnesis", "-o", "out"]).unwrap();
+        let dir = TempDir::new();
+        let pull = Pull {
+            repo: "foo/bar",
+            rev: "abc",
+            output: &dir.join("out"),
+            layout: &Layout::default(),
+        };
+        let downloads = download_artifacts(
+            &mock_api(&server),
+            &cli,
+            &pull,
+            all,
+            Default::default(),
+            &mut Records::default(),
+        )
+        .await
+        .unwrap();
+        let mut names = downloads
+            .timings
+            .iter()
+            .map(|timing| timing.name.as_str())
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, ["a", "b"]);
+        for timing in &downloads.timings {
+            assert!(timing.download >= Duration::from_millis(20), "{:?}", timing);
+        }
+    }
+}
@@ -0,0 +1,95 @@
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("failed to create output directory")]
+    CreateOutput,
+    #[error("failed to get repo")]
+    Repo,
+    #[error("failed to get rev")]
+    Rev,
+    #[error("no token provided")]
+    NoToken,
+    #[error("invalid token")]
+    InvalidToken,
+    #[error("failed to get token with OIDC")]
+    Oidc,
+    #[error("failed to get artifacts")]
+    GetArtifacts,
+    #[error("failed to get workflow runs")]
+    GetWorkflowRuns,
+    #[error("failed to get pull request")]
+    PullRequest,
+    #[error("failed to get jobs of workflow run")]
+    GetJobs,
+    #[error("failed to download artifact")]
+    DownloadArtifact,
+    #[error("failed to parse response")]
+    Parse,
+    #[error("failed to run command")]
+    Command,
+    #[error("failed to build request client")]
+    RequestClient,
+    #[error("request failed")]
+    Request,
+    #[error("artifact expired")]
+    Expired,
+    #[error("artifact or its workflow run was deleted")]
+    Deleted,
+    #[error("failed to extract artifact")]
+    Extract,
+    #[error("invalid layout file")]
+    Layout,
+    #[error("invalid artifact index")]
+    InvalidIndex,
+    #[error("failed to save raw response")]
+    DumpRaw,
+    #[error("failed to read or write manifest")]
+    Manifest,
+    #[error("failed to read or write checksums")]
+    Checksum,
+    #[error("failed to write metadata")]
+    Metadata,
+    #[error("failed to read or write marker file")]
+    Marker,
+    #[error("failed to list files in artifact")]
+    Preview,
+    #[error("warnings are treated as errors with --strict")]
+    Strict,
+    #[error("failed to read or write state")]
+    State,
+    #[error("failed to change permissions of output")]
+    Freeze,
+    #[error("failed to compare outputs")]
+    Compare,
+    #[error("invalid config")]
+    Config,
+    #[error("failed to read or write shared cache")]
+    SharedCache,
+    #[error("failed to write SBOM")]
+    Sbom,
+    #[error("failed to get rate limit")]
+    RateLimit,
+    #[error("failed to write env file")]
+    EmitEnv,
+    #[error("failed to verify artifact digest")]
+    Digest,
+    #[error("request timed out, see --download-timeout")]
+    Timeout,
+    #[error("--deadline passed")]
+    Deadline,
+    #[error("failed to clean old outputs")]
+    Clean,
+    #[error("server certificate doesn't match --pin-cert")]
+    PinCert,
+    #[error("failed to read or write inventory")]
+    Inventory,
+    #[error("failed to write step summary")]
+    StepSummary,
+    #[error("cancelled")]
+    Cancelled,
+    #[error("failed to get or save job summaries")]
+    Summaries,
+    #[error("hit the GitHub secondary rate limit, requests are sent too fast")]
+    SecondaryRateLimit,
+    #[error("failed to change owner of output")]
+    Owner,
+}
@@ -0,0 +1,1387 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    io::{self, Cursor, Read},
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+use error_stack::{report, Result, ResultExt};
+use zip::{write::SimpleFileOptions, CompressionMethod, DateTime, ZipArchive, ZipWriter};
+
+use crate::{
+    artifact::DedupDownloads,
+    checksum::sha256_hex,
+    glob::glob_match,
+    output::{progress, warning},
+    shared_cache::SharedCache,
+    sink::{FsSink, OutputSink},
+    Error,
+};
+
+/// What to do when a file being extracted already exists
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OnConflict {
+    /// Replace the existing file
+    #[default]
+    Overwrite,
+    /// Keep the existing file
+    Skip,
+    /// Stop with an error
+    Fail,
+    /// Keep the file from the artifact created last. Files not from this pull are replaced
+    Newest,
+}
+
+/// What to do when files differ only in the case of their paths, which
+/// collide on case-insensitive file systems
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OnCaseCollision {
+    /// Add a number to the name of the later file, like `file~1.txt`
+    Rename,
+    /// Stop with an error
+    #[default]
+    Fail,
+    /// Keep the earlier file
+    Skip,
+}
+
+/// What to do when a symlink in an artifact can't be created, for example
+/// on Windows without the privilege to create symlinks
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SymlinkPolicy {
+    /// Stop with an error
+    #[default]
+    Create,
+    /// Copy the file or directory the symlink points to
+    Copy,
+    /// Print a warning and leave the symlink out
+    Skip,
+}
+
+/// Line ending to convert text files to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LineEnding {
+    Lf,
+    Crlf,
+}
+
+#[derive(Debug, Default)]
+pub struct ExtractOptions {
+    pub on_conflict: OnConflict,
+    pub on_case_collision: OnCaseCollision,
+    pub symlink_policy: SymlinkPolicy,
+    /// Save the archives with an index of their files instead of extracting them
+    pub zip_index: bool,
+    /// Also save a normalized copy of each archive in this directory,
+    /// for reproducible redistribution
+    pub normalized_zip: Option<PathBuf>,
+    /// Only extract the files under this path in the archive, relative to it
+    pub inner_path: Option<PathBuf>,
+    /// Limits for each archive, to stop extracting zip bombs. `None` for no limit
+    pub max_entries: Option<usize>,
+    pub max_uncompressed: Option<u64>,
+    /// Convert line endings of files matching `text_globs`
+    pub normalize_eol: Option<LineEnding>,
+    pub text_globs: Vec<String>,
+    /// Permissions for extracted files and directories on Unix, instead of the ones in the zip
+    pub file_mode: Option<u32>,
+    pub dir_mode: Option<u32>,
+    /// Compute the SHA-256 of each extracted file
+    pub hash: bool,
+    /// Hashes of files from the previous pull, for --only-changed.
+    /// Files with the same hash are not written again
+    pub previous_sums: Option<HashMap<PathBuf, String>>,
+    /// Number of files each artifact should contain, for --expect-files
+    pub expect_files: Vec<ExpectFiles>,
+    /// Files written so far with the creation time of their artifact, for `--on-conflict newest`
+    pub written: Mutex<HashMap<PathBuf, String>>,
+    /// Files extracted so far by their lowercase path, to detect case collisions.
+    /// Shared between artifacts since they can be merged into the same directory
+    pub case_paths: Mutex<HashMap<String, PathBuf>>,
+    /// Store files in the --shared-cache and link them into the output
+    pub shared_cache: Option<SharedCache>,
+    /// Download archives with the same digest once, for --dedup-downloads
+    pub dedup: Option<DedupDownloads>,
+    /// Where to write the files, the local filesystem if `None`
+    pub sink: Option<Arc<dyn OutputSink>>,
+}
+
+/// Expected number of files in an artifact, in the format `N` for all artifacts
+/// or `NAME=N` for a specific artifact
+#[derive(Debug, Clone)]
+pub struct ExpectFiles {
+    pub name: Option<String>,
+    pub count: usize,
+}
+
+impl FromStr for ExpectFiles {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let (name, count) = match s.rsplit_once('=') {
+            Some((name, count)) => (Some(name.to_string()), count),
+            None => (None, s),
+        };
+        let count = count
+            .parse()
+            .map_err(|_| format!("invalid file count `{}`", count))?;
+        Ok(Self { name, count })
+    }
+}
+
+/// Parse an octal permission mode like `644`, for --file-mode and --dir-mode
+pub fn parse_mode(s: &str) -> std::result::Result<u32, String> {
+    let s = s.strip_prefix("0o").unwrap_or(s);
+    let mode = u32::from_str_radix(s, 8).map_err(|_| format!("invalid octal mode `{}`", s))?;
+    if mode > 0o7777 {
+        return Err(format!("mode `{}` is too large", s));
+    }
+    Ok(mode)
+}
+
+/// A file written (or kept unchanged) by the extraction
+#[derive(Debug)]
+pub struct ExtractedFile {
+    pub path: PathBuf,
+    pub sha256: Option<String>,
+    pub size: u64,
+}
+
+impl ExtractOptions {
+    /// Where to write the extracted files
+    pub fn sink(&self) -> &dyn OutputSink {
+        self.sink.as_deref().unwrap_or(&FsSink)
+    }
+
+    /// Get the number of files the artifact should contain, if specified
+    pub fn expected_files_for(&self, name: &str) -> Option<usize> {
+        let specific = self
+            .expect_files
+            .iter()
+            .find(|expect| expect.name.as_deref() == Some(name));
+        let all = self
+            .expect_files
+            .iter()
+            .find(|expect| expect.name.is_none());
+        specific.or(all).map(|expect| expect.count)
+    }
+
+    /// Check the path against the files extracted so far for --on-case-collision
+    ///
+    /// Returns the path to extract to, or `None` if the file should be skipped
+    fn resolve_case_collision(&self, path: PathBuf) -> Result<Option<PathBuf>, Error> {
+        let mut case_paths = self.case_paths.lock().unwrap_or_else(|e| e.into_inner());
+        let key = path.to_string_lossy().to_lowercase();
+        let existing = match case_paths.get(&key) {
+            // the same path is handled by --on-conflict
+            Some(existing) if *existing != path => existing,
+            _ => {
+                case_paths.insert(key, path.clone());
+                return Ok(Some(path));
+            }
+        };
+        match self.on_case_collision {
+            OnCaseCollision::Fail => Err(report!(Error::Extract)
+                .attach_printable(format!(
+                    "`{}` and `{}` only differ in case",
+                    existing.display(),
+                    path.display()
+                ))
+                .attach_printable("see --on-case-collision")),
+            OnCaseCollision::Skip => {
+                progress!(
+                    "skipping `{}`, which only differs in case from `{}`",
+                    path.display(),
+                    existing.display()
+                );
+                Ok(None)
+            }
+            OnCaseCollision::Rename => {
+                let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+                let extension = path
+                    .extension()
+                    .map(|extension| format!(".{}", extension.to_string_lossy()))
+                    .unwrap_or_default();
+                let (renamed, key) = (1..)
+                    .map(|i| {
+                        let renamed = path.with_file_name(format!("{}~{}{}", stem, i, extension));
+                        let key = renamed.to_string_lossy().to_lowercase();
+                        (renamed, key)
+                    })
+                    .find(|(_, key)| !case_paths.contains_key(key))
+                    .unwrap_or_default();
+                progress!(
+                    "renaming `{}` to `{}`, which only differs in case from `{}`",
+                    path.display(),
+                    renamed.display(),
+                    existing.display()
+                );
+                case_paths.insert(key, renamed.clone());
+                Ok(Some(renamed))
+            }
+        }
+    }
+
+    /// Get the line ending to convert the file in the archive to, if any
+    fn line_ending_for(&self, name: &str) -> Option<LineEnding> {
+        let line_ending = self.normalize_eol?;
+        self.text_globs
+            .iter()
+            .any(|pattern| glob_match(pattern, name))
+            .then_some(line_ending)
+    }
+}
+
+/// Extract the zip archive into the directory
+///
+/// If `expected_files` is set, the archive is checked to contain that many files
+/// before anything is extracted. `created_at` is the creation time of the artifact,
+/// used for `--on-conflict newest`
+pub fn extract(
+    bytes: &[u8],
+    out_dir: &Path,
+    options: &ExtractOptions,
+    expected_files: Option<usize>,
+    created_at: &str,
+) -> Result<Vec<ExtractedFile>, Error> {
+    let mut archive = ZipArchive::new(Cursor::new(bytes)).change_context(Error::Extract)?;
+    let inner_path = options.inner_path.as_deref();
+    check_file_count(&archive, expected_files, inner_path)?;
+    if let Some(max_entries) = options.max_entries.filter(|max| archive.len() > *max) {
+        return Err(report!(Error::Extract))
+            .attach_printable("possible zip bomb: exceeded limits")
+            .attach_printable(format!(
+                "{} entries, more than --max-entries {}",
+                archive.len(),
+                max_entries
+            ));
+    }
+    let max_uncompressed = options.max_uncompressed.unwrap_or(u64::MAX);
+    // the sizes in the archive can't be trusted, so the limit is checked while reading
+    let mut uncompressed = 0;
+    let sink = options.sink();
+    sink.create_dir_all(out_dir)
+        .change_context(Error::Extract)?;
+    let mut extracted = Vec::new();
+    // directories get --dir-mode at the end, so it doesn't stop files from being written
+    let mut dirs = BTreeSet::new();
+    dirs.insert(out_dir.to_path_buf());
+    // created at the end, so the targets are extracted before they are copied
+    let mut symlinks = Vec::new();
+
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i).change_context(Error::Extract)?;
+        let Some(relative_path) = file.enclosed_name() else {
+            return Err(report!(Error::Extract))
+                .attach_printable(format!("invalid path in archive: {}", file.name()));
+        };
+        let Some(relative_path) = strip_inner_path(relative_path, inner_path) else {
+            continue;
+        };
+        let path = out_dir.join(relative_path);
+        if file.is_dir() {
+            sink.create_dir_all(&path).change_context(Error::Extract)?;
+            dirs.extend(
+                path.ancestors()
+                    .take_while(|dir| dir.starts_with(out_dir))
+                    .map(Path::to_path_buf),
+            );
+            continue;
+        }
+        let Some(path) = options.resolve_case_collision(path)? else {
+            continue;
+        };
+        if let Some(parent) = path.parent() {
+            sink.create_dir_all(parent).change_context(Error::Extract)?;
+            dirs.extend(
+                parent
+                    .ancestors()
+                    .take_while(|dir| dir.starts_with(out_dir))
+                    .map(Path::to_path_buf),
+            );
+        }
+        let mut content = Vec::new();
+        let remaining = max_uncompressed - uncompressed;
+        (&mut file)
+            .take(remaining.saturating_add(1))
+            .read_to_end(&mut content)
+            .change_context(Error::Extract)
+            .attach_printable_lazy(|| format!("entry: {}", file.name()))?;
+        if content.len() as u64 > remaining {
+            return Err(report!(Error::Extract))
+                .attach_printable("possible zip bomb: exceeded limits")
+                .attach_printable(format!(
+                    "more than --max-uncompressed {} bytes",
+                    max_uncompressed
+                ));
+        }
+        uncompressed += content.len() as u64;
+        if file.is_symlink() {
+            let target = PathBuf::from(String::from_utf8_lossy(&content).into_owned());
+            symlinks.push((path, target));
+            continue;
+        }
+        if let Some(line_ending) = options.line_ending_for(file.name()) {
+            content = normalize_line_endings(&content, line_ending);
+        }
+        let sha256 = (options.hash || options.shared_cache.is_some()).then(|| sha256_hex(&content));
+
+        let size = content.len() as u64;
+        let mut on_conflict = options.on_conflict;
+        if let Some(previous) = &options.previous_sums {
+            if let Some(previous_sha256) = previous.get(&path) {
+                if sha256.as_ref() == Some(previous_sha256) && sink.exists(&path) {
+                    extracted.push(ExtractedFile { path, sha256, size });
+                    continue;
+                }
+                // the file is from the previous pull, so it's ok to replace
+                on_conflict = OnConflict::Overwrite;
+            }
+        }
+
+        // held while writing, so an older artifact can't replace the file between
+        // the check and the write
+        let mut written = None;
+        if on_conflict == OnConflict::Newest {
+            let mut guard = options.written.lock().unwrap_or_else(|e| e.into_inner());
+            if guard
+                .get(&path)
+                .is_some_and(|existing| existing.as_str() >= created_at)
+            {
+                progress!("keeping newer file `{}`", path.display());
+                continue;
+            }
+            guard.insert(path.clone(), created_at.to_string());
+            written = Some(guard);
+        }
+
+        let mode = options.file_mode.or(file.unix_mode());
+        if let (Some(cache), Some(sha256)) = (&options.shared_cache, &sha256) {
+            let linked = cache.link_file(sink, &content, sha256, mode, &path, on_conflict)?;
+            drop(written);
+            if linked {
+                extracted.push(ExtractedFile {
+                    path,
+                    sha256: Some(sha256.clone()),
+                    size,
+                });
+            }
+            continue;
+        }
+
+        if !write_file(sink, &path, &content, on_conflict)? {
+            continue;
+        }
+        drop(written);
+
+        if let Some(mode) = mode {
+            sink.set_mode(&path, mode)
+                .change_context(Error::Extract)
+                .attach_printable_lazy(|| format!("path: {}", path.display()))?;
+        }
+
+        extracted.push(ExtractedFile { path, sha256, size });
+    }
+
+    for (path, target) in symlinks {
+        extracted.extend(create_symlink(&path, &target, out_dir, options)?);
+    }
+
+    if let Some(mode) = options.dir_mode {
+        for dir in dirs {
+            sink.set_mode(&dir, mode)
+                .change_context(Error::Extract)
+                .attach_printable_lazy(|| format!("path: {}", dir.display()))?;
+        }
+    }
+
+    Ok(extracted)
+}
+
+/// Save the archive as `<name>.zip` in the directory instead of extracting it, with
+/// `<name>.index.txt` listing the paths in it, for --zip-index
+pub fn save_with_index(
+    bytes: &[u8],
+    out_dir: &Path,
+    name: &str,
+    options: &ExtractOptions,
+    expected_files: Option<usize>,
+) -> Result<Vec<ExtractedFile>, Error> {
+    let archive = ZipArchive::new(Cursor::new(bytes)).change_context(Error::Extract)?;
+    // the whole archive is saved, --inner-path can't be used with --zip-index
+    check_file_count(&archive, expected_files, None)?;
+    let index = archive
+        .file_names()
+        .map(|name| format!("{}\n", name))
+        .collect::<String>();
+    let sink = options.sink();
+    sink.create_dir_all(out_dir)
+        .change_context(Error::Extract)?;
+    let mut saved = Vec::new();
+    for (path, content) in [
+        (out_dir.join(format!("{}.zip", name)), bytes),
+        (
+            out_dir.join(format!("{}.index.txt", name)),
+            index.as_bytes(),
+        ),
+    ] {
+        if !write_file(sink, &path, content, options.on_conflict)? {
+            continue;
+        }
+        saved.push(ExtractedFile {
+            path,
+            sha256: options.hash.then(|| sha256_hex(content)),
+            size: content.len() as u64,
+        });
+    }
+    Ok(saved)
+}
+
+/// Repack the archive as `<name>.zip` in the directory, with the entries sorted by path and
+/// fixed timestamps and permissions, so the same files always give the same archive.
+/// Fails if the file already exists
+///
+/// Returns the SHA-256 of the repacked archive
+pub fn save_normalized_zip(
+    bytes: &[u8],
+    zip_dir: &Path,
+    name: &str,
+    sink: &dyn OutputSink,
+) -> Result<String, Error> {
+    let mut archive = ZipArchive::new(Cursor::new(bytes)).change_context(Error::Extract)?;
+    let mut names = archive.file_names().map(String::from).collect::<Vec<_>>();
+    names.sort();
+    let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = SimpleFileOptions::default()
+        .compression_method(CompressionMethod::Deflated)
+        .last_modified_time(DateTime::default());
+    for entry_name in names {
+        let mut file = archive
+            .by_name(&entry_name)
+            .change_context(Error::Extract)?;
+        if file.is_dir() {
+            writer
+                .add_directory(entry_name.as_str(), options.unix_permissions(0o755))
+                .change_context(Error::Extract)?;
+            continue;
+        }
+        let executable = file.unix_mode().is_some_and(|mode| mode & 0o111 != 0);
+        let permissions = if executable { 0o755 } else { 0o644 };
+        writer
+            .start_file(entry_name.as_str(), options.unix_permissions(permissions))
+            .change_context(Error::Extract)?;
+        io::copy(&mut file, &mut writer)
+            .change_context(Error::Extract)
+            .attach_printable_lazy(|| format!("entry: {}", entry_name))?;
+    }
+    let normalized = writer.finish().change_context(Error::Extract)?.into_inner();
+    let path = zip_dir.join(format!("{}.zip", name));
+    sink.create_dir_all(zip_dir)
+        .and_then(|_| sink.write_file(&path, &normalized, false))
+        .change_context(Error::Extract)
+        .attach_printable_lazy(|| format!("path: {}", path.display()))?;
+    Ok(sha256_hex(&normalized))
+}
+
+/// Get the path of the entry relative to --inner-path, or `None` if it's not under it
+fn strip_inner_path(path: PathBuf, inner_path: Option<&Path>) -> Option<PathBuf> {
+    let Some(inner_path) = inner_path else {
+        return Some(path);
+    };
+    let relative_path = path.strip_prefix(inner_path).ok()?;
+    (!relative_path.as_os_str().is_empty()).then(|| relative_path.to_path_buf())
+}
+
+/// Check the archive has the number of files from --expect-files,
+/// counting only the files that are extracted
+fn check_file_count(
+    archive: &ZipArchive<Cursor<&[u8]>>,
+    expected_files: Option<usize>,
+    inner_path: Option<&Path>,
+) -> Result<(), Error> {
+    let Some(expected) = expected_files else {
+        return Ok(());
+    };
+    let count = archive
+        .file_names()
+        .filter(|name| !name.ends_with('/'))
+        .filter(|name| strip_inner_path(PathBuf::from(name), inner_path).is_some())
+        .count();
+    if count != expected {
+        let report = report!(Error::Extract).attach_printable(format!(
+            "expected {} files in the artifact, but found {}",
+            expected, count
+        ));
+        return Err(match inner_path {
+            Some(inner_path) => {
+                report.attach_printable(format!("in --inner-path `{}`", inner_path.display()))
+            }
+            None => report,
+        });
+    }
+    Ok(())
+}
+
+/// Write the extracted file, returning `false` if it exists and is skipped
+fn write_file(
+    sink: &dyn OutputSink,
+    path: &Path,
+    content: &[u8],
+    on_conflict: OnConflict,
+) -> Result<bool, Error> {
+    let overwrite = matches!(on_conflict, OnConflict::Overwrite | OnConflict::Newest);
+    match sink.write_file(path, content, overwrite) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists && on_conflict == OnConflict::Skip => {
+            progress!("skipping existing file `{}`", path.display());
+            Ok(false)
+        }
+        Err(e) => {
+            let exists = e.kind() == io::ErrorKind::AlreadyExists;
+            let report = report!(e)
+                .change_context(Error::Extract)
+                .attach_printable(format!("path: {}", path.display()));
+            if exists {
+                return Err(report.attach_printable("file already exists, see --on-conflict"));
+            }
+            Err(report)
+        }
+    }
+}
+
+/// Create the symlink at `path`, or apply the --symlink-policy if it can't be created
+///
+/// Returns the files copied for `SymlinkPolicy::Copy`
+fn create_symlink(
+    path: &Path,
+    target: &Path,
+    out_dir: &Path,
+    options: &ExtractOptions,
+) -> Result<Vec<ExtractedFile>, Error> {
+    let sink = options.sink();
+    let parent = path.parent().unwrap_or(out_dir);
+    // the target is resolved without following symlinks, which is only right
+    // if the directory of the symlink isn't reached through one
+    let symlinked_dir = parent
+        .ancestors()
+        .take_while(|dir| *dir != out_dir && dir.starts_with(out_dir))
+        .find(|dir| sink.is_symlink(dir));
+    if let Some(dir) = symlinked_dir {
+        return Err(report!(Error::Extract))
+            .attach_printable(format!("path: {}", path.display()))
+            .attach_printable(format!(
+                "symlink is in `{}`, which is a symlink",
+                dir.display()
+            ));
+    }
+    let resolved = resolve_symlink_target(parent, target)
+        .filter(|resolved| resolved.starts_with(out_dir))
+        .ok_or_else(|| report!(Error::Extract))
+        .attach_printable(format!("path: {}", path.display()))
+        .attach_printable(format!(
+            "symlink points outside of the artifact: {}",
+            target.display()
+        ))?;
+
+    if sink.exists(path) {
+        match options.on_conflict {
+            OnConflict::Skip => {
+                progress!("skipping existing file `{}`", path.display());
+                return Ok(Vec::new());
+            }
+            OnConflict::Fail => {
+                return Err(report!(Error::Extract))
+                    .attach_printable(format!("path: {}", path.display()))
+                    .attach_printable("file already exists, see --on-conflict");
+            }
+            OnConflict::Overwrite | OnConflict::Newest => {
+                sink.remove(path)
+                    .change_context(Error::Extract)
+                    .attach_printable_lazy(|| format!("path: {}", path.display()))?;
+            }
+        }
+    }
+
+    let result = sink.symlink(target, path, sink.is_dir(&resolved));
+    let Err(e) = result else {
+        return Ok(Vec::new());
+    };
+    match options.symlink_policy {
+        SymlinkPolicy::Create => Err(report!(e)
+            .change_context(Error::Extract)
+            .attach_printable(format!("path: {}", path.display()))
+            .attach_printable("failed to create symlink, see --symlink-policy")),
+        SymlinkPolicy::Skip => {
+            warning!(
+                "skipping symlink `{}`, it can't be created: {}",
+                path.display(),
+                e
+            );
+            Ok(Vec::new())
+        }
+        SymlinkPolicy::Copy => {
+            if path.starts_with(&resolved) {
+                return Err(report!(Error::Extract))
+                    .attach_printable(format!("path: {}", path.display()))
+                    .attach_printable("can't copy a directory into itself");
+            }
+            progress!(
+                "copying `{}` to `{}`, since the symlink can't be created",
+                resolved.display(),
+                path.display()
+            );
+            let mut copied = Vec::new();
+            copy_path(sink, &resolved, path, options.hash, &mut copied)
+                .change_context(Error::Extract)
+                .attach_printable_lazy(|| format!("path: {}", path.display()))
+                .attach_printable_lazy(|| format!("target: {}", resolved.display()))?;
+            Ok(copied)
+        }
+    }
+}
+
+/// Join the symlink target to the directory of the symlink, removing `.` and `..`,
+/// or `None` if it's absolute or goes above the root
+///
+/// `..` is only allowed at the start. After a name it would go up from where that name
+/// points to, which is somewhere else if it's another symlink
+fn resolve_symlink_target(parent: &Path, target: &Path) -> Option<PathBuf> {
+    let mut resolved = parent.to_path_buf();
+    let mut named = false;
+    for component in target.components() {
+        match component {
+            Component::Normal(name) => {
+                resolved.push(name);
+                named = true;
+            }
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if named || !resolved.pop() {
+                    return None;
+                }
+            }
+            Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    Some(resolved)
+}
+
+/// Copy the file, or the directory recursively, following symlinks
+fn copy_path(
+    sink: &dyn OutputSink,
+    from: &Path,
+    to: &Path,
+    hash: bool,
+    copied: &mut Vec<ExtractedFile>,
+) -> io::Result<()> {
+    if sink.is_dir(from) {
+        sink.create_dir_all(to)?;
+        for entry in sink.read_dir(from)? {
+            let name = entry.file_name().unwrap_or_default();
+            copy_path(sink, &entry, &to.join(name), hash, copied)?;
+        }
+        return Ok(());
+    }
+    let content = sink.read(from)?;
+    sink.write_file(to, &content, true)?;
+    copied.push(ExtractedFile {
+        path: to.to_path_buf(),
+        sha256: hash.then(|| sha256_hex(&content)),
+        size: content.len() as u64,
+    });
+    Ok(())
+}
+
+/// Convert all line endings (LF or CRLF) in the content to the given line ending
+fn normalize_line_endings(content: &[u8], line_ending: LineEnding) -> Vec<u8> {
+    let mut output = Vec::with_capacity(content.len());
+    let mut i = 0;
+    while i < content.len() {
+        let is_line_end = match content[i] {
+            b'\n' => true,
+            b'\r' if content.get(i + 1) == Some(&b'\n') => {
+                i += 1;
+                true
+            }
+            _ => false,
+        };
+        if !is_line_end {
+            output.push(content[i]);
+        } else if line_ending == LineEnding::Crlf {
+            output.extend_from_slice(b"\r\n");
+        } else {
+            output.push(b'\n');
+        }
+        i += 1;
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io::Write};
+
+    use super::*;
+    use crate::sink::memory::{MemoryEntry, MemorySink};
+
+    use crate::{
+        sink::FsSink,
+        test_util::{zip_file, TempDir},
+    };
+
+    /// Extract the archives of three artifacts into the same directory
+    fn extract_merged(dir: &Path, on_conflict: OnConflict) -> Result<(), Error> {
+        let options = ExtractOptions {
+            on_conflict,
+            ..Default::default()
+        };
+        for platform in ["linux", "mac", "win"] {
+            let zip = zip_file(&[
+                (&format!("bin/app-{}", platform), platform.as_bytes()),
+                ("README.md", platform.as_bytes()),
+            ]);
+            extract(&zip, dir, &options, None, "")?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_merged() {
+        let dir = TempDir::new();
+        let out = dir.join("app");
+        extract_merged(&out, OnConflict::Overwrite).unwrap();
+        for platform in ["linux", "mac", "win"] {
+            let content = fs::read_to_string(out.join(format!("bin/app-{}", platform))).unwrap();
+            assert_eq!(content, platform);
+        }
+        assert_eq!(fs::read_to_string(out.join("README.md")).unwrap(), "win");
+
+        let out = dir.join("skip");
+        extract_merged(&out, OnConflict::Skip).unwrap();
+        assert_eq!(fs::read_to_string(out.join("README.md")).unwrap(), "linux");
+        assert!(out.join("bin/app-win").exists());
+
+        let out = dir.join("fail");
+        let err = extract_merged(&out, OnConflict::Fail).unwrap_err();
+        assert!(format!("{:?}", err).contains("see --on-conflict"));
+        // stopped at the second artifact
+        assert!(out.join("bin/app-linux").exists());
+    }
+
+    #[test]
+    fn test_normalize_line_endings() {
+        let content = b"a\r\nb\nc\rd\r\n";
+        assert_eq!(
+            normalize_line_endings(content, LineEnding::Lf),
+            b"a\nb\nc\rd\n"
+        );
+        assert_eq!(
+            normalize_line_endings(content, LineEnding::Crlf),
+            b"a\r\nb\r\nc\rd\r\n"
+        );
+        assert_eq!(normalize_line_endings(b"", LineEnding::Crlf), b"");
+    }
+
+    #[test]
+    fn test_extract_normalize_eol() {
+        let dir = TempDir::new();
+        let zip = zip_file(&[("a.txt", b"x\r\ny\r\n"), ("b.bin", b"x\r\ny\r\n")]);
+        let options = ExtractOptions {
+            on_conflict: OnConflict::Overwrite,
+            normalize_eol: Some(LineEnding::Lf),
+            text_globs: vec!["*.txt".to_string()],
+            ..Default::default()
+        };
+        extract(&zip, &dir.join("out"), &options, None, "").unwrap();
+        assert_eq!(fs::read(dir.join("out/a.txt")).unwrap(), b"x\ny\n");
+        assert_eq!(fs::read(dir.join("out/b.bin")).unwrap(), b"x\r\ny\r\n");
+    }
+
+    #[test]
+    fn test_extract_only_changed() {
+        use std::os::unix::fs::MetadataExt;
+
+        let dir = TempDir::new();
+        let out = dir.join("out");
+        let mut options = ExtractOptions {
+            on_conflict: OnConflict::Fail,
+            hash: true,
+            ..Default::default()
+        };
+        let zip = zip_file(&[("same.txt", b"same"), ("changed.txt", b"old")]);
+        let files = extract(&zip, &out, &options, None, "").unwrap();
+        let same = fs::metadata(out.join("same.txt")).unwrap();
+
+        options.previous_sums = Some(
+            files
+                .into_iter()
+                .map(|file| (file.path, file.sha256.unwrap()))
+                .collect(),
+        );
+        let zip = zip_file(&[("same.txt", b"same"), ("changed.txt", b"new")]);
+        let files = extract(&zip, &out, &options, None, "").unwrap();
+        assert_eq!(files.len(), 2);
+
+        // the unchanged file is not written again
+        let same_after = fs::metadata(out.join("same.txt")).unwrap();
+        assert_eq!(same.ino(), same_after.ino());
+        assert_eq!(same.modified().unwrap(), same_after.modified().unwrap());
+        assert_eq!(fs::read_to_string(out.join("changed.txt")).unwrap(), "new");
+    }
+
+    #[test]
+    fn test_expect_files_in_inner_path() {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        let options = SimpleFileOptions::default();
+        writer.add_directory("dist/", options).unwrap();
+        writer.start_file("dist/app.js", options).unwrap();
+        writer.add_directory("dist/lib/", options).unwrap();
+        writer.start_file("dist/lib/util.js", options).unwrap();
+        writer.start_file("README.md", options).unwrap();
+        let zip = writer.finish().unwrap().into_inner();
+        let archive = ZipArchive::new(Cursor::new(&zip[..])).unwrap();
+
+        // the directories aren't counted
+        assert!(check_file_count(&archive, Some(3), None).is_ok());
+        let dist = Some(Path::new("dist"));
+        assert!(check_file_count(&archive, Some(2), dist).is_ok());
+        let err = check_file_count(&archive, Some(3), dist).unwrap_err();
+        let err = format!("{:?}", err);
+        assert!(
+            err.contains("expected 3 files in the artifact, but found 2"),
+            "{}",
+            err
+        );
+        assert!(err.contains("in --inner-path `dist`"), "{}", err);
+        // like `dist` in `distribution.txt`, a prefix of the name isn't enough
+        assert!(check_file_count(&archive, Some(0), Some(Path::new("dis"))).is_ok());
+    }
+
+    #[test]
+    fn test_expect_files() {
+        let expect = |s: &str| s.parse::<ExpectFiles>().unwrap();
+        assert!("app=x".parse::<ExpectFiles>().is_err());
+        let options = ExtractOptions {
+            on_conflict: OnConflict::Fail,
+            expect_files: vec![expect("2"), expect("docs=1")],
+            ..Default::default()
+        };
+        assert_eq!(options.expected_files_for("app"), Some(2));
+        assert_eq!(options.expected_files_for("docs"), Some(1));
+
+        let dir = TempDir::new();
+        let zip = zip_file(&[("a.txt", b"a"), ("dir/b.txt", b"b")]);
+        extract(&zip, &dir.join("ok"), &options, Some(2), "").unwrap();
+        let err = extract(&zip, &dir.join("bad"), &options, Some(1), "").unwrap_err();
+        assert!(format!("{:?}", err).contains("expected 1 files in the artifact, but found 2"));
+        // nothing is extracted
+        assert!(!dir.join("bad").exists());
+    }
+
+    #[test]
+    fn test_extract_newest() {
+        let dir = TempDir::new();
+        let out = dir.join("out");
+        std::fs::create_dir_all(&out).unwrap();
+        std::fs::write(out.join("stale.txt"), "stale").unwrap();
+        let options = ExtractOptions {
+            on_conflict: OnConflict::Newest,
+            ..Default::default()
+        };
+        let extract_from = |created_at: &str| {
+            let zip = zip_file(&[("a.txt", created_at.as_bytes()), ("stale.txt", b"new")]);
+            extract(&zip, &out, &options, None, created_at).unwrap();
+        };
+        // the newer artifact finished downloading first
+        extract_from("2024-01-02T00:00:00Z");
+        extract_from("2024-01-01T00:00:00Z");
+        assert_eq!(
+            fs::read_to_string(out.join("a.txt")).unwrap(),
+            "2024-01-02T00:00:00Z"
+        );
+        extract_from("2024-01-03T00:00:00Z");
+        assert_eq!(
+            fs::read_to_string(out.join("a.txt")).unwrap(),
+            "2024-01-03T00:00:00Z"
+        );
+        // not from this pull
+        assert_eq!(fs::read_to_string(out.join("stale.txt")).unwrap(), "new");
+    }
+
+    #[test]
+    fn test_case_collision() {
+        let dir = TempDir::new();
+        let extract_with = |on_case_collision: OnCaseCollision, out: &Path| {
+            let options = ExtractOptions {
+                on_case_collision,
+                ..Default::default()
+            };
+            // from two artifacts merged into the same directory
+            extract(
+                &zip_file(&[("docs/README.md", b"1")]),
+                out,
+                &options,
+                None,
+                "",
+            )?;
+            extract(
+                &zip_file(&[("docs/readme.md", b"2")]),
+                out,
+                &options,
+                None,
+                "",
+            )
+        };
+
+        let out = dir.join("fail");
+        let err = extract_with(OnCaseCollision::Fail, &out).unwrap_err();
+        assert!(format!("{:?}", err).contains("only differ in case"));
+
+        let out = dir.join("skip");
+        extract_with(OnCaseCollision::Skip, &out).unwrap();
+        assert_eq!(fs::read_to_string(out.join("docs/README.md")).unwrap(), "1");
+        assert!(!out.join("docs/readme.md").exists());
+
+        let out = dir.join("rename");
+        extract_with(OnCaseCollision::Rename, &out).unwrap();
+        assert_eq!(fs::read_to_string(out.join("docs/README.md")).unwrap(), "1");
+        assert_eq!(
+            fs::read_to_string(out.join("docs/readme~1.md")).unwrap(),
+            "2"
+        );
+    }
+
+    #[test]
+    fn test_parse_mode() {
+        assert_eq!(parse_mode("644"), Ok(0o644));
+        assert_eq!(parse_mode("0o755"), Ok(0o755));
+        assert!(parse_mode("999").is_err());
+        assert!(parse_mode("17777").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_extract_modes() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = TempDir::new();
+        let out = dir.join("out");
+        let options = ExtractOptions {
+            file_mode: Some(0o600),
+            // without write permission, so it only works if set after the files are written
+            dir_mode: Some(0o500),
+            ..Default::default()
+        };
+        let zip = zip_file(&[("a.txt", b"1"), ("sub/dir/b.txt", b"2")]);
+        extract(&zip, &out, &options, None, "").unwrap();
+
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o7777;
+        assert_eq!(mode(&out.join("a.txt")), 0o600);
+        assert_eq!(mode(&out.join("sub/dir/b.txt")), 0o600);
+        for path in [&out, &out.join("sub"), &out.join("sub/dir")] {
+            assert_eq!(mode(path), 0o500);
+        }
+        // so the temp dir can be removed
+        for path in [&out, &out.join("sub"), &out.join("sub/dir")] {
+            fs::set_permissions(path, fs::Permissions::from_mode(0o755)).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_extracted_size() {
+        let dir = TempDir::new();
+        let zip = zip_file(&[("a.txt", b"12345"), ("b/c.txt", b"")]);
+        let files = extract(&zip, &dir.join("out"), &Default::default(), None, "").unwrap();
+        let sizes = files.iter().map(|file| file.size).collect::<Vec<_>>();
+        assert_eq!(sizes, [5, 0]);
+    }
+
+    #[test]
+    fn test_resolve_symlink_target() {
+        let parent = Path::new("out/app/bin");
+        assert_eq!(
+            resolve_symlink_target(parent, Path::new("../lib/./a.so")),
+            Some(PathBuf::from("out/app/lib/a.so"))
+        );
+        assert_eq!(
+            resolve_symlink_target(parent, Path::new("/etc/passwd")),
+            None
+        );
+        assert_eq!(
+            resolve_symlink_target(Path::new(""), Path::new("../a")),
+            None
+        );
+        // `up` could be a symlink to somewhere higher
+        assert_eq!(
+            resolve_symlink_target(parent, Path::new("up/../a.so")),
+            None
+        );
+    }
+
+    fn zip_with_symlink(name: &str, target: &str) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        let options = zip::write::SimpleFileOptions::default();
+        writer.start_file("lib/a.so", options).unwrap();
+        writer.write_all(b"library").unwrap();
+        writer.add_symlink(name, target, options).unwrap();
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_extract_symlink() {
+        let dir = TempDir::new();
+        let out = dir.join("out");
+        let zip = zip_with_symlink("bin/a.so", "../lib/a.so");
+        extract(&zip, &out, &Default::default(), None, "").unwrap();
+        let link = out.join("bin/a.so");
+        assert!(fs::symlink_metadata(&link).unwrap().is_symlink());
+        assert_eq!(fs::read_link(&link).unwrap(), Path::new("../lib/a.so"));
+        assert_eq!(fs::read_to_string(&link).unwrap(), "library");
+
+        let zip = zip_with_symlink("bin/passwd", "../../../etc/passwd");
+        let err = extract(&zip, &dir.join("escape"), &Default::default(), None, "").unwrap_err();
+        assert!(format!("{:?}", err).contains("symlink points outside of the artifact"));
+        assert!(!dir.join("escape/bin/passwd").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_extract_chained_symlink() {
+        let dir = TempDir::new();
+        let out = dir.join("out");
+        // `a/b/up` points to `out`, so `a/b/up/..` would be above it
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        let options = zip::write::SimpleFileOptions::default();
+        writer.add_symlink("a/b/up", "../..", options).unwrap();
+        writer.add_symlink("escape", "a/b/up/..", options).unwrap();
+        let zip = writer.finish().unwrap().into_inner();
+        let err = extract(&zip, &out, &Default::default(), None, "").unwrap_err();
+        assert!(format!("{:?}", err).contains("symlink points outside of the artifact"));
+        assert!(!out.join("escape").exists());
+
+        // the same with the symlinks from two artifacts
+        let here = zip_with_symlink("here", ".");
+        extract(&here, &out, &Default::default(), None, "").unwrap();
+        let zip = zip_with_symlink("here/sub/up", "../..");
+        let err = extract(&zip, &out, &Default::default(), None, "").unwrap_err();
+        let err = format!("{:?}", err);
+        assert!(err.contains("which is a symlink"), "{}", err);
+        assert!(!out.join("sub/up").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_overwrite_symlink() {
+        let dir = TempDir::new();
+        let out = dir.join("out");
+        let zip = zip_with_symlink("latest.so", "lib/a.so");
+        extract(&zip, &out, &Default::default(), None, "").unwrap();
+        let options = ExtractOptions {
+            on_conflict: OnConflict::Overwrite,
+            ..Default::default()
+        };
+        let zip = zip_file(&[("latest.so", b"replaced")]);
+        extract(&zip, &out, &options, None, "").unwrap();
+        // the symlink is replaced, not written through
+        assert!(!fs::symlink_metadata(out.join("latest.so"))
+            .unwrap()
+            .is_symlink());
+        assert_eq!(fs::read_to_string(out.join("lib/a.so")).unwrap(), "library");
+    }
+
+    #[test]
+    fn test_copy_symlink_to_memory() {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        let options = SimpleFileOptions::default();
+        writer.start_file("lib/a.so", options).unwrap();
+        writer.write_all(b"library").unwrap();
+        writer
+            .add_symlink("bin/a.so", "../lib/a.so", options)
+            .unwrap();
+        writer.add_symlink("all", "lib", options).unwrap();
+        let bytes = writer.finish().unwrap().into_inner();
+
+        let sink = Arc::new(MemorySink::without_symlinks());
+        let options = ExtractOptions {
+            symlink_policy: SymlinkPolicy::Copy,
+            sink: Some(sink.clone()),
+            ..Default::default()
+        };
+        let out = Path::new("out");
+        let files = extract(&bytes, out, &options, None, "").unwrap();
+        let paths = files
+            .iter()
+            .map(|file| file.path.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            [
+                PathBuf::from("out/lib/a.so"),
+                PathBuf::from("out/bin/a.so"),
+                PathBuf::from("out/all/a.so")
+            ]
+        );
+        let entries = sink.entries();
+        assert_eq!(entries[Path::new("out/bin/a.so")], file("library", None));
+        assert_eq!(entries[Path::new("out/all")], MemoryEntry::Dir);
+        assert_eq!(entries[Path::new("out/all/a.so")], file("library", None));
+        assert!(!entries
+            .values()
+            .any(|entry| matches!(entry, MemoryEntry::Symlink(_))));
+    }
+
+    #[test]
+    fn test_save_with_index() {
+        let dir = TempDir::new();
+        let dir = dir.join("out");
+        let zip = zip_file(&[("bin/app", b"app"), ("README.md", b"readme")]);
+        let options = ExtractOptions {
+            zip_index: true,
+            hash: true,
+            ..Default::default()
+        };
+        let saved = save_with_index(&zip, &dir, "app", &options, Some(2)).unwrap();
+        assert_eq!(fs::read(dir.join("app.zip")).unwrap(), zip);
+        assert_eq!(
+            fs::read_to_string(dir.join("app.index.txt")).unwrap(),
+            "bin/app\nREADME.md\n"
+        );
+        assert_eq!(saved.len(), 2);
+        assert_eq!(saved[0].sha256.as_deref(), Some(sha256_hex(&zip).as_str()));
+        assert!(!dir.join("bin").exists());
+
+        let err = save_with_index(&zip, &dir, "app", &options, Some(3)).unwrap_err();
+        assert!(format!("{:?}", err).contains("expected 3 files"));
+    }
+
+    #[test]
+    fn test_zip_bomb_limits() {
+        let dir = TempDir::new();
+        let zeros = vec![0; 1 << 20];
+        let bomb = zip_file(&[("a.bin", &zeros), ("b.bin", &zeros)]);
+        // compresses by more than 100 times
+        assert!(bomb.len() * 100 < 2 * zeros.len());
+
+        let options = ExtractOptions {
+            max_uncompressed: Some(3 << 19),
+            ..Default::default()
+        };
+        let err = extract(&bomb, &dir.join("bomb"), &options, None, "").unwrap_err();
+        let err = format!("{:?}", err);
+        assert!(err.contains("possible zip bomb"), "{}", err);
+        assert!(err.contains("--max-uncompressed"), "{}", err);
+        // the entry that went over the limit isn't written
+        assert!(!dir.join("bomb/b.bin").exists());
+
+        let options = ExtractOptions {
+            max_uncompressed: Some(2 << 20),
+            ..Default::default()
+        };
+        extract(&bomb, &dir.join("ok"), &options, None, "").unwrap();
+        assert_eq!(fs::metadata(dir.join("ok/b.bin")).unwrap().len(), 1 << 20);
+
+        let options = ExtractOptions {
+            max_entries: Some(1),
+            ..Default::default()
+        };
+        let err = extract(&bomb, &dir.join("entries"), &options, None, "").unwrap_err();
+        assert!(format!("{:?}", err).contains("2 entries, more than --max-entries 1"));
+        assert!(!dir.join("entries").exists());
+    }
+
+    #[test]
+    fn test_save_normalized_zip() {
+        let dir = TempDir::new();
+        let zip_with = |names: &[&str], time: zip::DateTime| {
+            let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+            for name in names {
+                let options = SimpleFileOptions::default()
+                    .compression_method(CompressionMethod::Stored)
+                    .last_modified_time(time)
+                    .unix_permissions(0o600);
+                writer.start_file(*name, options).unwrap();
+                writer.write_all(name.as_bytes()).unwrap();
+            }
+            writer.finish().unwrap().into_inner()
+        };
+        let time = zip::DateTime::from_date_and_time(2024, 1, 2, 3, 4, 6).unwrap();
+        let a = zip_with(&["b.txt", "a/c.txt"], zip::DateTime::default());
+        let b = zip_with(&["a/c.txt", "b.txt"], time);
+        assert_ne!(a, b);
+
+        // the directory is created if needed
+        let zip_dir = dir.join("out.normalized");
+        let sha_a = save_normalized_zip(&a, &zip_dir, "a", &FsSink).unwrap();
+        let sha_b = save_normalized_zip(&b, &zip_dir, "b", &FsSink).unwrap();
+        assert_eq!(sha_a, sha_b);
+        let normalized = fs::read(zip_dir.join("a.zip")).unwrap();
+        assert_eq!(sha256_hex(&normalized), sha_a);
+        let archive = ZipArchive::new(Cursor::new(normalized.clone())).unwrap();
+        assert_eq!(
+            archive.file_names().collect::<Vec<_>>(),
+            ["a/c.txt", "b.txt"]
+        );
+        // an existing archive is not overwritten
+        assert!(save_normalized_zip(&b, &zip_dir, "a", &FsSink).is_err());
+        assert_eq!(fs::read(zip_dir.join("a.zip")).unwrap(), normalized);
+    }
+
+    #[test]
+    fn test_extract_inner_path() {
+        let dir = TempDir::new();
+        let zip = zip_file(&[
+            ("dist/app.js", b"app"),
+            ("dist/lib/util.js", b"util"),
+            ("distribution.txt", b"other"),
+            ("README.md", b"readme"),
+        ]);
+        let options = ExtractOptions {
+            inner_path: Some(PathBuf::from("dist")),
+            ..Default::default()
+        };
+        let out = dir.join("out");
+        extract(&zip, &out, &options, None, "").unwrap();
+        assert_eq!(fs::read_to_string(out.join("app.js")).unwrap(), "app");
+        assert_eq!(fs::read_to_string(out.join("lib/util.js")).unwrap(), "util");
+        assert!(!out.join("dist").exists());
+        assert!(!out.join("distribution.txt").exists());
+        assert!(!out.join("README.md").exists());
+    }
+
+    #[test]
+    fn test_extract_to_sink() {
+        /// Write to the filesystem, and record what was written
+        #[derive(Debug, Default)]
+        struct RecordingSink {
+            written: Mutex<Vec<PathBuf>>,
+        }
+        impl OutputSink for RecordingSink {
+            fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+                FsSink.create_dir_all(path)
+            }
+            fn write_file(&self, path: &Path, content: &[u8], overwrite: bool) -> io::Result<()> {
+                self.written.lock().unwrap().push(path.to_path_buf());
+                FsSink.write_file(path, content, overwrite)
+            }
+            fn set_mode(&self, path: &Path, mode: u32) -> io::Result<()> {
+                FsSink.set_mode(path, mode)
+            }
+            fn exists(&self, path: &Path) -> bool {
+                FsSink.exists(path)
+            }
+            fn is_symlink(&self, path: &Path) -> bool {
+                FsSink.is_symlink(path)
+            }
+            fn is_dir(&self, path: &Path) -> bool {
+                FsSink.is_dir(path)
+            }
+            fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+                FsSink.read(path)
+            }
+            fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+                FsSink.read_dir(path)
+            }
+            fn remove(&self, path: &Path) -> io::Result<()> {
+                FsSink.remove(path)
+            }
+            fn symlink(&self, target: &Path, path: &Path, is_dir: bool) -> io::Result<()> {
+                FsSink.symlink(target, path, is_dir)
+            }
+        }
+
+        let dir = TempDir::new();
+        let sink = Arc::new(RecordingSink::default());
+        let options = ExtractOptions {
+            sink: Some(Arc::clone(&sink) as Arc<dyn OutputSink>),
+            on_conflict: OnConflict::Fail,
+            ..Default::default()
+        };
+        let zip = zip_file(&[("a.txt", b"a"), ("sub/b.txt", b"b")]);
+        let out = dir.join("out");
+        extract(&zip, &out, &options, None, "").unwrap();
+        assert_eq!(
+            *sink.written.lock().unwrap(),
+            [out.join("a.txt"), out.join("sub/b.txt")]
+        );
+        // existing files go through the sink too, which reports the conflict
+        let err = extract(&zip, &out, &options, None, "").unwrap_err();
+        assert!(format!("{:?}", err).contains("a.txt"), "{:?}", err);
+        assert_eq!(sink.written.lock().unwrap().len(), 3);
+    }
+
+    fn file(content: &str, mode: Option<u32>) -> MemoryEntry {
+        MemoryEntry::File {
+            content: content.as_bytes().to_vec(),
+            mode,
+        }
+    }
+
+    #[test]
+    fn test_extract_to_memory() {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        let options = SimpleFileOptions::default();
+        writer.add_directory("dist/", options).unwrap();
+        writer.start_file("dist/app.txt", options).unwrap();
+        writer.write_all(b"app").unwrap();
+        writer.start_file("readme.txt", options).unwrap();
+        writer.write_all(b"line\r\n").unwrap();
+        writer
+            .add_symlink("dist/latest.txt", "app.txt", options)
+            .unwrap();
+        let bytes = writer.finish().unwrap().into_inner();
+
+        let sink = Arc::new(MemorySink::default());
+        let options = ExtractOptions {
+            max_entries: Some(10),
+            max_uncompressed: Some(1024),
+            normalize_eol: Some(LineEnding::Lf),
+            text_globs: vec!["*.txt".to_string()],
+            file_mode: Some(0o644),
+            sink: Some(sink.clone()),
+            ..Default::default()
+        };
+        let out = Path::new("out");
+        let files = extract(&bytes, out, &options, Some(3), "").unwrap();
+        let paths = files
+            .iter()
+            .map(|file| file.path.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            [
+                PathBuf::from("out/dist/app.txt"),
+                PathBuf::from("out/readme.txt")
+            ]
+        );
+
+        let entries = sink.entries();
+        assert_eq!(entries[Path::new("out")], MemoryEntry::Dir);
+        assert_eq!(entries[Path::new("out/dist")], MemoryEntry::Dir);
+        assert_eq!(
+            entries[Path::new("out/dist/app.txt")],
+            file("app", Some(0o644))
+        );
+        assert_eq!(
+            entries[Path::new("out/readme.txt")],
+            file("line\n", Some(0o644))
+        );
+        assert_eq!(
+            entries[Path::new("out/dist/latest.txt")],
+            MemoryEntry::Symlink(PathBuf::from("app.txt"))
+        );
+        assert_eq!(entries.len(), 5);
+
+        // extracting again fails with --on-conflict fail
+        let options = ExtractOptions {
+            on_conflict: OnConflict::Fail,
+            ..options
+        };
+        assert!(extract(&bytes, out, &options, None, "").is_err());
+    }
+}
@@ -0,0 +1,145 @@
+//! Making the output read-only after a pull, for --freeze
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use error_stack::{Result, ResultExt};
+
+use crate::{checksum::SHA256SUMS, manifest::Manifest, retention, Error};
+
+/// Files magnesis writes in the output directory, besides the artifacts
+pub const METADATA_FILES: &[&str] = &[
+    Manifest::FILE_NAME,
+    SHA256SUMS,
+    "metadata.json",
+    ".gitignore",
+    retention::MARKER,
+];
+
+/// Remove write permissions from everything in the output
+///
+/// If `keep_metadata` is true, the metadata files and the output directory
+/// itself stay writable, so they can be updated by the next pull
+pub async fn freeze(output: PathBuf, keep_metadata: bool) -> Result<(), Error> {
+    tokio::task::spawn_blocking(move || {
+        let keep = |path: &Path| {
+            keep_metadata
+                && (path == output
+                    || path.parent() == Some(&output)
+                        && path
+                            .file_name()
+                            .and_then(|name| name.to_str())
+                            .is_some_and(|name| METADATA_FILES.contains(&name)))
+        };
+        walk(&output, &mut |path| {
+            if keep(path) {
+                return Ok(());
+            }
+            set_writable(path, false)
+        })
+        .attach_printable_lazy(|| format!("path: {}", output.display()))
+    })
+    .await
+    .change_context(Error::Freeze)?
+    .change_context(Error::Freeze)
+}
+
+/// Make everything in a frozen output writable again by the owner, so it can be replaced
+///
+/// Files with other hard links, like to the --shared-cache, are left as they are,
+/// since their permissions are shared with the links
+pub async fn thaw(output: PathBuf) -> Result<(), Error> {
+    tokio::task::spawn_blocking(move || {
+        walk(&output, &mut |path| {
+            if is_hard_linked(path)? {
+                return Ok(());
+            }
+            set_writable(path, true)
+        })
+        .attach_printable_lazy(|| format!("path: {}", output.display()))
+    })
+    .await
+    .change_context(Error::Freeze)?
+    .change_context(Error::Freeze)
+}
+
+/// Call `f` on the path and everything under it. Symlinks are not followed
+fn walk(path: &Path, f: &mut impl FnMut(&Path) -> io::Result<()>) -> io::Result<()> {
+    let metadata = fs::symlink_metadata(path)?;
+    if metadata.is_symlink() {
+        return Ok(());
+    }
+    f(path)?;
+    if metadata.is_dir() {
+        for entry in fs::read_dir(path)? {
+            walk(&entry?.path(), f)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn is_hard_linked(path: &Path) -> io::Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+    let metadata = fs::symlink_metadata(path)?;
+    Ok(metadata.is_file() && metadata.nlink() > 1)
+}
+
+#[cfg(not(unix))]
+fn is_hard_linked(_path: &Path) -> io::Result<bool> {
+    Ok(false)
+}
+
+fn set_writable(path: &Path, writable: bool) -> io::Result<()> {
+    let mut permissions = fs::metadata(path)?.permissions();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = permissions.mode();
+        permissions.set_mode(if writable {
+            mode | 0o200
+        } else {
+            mode & !0o222
+        });
+    }
+    #[cfg(not(unix))]
+    permissions.set_readonly(!writable);
+    fs::set_permissions(path, permissions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_util::TempDir;
+
+    fn is_readonly(path: &Path) -> bool {
+        fs::metadata(path).unwrap().permissions().readonly()
+    }
+
+    #[tokio::test]
+    async fn test_freeze_thaw() {
+        let dir = TempDir::new();
+        let output = dir.join("out");
+        fs::create_dir_all(output.join("app")).unwrap();
+        fs::write(output.join("app/a.txt"), "1").unwrap();
+        fs::write(output.join(Manifest::FILE_NAME), "{}").unwrap();
+
+        freeze(output.clone(), true).await.unwrap();
+        assert!(is_readonly(&output.join("app")));
+        assert!(is_readonly(&output.join("app/a.txt")));
+        assert!(!is_readonly(&output));
+        assert!(!is_readonly(&output.join(Manifest::FILE_NAME)));
+
+        freeze(output.clone(), false).await.unwrap();
+        assert!(is_readonly(&output));
+        assert!(is_readonly(&output.join(Manifest::FILE_NAME)));
+
+        thaw(output.clone()).await.unwrap();
+        for path in ["", "app", "app/a.txt", Manifest::FILE_NAME] {
+            assert!(!is_readonly(&output.join(path)));
+        }
+    }
+}
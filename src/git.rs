@@ -0,0 +1,127 @@
+use std::{process::Output, time::Duration};
+
+use error_stack::{report, Result, ResultExt};
+use tokio::process::Command;
+
+use crate::Error;
+
+/// Check if the revision is already a full commit hash that can be used as-is
+pub fn is_full_sha(rev: &str) -> bool {
+    rev.len() == 40 && rev.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Run git with the arguments, killing it if it doesn't finish within the timeout
+///
+/// The repo and the revision are resolved at the same time. The commands used only read
+/// the repo and don't take the index lock, and optional locks are turned off so
+/// they can't wait on each other (or on another git process), even if more commands are added
+async fn run_git(args: &[&str], timeout: Duration) -> Result<Output, Error> {
+    let output = Command::new("git")
+        .args(args)
+        .env("GIT_OPTIONAL_LOCKS", "0")
+        .kill_on_drop(true)
+        .output();
+    match tokio::time::timeout(timeout, output).await {
+        Ok(output) => output.change_context(Error::Command),
+        Err(_) => Err(report!(Error::Command))
+            .attach_printable(format!(
+                "`git {}` timed out after {}s",
+                args.join(" "),
+                timeout.as_secs()
+            ))
+            .attach_printable("git could be waiting for a lock or a prompt, see --git-timeout"),
+    }
+}
+
+pub async fn get_rev(rev: String, timeout: Duration) -> Result<String, Error> {
+    if is_full_sha(&rev) {
+        return Ok(rev);
+    }
+
+    let output = run_git(&["rev-parse", &rev], timeout).await?;
+    if !output.status.success() {
+        return Err(report!(Error::Command)).attach_printable(format!("status: {}", output.status));
+    }
+    let decoded = std::str::from_utf8(&output.stdout).change_context(Error::Command)?;
+    Ok(decoded.trim().to_string())
+}
+
+/// Check that the SHA is a commit object in the local repo
+pub async fn verify_commit(sha: &str, timeout: Duration) -> Result<(), Error> {
+    let output = run_git(&["cat-file", "-e", &format!("{}^{{commit}}", sha)], timeout).await?;
+    if !output.status.success() {
+        return Err(report!(Error::Rev))
+            .attach_printable(format!("`{}` is not a commit in the local repo", sha));
+    }
+    Ok(())
+}
+
+pub async fn get_repo(timeout: Duration) -> Result<String, Error> {
+    let output = run_git(&["remote", "get-url", "origin"], timeout).await?;
+    if !output.status.success() {
+        return Err(report!(Error::Command)).attach_printable(format!("status: {}", output.status));
+    }
+    let decoded = std::str::from_utf8(&output.stdout)
+        .change_context(Error::Command)?
+        .trim();
+
+    let repo = if let Some(repo) = decoded.strip_prefix("http://github.com/") {
+        repo
+    } else if let Some(repo) = decoded.strip_prefix("https://github.com/") {
+        repo
+    } else if let Some(repo) = decoded.strip_prefix("git@github.com:") {
+        repo
+    } else {
+        return Err(report!(Error::Repo))
+            .attach_printable(format!("failed to get repo from: {}", decoded));
+    };
+
+    Ok(repo.strip_suffix(".git").unwrap_or(repo).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_full_sha() {
+        assert!(is_full_sha("0123456789abcdef0123456789abcdef01234567"));
+        assert!(!is_full_sha("0123456"));
+        assert!(!is_full_sha("main"));
+        assert!(!is_full_sha("0123456789abcdef0123456789abcdef0123456g"));
+    }
+
+    const TIMEOUT: Duration = Duration::from_secs(30);
+
+    #[tokio::test]
+    async fn test_verify_commit() {
+        let head = get_rev("HEAD".to_string(), TIMEOUT).await.unwrap();
+        verify_commit(&head, TIMEOUT).await.unwrap();
+
+        let err = verify_commit("0000000000000000000000000000000000000000", TIMEOUT)
+            .await
+            .unwrap_err();
+        assert!(format!("{:?}", err).contains("is not a commit in the local repo"));
+    }
+
+    #[tokio::test]
+    async fn test_run_git_timeout() {
+        let output = run_git(&["--version"], TIMEOUT).await.unwrap();
+        assert!(output.status.success());
+
+        let args = ["-c", "alias.hang=!sleep 10", "hang"];
+        let err = run_git(&args, Duration::from_millis(100))
+            .await
+            .unwrap_err();
+        assert!(format!("{:?}", err).contains("`git -c alias.hang=!sleep 10 hang` timed out"));
+    }
+
+    #[tokio::test]
+    async fn test_run_git_without_optional_locks() {
+        let output = run_git(&["-c", "alias.env=!env", "env"], TIMEOUT)
+            .await
+            .unwrap();
+        let env = String::from_utf8(output.stdout).unwrap();
+        assert!(env.lines().any(|line| line == "GIT_OPTIONAL_LOCKS=0"));
+    }
+}
@@ -0,0 +1,167 @@
+use std::path::{Path, PathBuf};
+
+use tokio::fs;
+
+use crate::{atomic_file, checksum::sha256_hex};
+
+/// Repo and HEAD commit derived from git, cached in the git directory
+/// to avoid running git on every invocation
+///
+/// The cache is keyed by the content of HEAD, the commit it points to and
+/// the git config, so it is invalidated when any of them change.
+/// It is read directly from the files, without running git.
+#[derive(Debug)]
+pub struct GitCache {
+    path: PathBuf,
+    key: String,
+    pub repo: Option<String>,
+    pub head: Option<String>,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct CacheFile {
+    key: String,
+    repo: Option<String>,
+    head: Option<String>,
+}
+
+impl GitCache {
+    const FILE_NAME: &'static str = "magnesis-cache.json";
+
+    /// Load the cache for the git repo in the current directory
+    ///
+    /// Returns `None` if the git directory or the cache key cannot be determined,
+    /// in which case the cache is not used
+    pub async fn load() -> Option<Self> {
+        // git might not be using the directory we find
+        if std::env::var_os("GIT_DIR").is_some() {
+            return None;
+        }
+        Self::load_in(&std::env::current_dir().ok()?).await
+    }
+
+    /// Load the cache for the git repo containing the directory
+    async fn load_in(dir: &Path) -> Option<Self> {
+        let git_dir = find_git_dir(dir).await?;
+        let key = cache_key(&git_dir).await?;
+        let path = git_dir.join(Self::FILE_NAME);
+        let cached = match fs::read(&path).await {
+            Ok(bytes) => serde_json::from_slice::<CacheFile>(&bytes)
+                .ok()
+                .filter(|cached| cached.key == key),
+            Err(_) => None,
+        };
+        let (repo, head) = match cached {
+            Some(cached) => (cached.repo, cached.head),
+            None => (None, None),
+        };
+        Some(Self {
+            path,
+            key,
+            repo,
+            head,
+        })
+    }
+
+    /// Save the cache if it can be written. If not, the next run just asks git again
+    pub async fn save(&self) {
+        let file = CacheFile {
+            key: self.key.clone(),
+            repo: self.repo.clone(),
+            head: self.head.clone(),
+        };
+        let Ok(json) = serde_json::to_vec(&file) else {
+            return;
+        };
+        // revisions pulled at the same time save it at the same time
+        let _ = atomic_file::write(&self.path, json).await;
+    }
+}
+
+/// Find the git directory of the repo containing the directory
+async fn find_git_dir(start: &Path) -> Option<PathBuf> {
+    for dir in start.ancestors() {
+        let dot_git = dir.join(".git");
+        if dot_git.is_dir() {
+            return Some(dot_git);
+        }
+        if dot_git.is_file() {
+            // worktrees and submodules have a `.git` file pointing to the git directory
+            let content = fs::read_to_string(&dot_git).await.ok()?;
+            let git_dir = content.trim().strip_prefix("gitdir:")?.trim();
+            return Some(dir.join(git_dir));
+        }
+    }
+    None
+}
+
+/// Compute the cache key from HEAD, the commit it points to, and the config
+async fn cache_key(git_dir: &Path) -> Option<String> {
+    // worktrees share refs and config with the main git directory
+    let common_dir = match fs::read_to_string(git_dir.join("commondir")).await {
+        Ok(common_dir) => git_dir.join(common_dir.trim()),
+        Err(_) => git_dir.to_path_buf(),
+    };
+    let head = fs::read_to_string(git_dir.join("HEAD")).await.ok()?;
+    let commit = match head.trim().strip_prefix("ref:") {
+        Some(ref_name) => read_ref(git_dir, &common_dir, ref_name.trim()).await?,
+        None => head.trim().to_string(),
+    };
+    let config = fs::read(common_dir.join("config")).await.ok()?;
+
+    let mut key = format!("{}\n{}\n", head.trim(), commit).into_bytes();
+    key.extend_from_slice(&config);
+    Some(sha256_hex(&key))
+}
+
+/// Read the commit the ref points to, from the loose ref or packed-refs
+async fn read_ref(git_dir: &Path, common_dir: &Path, ref_name: &str) -> Option<String> {
+    for dir in [git_dir, common_dir] {
+        if let Ok(commit) = fs::read_to_string(dir.join(ref_name)).await {
+            return Some(commit.trim().to_string());
+        }
+    }
+    let packed_refs = fs::read_to_string(common_dir.join("packed-refs"))
+        .await
+        .ok()?;
+    packed_refs.lines().find_map(|line| {
+        let (commit, name) = line.split_once(' ')?;
+        (name == ref_name).then(|| commit.to_string())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_util::TempDir;
+
+    #[tokio::test]
+    async fn test_cached_repo_avoids_git() {
+        // not a real repo, so anything not from the cache would need git and fail
+        let dir = TempDir::new();
+        let git_dir = dir.join(".git");
+        std::fs::create_dir_all(git_dir.join("refs/heads")).unwrap();
+        std::fs::write(git_dir.join("HEAD"), "ref: refs/heads/main\n").unwrap();
+        std::fs::write(git_dir.join("refs/heads/main"), "aaaa\n").unwrap();
+        std::fs::write(git_dir.join("config"), "[core]\n").unwrap();
+        let work_dir = dir.join("src");
+        std::fs::create_dir_all(&work_dir).unwrap();
+
+        let mut cache = GitCache::load_in(&work_dir).await.unwrap();
+        assert_eq!(cache.repo, None);
+        cache.repo = Some("foo/bar".to_string());
+        cache.head = Some("aaaa".to_string());
+        cache.save().await;
+
+        let cache = GitCache::load_in(&work_dir).await.unwrap();
+        assert_eq!(cache.repo.as_deref(), Some("foo/bar"));
+        assert_eq!(cache.head.as_deref(), Some("aaaa"));
+
+        // a new commit invalidates the cache
+        std::fs::write(git_dir.join("refs/heads/main"), "bbbb\n").unwrap();
+        let cache = GitCache::load_in(&work_dir).await.unwrap();
+        assert_eq!(cache.repo, None);
+        assert_eq!(cache.head, None);
+    }
+}
@@ -0,0 +1,1248 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex, Once,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use error_stack::{report, Result, ResultExt};
+use reqwest::{
+    header::{self, HeaderMap, HeaderName, HeaderValue},
+    redirect::Policy,
+    tls::TlsInfo,
+    Certificate, Client, RequestBuilder, Response, StatusCode, Url,
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    checksum::sha256_hex,
+    git::is_full_sha,
+    output::{progress, warning},
+    retry::{redact_url, RetryAfter, RetryPolicy, Status},
+    Error,
+};
+
+/// API of github.com, used if there is no --api-url
+pub const DEFAULT_API_URL: &str = "https://api.github.com";
+
+/// Client for calling the GitHub API
+pub struct Api {
+    client: Client,
+    /// Same as `client`, but doesn't follow redirects, for getting signed download URLs
+    no_redirect_client: Client,
+    /// Client without the token, for downloading from signed URLs
+    /// and checking --pin-cert before the token is sent
+    storage_client: Client,
+    token: String,
+    /// Base URL of the API, without a trailing `/`
+    api_url: Url,
+    options: ApiOptions,
+    /// Lowest rate limit seen in the response headers, for --print-rate-limit
+    rate_limit: Mutex<Option<RateLimit>>,
+}
+
+#[derive(Debug, Default)]
+pub struct ApiOptions {
+    /// Base URL of the API, for --api-url. The API of github.com if not set
+    pub api_url: Option<String>,
+    /// Directory to save raw response bodies to, for --dump-raw
+    pub dump_raw: Option<PathBuf>,
+    /// Retries for listing and other API calls
+    pub list_retry: RetryPolicy,
+    /// Retries for downloading artifacts
+    pub download_retry: RetryPolicy,
+    /// Accept invalid TLS certificates, for --insecure
+    pub insecure: bool,
+    /// Extra root certificate (PEM or DER) to trust
+    pub ca_cert: Option<PathBuf>,
+    /// Fail if fields that have defaults are missing from responses, for --strict-schema
+    pub strict_schema: bool,
+    /// Lowercase hex SHA-256 of the API server's certificate, for --pin-cert
+    pub pin_cert: Option<String>,
+    /// Stops requests and waits to retry them, on Ctrl-C
+    pub cancel: CancellationToken,
+}
+
+/// Response type with fields that can be missing from the API response
+///
+/// Missing fields get a default value, unless --strict-schema is used
+pub trait Schema {
+    /// Paths of the fields that were missing from the response
+    fn missing_fields(&self) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+impl Api {
+    pub fn new(token: String, options: ApiOptions) -> Result<Self, Error> {
+        let api_url = options.api_url.as_deref().unwrap_or(DEFAULT_API_URL);
+        let api_url = Url::parse(api_url.trim_end_matches('/'))
+            .change_context(Error::RequestClient)
+            .attach_printable_lazy(|| format!("invalid API URL `{}`", api_url))?;
+        let mut headers = HeaderMap::new();
+        let authorization = HeaderValue::from_str(&format!("Bearer {}", token))
+            .change_context(Error::InvalidToken)?;
+        headers.insert("Authorization", authorization);
+        headers.insert(
+            "User-Agent",
+            HeaderValue::from_name(HeaderName::from_static("reqwest")),
+        );
+        let certificate = match &options.ca_cert {
+            Some(path) => Some(
+                load_certificate(path)
+                    .attach_printable_lazy(|| format!("path: {}", path.display()))?,
+            ),
+            None => None,
+        };
+        if options.insecure {
+            warning!("--insecure is used, TLS certificates will NOT be verified!");
+            eprintln!("  only use this for testing with servers you trust");
+        }
+        let build = |headers: HeaderMap, redirect: Policy| {
+            let mut builder = Client::builder()
+                .default_headers(headers)
+                .redirect(redirect)
+                .danger_accept_invalid_certs(options.insecure)
+                .tls_info(options.pin_cert.is_some());
+            if let Some(certificate) = &certificate {
+                builder = builder.add_root_certificate(certificate.clone());
+            }
+            builder.build().change_context(Error::RequestClient)
+        };
+        // with --pin-cert, redirects are followed in `send`, so the token is never sent
+        // to a host whose certificate wasn't checked
+        let redirect = match options.pin_cert {
+            Some(_) => Policy::none(),
+            None => Policy::default(),
+        };
+        let client = build(headers.clone(), redirect)?;
+        let no_redirect_client = build(headers.clone(), Policy::none())?;
+        headers.remove("Authorization");
+        let storage_client = build(headers, Policy::default())?;
+
+        Ok(Self {
+            client,
+            no_redirect_client,
+            storage_client,
+            token,
+            api_url,
+            options,
+            rate_limit: Mutex::new(None),
+        })
+    }
+
+    /// Get the URL of the API endpoint, like `repos/OWNER/REPO/actions/artifacts`
+    pub fn url(&self, path: &str) -> String {
+        format!("{}/{}", self.api_url.as_str().trim_end_matches('/'), path)
+    }
+
+    /// Check if the URL is on the API server, and not on a mirror or storage it redirects to
+    fn is_api_url(&self, url: &Url) -> bool {
+        url.origin() == self.api_url.origin()
+    }
+
+    /// Get the URL to show in messages, with the signature removed if it's not an API URL
+    pub fn redacted_url(&self, url: &str) -> String {
+        redact_url(url, self.api_url.as_str())
+    }
+
+    /// Check the certificate of the API server against --pin-cert, without sending the token
+    pub async fn verify_pin(&self) -> Result<(), Error> {
+        if self.options.pin_cert.is_none() {
+            return Ok(());
+        }
+        let response = self
+            .storage_client
+            .get(self.api_url.clone())
+            .send()
+            .await
+            .change_context(Error::PinCert)?;
+        self.check_pin(&response)
+    }
+
+    /// Check the certificate the response came with against --pin-cert
+    ///
+    /// Only responses from the API server are checked, since the pin is for its certificate.
+    /// Mirrors from --download-rewrite and the storage of the archives have their own
+    pub fn check_pin(&self, response: &Response) -> Result<(), Error> {
+        let Some(expected) = &self.options.pin_cert else {
+            return Ok(());
+        };
+        if !self.is_api_url(response.url()) {
+            return Ok(());
+        }
+        let actual = response
+            .extensions()
+            .get::<TlsInfo>()
+            .and_then(|info| info.peer_certificate())
+            .map(sha256_hex);
+        if actual.as_ref() != Some(expected) {
+            return Err(report!(Error::PinCert))
+                .attach_printable(format!("expected: {}", expected))
+                .attach_printable(format!(
+                    "actual: {}",
+                    actual.as_deref().unwrap_or("no certificate")
+                ))
+                .attach_printable("if the server certificate was renewed, update --pin-cert");
+        }
+        Ok(())
+    }
+
+    /// Make a GET request with the token. Send it with [`Api::send`]
+    pub fn get(&self, url: &str) -> RequestBuilder {
+        self.client.get(url)
+    }
+
+    /// Send a request made with [`Api::get`], checking the certificate against --pin-cert
+    ///
+    /// With --pin-cert, a redirect is followed here without the token, since it usually
+    /// points to a different host, like the storage for artifact archives
+    pub async fn send(&self, request: RequestBuilder) -> Result<Response, Error> {
+        let request = request.build().change_context(Error::Request)?;
+        let headers = request.headers().clone();
+        let response = self
+            .client
+            .execute(request)
+            .await
+            .change_context(Error::Request)?;
+        self.check_pin(&response)?;
+        if self.options.pin_cert.is_none() || !response.status().is_redirection() {
+            return Ok(response);
+        }
+        let Some(location) = response
+            .headers()
+            .get(header::LOCATION)
+            .and_then(|value| value.to_str().ok())
+        else {
+            return Ok(response);
+        };
+        let location = response
+            .url()
+            .join(location)
+            .change_context(Error::Request)
+            .attach_printable("invalid redirect location")?;
+        self.storage_client
+            .get(location)
+            .headers(headers)
+            .send()
+            .await
+            .change_context(Error::Request)
+    }
+
+    pub fn no_redirect_client(&self) -> &Client {
+        &self.no_redirect_client
+    }
+
+    pub fn storage_client(&self) -> &Client {
+        &self.storage_client
+    }
+
+    pub fn cancel(&self) -> &CancellationToken {
+        &self.options.cancel
+    }
+
+    pub fn list_retry(&self) -> RetryPolicy {
+        self.options.list_retry.clone()
+    }
+
+    pub fn download_retry(&self) -> RetryPolicy {
+        self.options.download_retry.clone()
+    }
+
+    /// Send a GET request to the API and parse the JSON response
+    pub async fn get_json<T: serde::de::DeserializeOwned + Schema>(
+        &self,
+        url: &str,
+    ) -> Result<T, Error> {
+        let bytes = self
+            .options
+            .list_retry
+            .run(&self.redacted_url(url), &self.options.cancel, || async {
+                let response = self.send(self.get(url)).await?;
+                let response = error_for_status(response).await?;
+                warn_if_deprecated(&response);
+                self.record_rate_limit(&response);
+                response.bytes().await.change_context(Error::Request)
+            })
+            .await?;
+        if let Some(dir) = &self.options.dump_raw {
+            self.dump(dir, url, &bytes).await?;
+        }
+
+        let value: T = parse_json(&bytes)?;
+        if self.options.strict_schema {
+            let missing = value.missing_fields();
+            if !missing.is_empty() {
+                return Err(report!(Error::Parse))
+                    .attach_printable(format!("missing fields: {}", missing.join(", ")))
+                    .attach_printable(format!("url: {}", url))
+                    .attach_printable("run without --strict-schema to use defaults for them");
+            }
+        }
+        Ok(value)
+    }
+
+    /// Keep the rate limit from the response headers if it's the lowest so far
+    fn record_rate_limit(&self, response: &Response) {
+        let header = |name: &str| -> Option<u64> {
+            response.headers().get(name)?.to_str().ok()?.parse().ok()
+        };
+        let (Some(limit), Some(remaining), Some(reset)) = (
+            header("X-RateLimit-Limit"),
+            header("X-RateLimit-Remaining"),
+            header("X-RateLimit-Reset"),
+        ) else {
+            return;
+        };
+        let mut rate_limit = self.rate_limit.lock().unwrap();
+        // a later reset is a new window, where the remaining count starts over
+        let is_lower = rate_limit.as_ref().is_none_or(|last| {
+            reset > last.reset || (reset == last.reset && remaining < last.remaining)
+        });
+        if is_lower {
+            *rate_limit = Some(RateLimit {
+                limit,
+                remaining,
+                reset,
+                scopes: None,
+            });
+        }
+    }
+
+    /// Rate limit from the headers of the API responses so far
+    pub fn last_rate_limit(&self) -> Option<RateLimit> {
+        self.rate_limit.lock().unwrap().clone()
+    }
+
+    /// Save the raw response body to the directory, with the token redacted
+    async fn dump(&self, dir: &Path, url: &str, bytes: &[u8]) -> Result<(), Error> {
+        // keep the files in request order even if they are in the same millisecond
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or_default();
+        let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+        let name = url
+            .trim_start_matches(self.url("").as_str())
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect::<String>();
+        let path = dir.join(format!("{}-{:03}-{}.json", timestamp, count, name));
+
+        let write = async {
+            tokio::fs::create_dir_all(dir).await?;
+            tokio::fs::write(&path, redact(bytes, &self.token)).await
+        };
+        write
+            .await
+            .change_context(Error::DumpRaw)
+            .attach_printable_lazy(|| format!("path: {}", path.display()))?;
+        progress!("saved response to `{}`", path.display());
+        Ok(())
+    }
+}
+
+fn load_certificate(path: &Path) -> Result<Certificate, Error> {
+    let bytes = std::fs::read(path).change_context(Error::RequestClient)?;
+    Certificate::from_pem(&bytes)
+        .or_else(|_| Certificate::from_der(&bytes))
+        .change_context(Error::RequestClient)
+        .attach_printable("invalid certificate, expected PEM or DER format")
+}
+
+/// Parse the value of --pin-cert, a SHA-256 fingerprint in hex, with or without colons
+pub fn parse_fingerprint(s: &str) -> std::result::Result<String, String> {
+    let hex = s.replace(':', "").to_ascii_lowercase();
+    if hex.len() != 64 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("invalid SHA-256 fingerprint `{}`", s));
+    }
+    Ok(hex)
+}
+
+/// Replace occurrences of the secret in the bytes
+fn redact(bytes: &[u8], secret: &str) -> Vec<u8> {
+    const REDACTED: &[u8] = b"[REDACTED]";
+    let secret = secret.as_bytes();
+    let mut output = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if !secret.is_empty() && bytes[i..].starts_with(secret) {
+            output.extend_from_slice(REDACTED);
+            i += secret.len();
+        } else {
+            output.push(bytes[i]);
+            i += 1;
+        }
+    }
+    output
+}
+
+/// Check if any workflow ran on the commit
+pub async fn has_workflow_runs(api: &Api, repo: &str, rev: &str) -> Result<bool, Error> {
+    let runs: WorkflowRuns = api
+        .get_json(&api.url(&format!(
+            "repos/{}/actions/runs?head_sha={}&per_page=1",
+            repo, rev
+        )))
+        .await
+        .change_context(Error::GetWorkflowRuns)?;
+
+    Ok(runs.total_count > 0)
+}
+
+/// Get the workflow runs that belong to the check suite
+pub async fn get_check_suite_runs(
+    api: &Api,
+    repo: &str,
+    check_suite: u64,
+) -> Result<Vec<Run>, Error> {
+    const PER_PAGE: usize = 100;
+    let mut runs = Vec::new();
+    for page in 1.. {
+        let listed: WorkflowRuns = api
+            .get_json(&api.url(&format!(
+                "repos/{}/actions/runs?check_suite_id={}&per_page={}&page={}",
+                repo, check_suite, PER_PAGE, page
+            )))
+            .await
+            .change_context(Error::GetWorkflowRuns)
+            .attach_printable_lazy(|| format!("check suite: {}", check_suite))
+            .attach_printable_lazy(|| format!("page: {}", page))?;
+        let page_runs = listed.workflow_runs.unwrap_or_default();
+        let len = page_runs.len();
+        runs.extend(page_runs);
+        if len < PER_PAGE || runs.len() as u64 >= listed.total_count {
+            break;
+        }
+    }
+    Ok(runs)
+}
+
+/// Get the jobs of the workflow run
+pub async fn get_run_jobs(api: &Api, repo: &str, run_id: u64) -> Result<Vec<Job>, Error> {
+    let jobs: Jobs = api
+        .get_json(&api.url(&format!(
+            "repos/{}/actions/runs/{}/jobs?per_page=100",
+            repo, run_id
+        )))
+        .await
+        .change_context(Error::GetJobs)
+        .attach_printable_lazy(|| format!("run: {}", run_id))?;
+
+    Ok(jobs.jobs)
+}
+
+/// Get the output of the check run of a job, from the `check_run_url` of the job
+pub async fn get_check_run_output(api: &Api, url: &str) -> Result<CheckRunOutput, Error> {
+    let check_run: CheckRun = api
+        .get_json(url)
+        .await
+        .change_context(Error::Summaries)
+        .attach_printable_lazy(|| format!("url: {}", url))?;
+
+    Ok(check_run.output)
+}
+
+/// Get a workflow run by its ID
+pub async fn get_run(api: &Api, repo: &str, run_id: u64) -> Result<Run, Error> {
+    api.get_json(&api.url(&format!("repos/{}/actions/runs/{}", repo, run_id)))
+        .await
+        .change_context(Error::GetWorkflowRuns)
+        .attach_printable_lazy(|| format!("run: {}", run_id))
+}
+
+/// Get an attempt of a workflow run, by its number starting at 1
+pub async fn get_run_attempt(
+    api: &Api,
+    repo: &str,
+    run_id: u64,
+    attempt: u64,
+) -> Result<Run, Error> {
+    api.get_json(&api.url(&format!(
+        "repos/{}/actions/runs/{}/attempts/{}",
+        repo, run_id, attempt
+    )))
+    .await
+    .change_context(Error::GetWorkflowRuns)
+    .attach_printable_lazy(|| format!("run: {}, attempt: {}", run_id, attempt))
+}
+
+/// Get the head commit and repo of a pull request
+pub async fn get_pull_request_head(
+    api: &Api,
+    repo: &str,
+    number: u64,
+) -> Result<PullRequestHead, Error> {
+    let pull_request: PullRequest = api
+        .get_json(&api.url(&format!("repos/{}/pulls/{}", repo, number)))
+        .await
+        .change_context(Error::PullRequest)
+        .attach_printable_lazy(|| format!("pull request: #{}", number))?;
+
+    Ok(pull_request.head)
+}
+
+/// Resolve a revision (short SHA, branch or tag) to the full commit SHA with the API,
+/// without needing a local clone
+pub async fn get_commit_sha(api: &Api, repo: &str, rev: &str) -> Result<String, Error> {
+    if is_full_sha(rev) {
+        return Ok(rev.to_string());
+    }
+    let commit: Commit = api
+        .get_json(&api.url(&format!("repos/{}/commits/{}", repo, rev)))
+        .await
+        .change_context(Error::Rev)
+        .attach_printable_lazy(|| format!("rev: {}", rev))?;
+
+    Ok(commit.sha)
+}
+
+/// Get the rate limit of the token, and its scopes if GitHub reports them
+///
+/// Requests to `/rate_limit` don't count against the rate limit
+pub async fn get_rate_limit(api: &Api) -> Result<RateLimit, Error> {
+    let url = api.url("rate_limit");
+    let (scopes, bytes) = api
+        .list_retry()
+        .run(&url, api.cancel(), || async {
+            let response = api.send(api.get(&url)).await?;
+            let response = error_for_status(response).await?;
+            // only classic tokens have scopes
+            let scopes = response
+                .headers()
+                .get("X-OAuth-Scopes")
+                .and_then(|value| value.to_str().ok())
+                .map(|value| {
+                    value
+                        .split(',')
+                        .map(|scope| scope.trim().to_string())
+                        .filter(|scope| !scope.is_empty())
+                        .collect()
+                });
+            let bytes = response.bytes().await.change_context(Error::Request)?;
+            Ok((scopes, bytes))
+        })
+        .await
+        .change_context(Error::RateLimit)?;
+    let response: RateLimitResponse = parse_json(&bytes).change_context(Error::RateLimit)?;
+    Ok(RateLimit {
+        scopes,
+        ..response.resources.core
+    })
+}
+
+/// Get the paths of the files the commit changed
+pub async fn get_commit_files(api: &Api, repo: &str, sha: &str) -> Result<Vec<String>, Error> {
+    const PER_PAGE: usize = 100;
+    let mut files = Vec::new();
+    for page in 1.. {
+        let commit: Commit = api
+            .get_json(&api.url(&format!(
+                "repos/{}/commits/{}?per_page={}&page={}",
+                repo, sha, PER_PAGE, page
+            )))
+            .await
+            .change_context(Error::Rev)
+            .attach_printable_lazy(|| format!("commit: {}", sha))?;
+        let page_files = commit.files.unwrap_or_default();
+        let len = page_files.len();
+        files.extend(page_files.into_iter().map(|file| file.filename));
+        if len < PER_PAGE {
+            break;
+        }
+    }
+    Ok(files)
+}
+
+/// Fail if the response has an error status, like [`Response::error_for_status`]
+///
+/// A 403 or 429 with a message about the secondary rate limit fails with
+/// [`Error::SecondaryRateLimit`], since it goes away after waiting, unlike the primary
+/// rate limit or missing permissions. `Retry-After` is attached if GitHub sent it
+pub async fn error_for_status(response: Response) -> Result<Response, Error> {
+    let status = response.status();
+    if status != StatusCode::FORBIDDEN && status != StatusCode::TOO_MANY_REQUESTS {
+        return response.error_for_status().change_context(Error::Request);
+    }
+    let retry_after = response
+        .headers()
+        .get(header::RETRY_AFTER)
+        .and_then(|value| value.to_str().ok()?.trim().parse().ok())
+        .map(Duration::from_secs);
+    let body = response.text().await.unwrap_or_default();
+    let message = serde_json::from_str::<serde_json::Value>(&body)
+        .ok()
+        .and_then(|value| Some(value.get("message")?.as_str()?.to_string()))
+        .unwrap_or(body);
+    let context = if message.to_lowercase().contains("secondary rate limit") {
+        Error::SecondaryRateLimit
+    } else {
+        Error::Request
+    };
+    let mut report = report!(context)
+        .attach_printable(Status(status))
+        .attach_printable(format!("message: {}", message));
+    if let Some(retry_after) = retry_after {
+        report = report.attach_printable(RetryAfter(retry_after));
+    }
+    Err(report)
+}
+
+/// Print a warning, once per run, if GitHub says the endpoint is going away
+pub fn warn_if_deprecated(response: &Response) {
+    static WARNED: Once = Once::new();
+    let Some(deprecation) = Deprecation::from_headers(response.headers()) else {
+        return;
+    };
+    WARNED.call_once(|| {
+        warning!(
+            "GitHub API endpoint `{}` is deprecated",
+            response.url().path()
+        );
+        if let Some(sunset) = deprecation.sunset {
+            eprintln!("  it will be removed after: {}", sunset);
+        }
+        eprintln!("  please report this at https://github.com/Pistonite/magnesis/issues");
+    });
+}
+
+/// Deprecation of an endpoint, from the response headers
+#[derive(Debug, PartialEq)]
+struct Deprecation<'a> {
+    /// When the endpoint will be removed, if GitHub said
+    sunset: Option<&'a str>,
+}
+
+impl<'a> Deprecation<'a> {
+    fn from_headers(headers: &'a HeaderMap) -> Option<Self> {
+        if !headers.contains_key("Deprecation") && !headers.contains_key("Sunset") {
+            return None;
+        }
+        Some(Self {
+            sunset: headers.get("Sunset").and_then(|v| v.to_str().ok()),
+        })
+    }
+}
+
+/// Parse a JSON response body, attaching where and what failed to parse on error
+pub fn parse_json<T: serde::de::DeserializeOwned>(bytes: &[u8]) -> Result<T, Error> {
+    let mut deserializer = serde_json::Deserializer::from_slice(bytes);
+    serde_path_to_error::deserialize(&mut deserializer).map_err(|err| {
+        let path = err.path().to_string();
+        let err = err.into_inner();
+        let snippet = json_snippet(bytes, err.line(), err.column());
+        report!(err)
+            .change_context(Error::Parse)
+            .attach_printable(format!("path: {}", path))
+            .attach_printable(format!("near: {}", snippet))
+            .attach_printable(format!("body length: {}", bytes.len()))
+    })
+}
+
+/// Get the part of the JSON around the 1-based line and column
+fn json_snippet(bytes: &[u8], line: usize, column: usize) -> String {
+    const CONTEXT: usize = 40;
+    let line_start = bytes
+        .split_inclusive(|b| *b == b'\n')
+        .take(line.saturating_sub(1))
+        .map(|l| l.len())
+        .sum::<usize>();
+    let offset = (line_start + column.saturating_sub(1)).min(bytes.len());
+    let start = offset.saturating_sub(CONTEXT);
+    let end = (offset + CONTEXT).min(bytes.len());
+    String::from_utf8_lossy(&bytes[start..end]).into_owned()
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct WorkflowRuns {
+    total_count: u64,
+    workflow_runs: Option<Vec<Run>>,
+}
+
+impl Schema for WorkflowRuns {
+    fn missing_fields(&self) -> Vec<String> {
+        let Some(runs) = &self.workflow_runs else {
+            return vec!["workflow_runs".to_string()];
+        };
+        runs.iter()
+            .enumerate()
+            .flat_map(|(i, run)| {
+                run.missing_fields()
+                    .into_iter()
+                    .map(move |field| format!("workflow_runs[{}].{}", i, field))
+            })
+            .collect()
+    }
+}
+
+/// A workflow run, as returned by the runs API
+#[derive(Debug, serde::Deserialize)]
+pub struct Run {
+    pub id: u64,
+    pub head_sha: String,
+    /// Name of the workflow
+    pub name: Option<String>,
+    /// Path of the workflow file, like `.github/workflows/build.yml`
+    pub path: Option<String>,
+    /// Number of the attempt, starting at 1. The latest attempt unless the run
+    /// is requested with [`get_run_attempt`]
+    pub run_attempt: Option<u64>,
+    /// RFC 3339 timestamp in UTC of when the attempt started
+    pub run_started_at: Option<String>,
+    /// Title of the run, which for manually dispatched runs can include the inputs
+    pub display_title: Option<String>,
+}
+
+impl Schema for Run {
+    fn missing_fields(&self) -> Vec<String> {
+        let mut missing = Vec::new();
+        if self.name.is_none() {
+            missing.push("name".to_string());
+        }
+        if self.path.is_none() {
+            missing.push("path".to_string());
+        }
+        if self.run_attempt.is_none() {
+            missing.push("run_attempt".to_string());
+        }
+        if self.run_started_at.is_none() {
+            missing.push("run_started_at".to_string());
+        }
+        if self.display_title.is_none() {
+            missing.push("display_title".to_string());
+        }
+        missing
+    }
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct Jobs {
+    jobs: Vec<Job>,
+}
+
+impl Schema for Jobs {}
+
+/// A job in a workflow run, as returned by the jobs API
+#[derive(Debug, serde::Deserialize)]
+pub struct Job {
+    pub name: String,
+    /// RFC 3339 timestamps in UTC. `None` if the job hasn't started or finished
+    pub started_at: Option<String>,
+    pub completed_at: Option<String>,
+    /// API URL of the check run of the job, which has its output
+    #[serde(default)]
+    pub check_run_url: Option<String>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct CheckRun {
+    output: CheckRunOutput,
+}
+
+impl Schema for CheckRun {}
+
+/// Output of a check run, which is shown on the page of the job
+#[derive(Debug, serde::Deserialize)]
+pub struct CheckRunOutput {
+    pub title: Option<String>,
+    pub summary: Option<String>,
+    pub text: Option<String>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct Commit {
+    sha: String,
+    /// Only in the response for a single commit
+    files: Option<Vec<CommitFile>>,
+}
+
+impl Schema for Commit {}
+
+#[derive(Debug, serde::Deserialize)]
+struct CommitFile {
+    filename: String,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct PullRequest {
+    head: PullRequestHead,
+}
+
+impl Schema for PullRequest {}
+
+#[derive(Debug, serde::Deserialize)]
+struct RateLimitResponse {
+    resources: RateLimitResources,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct RateLimitResources {
+    core: RateLimit,
+}
+
+/// Rate limit of the core API for the token
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct RateLimit {
+    pub limit: u64,
+    pub remaining: u64,
+    /// When the limit resets, in seconds since the Unix epoch
+    pub reset: u64,
+    /// Scopes of the token, from the `X-OAuth-Scopes` header.
+    /// `None` for tokens that don't have scopes, like fine-grained and GitHub Actions tokens
+    #[serde(default)]
+    pub scopes: Option<Vec<String>>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct PullRequestHead {
+    pub sha: String,
+    /// `None` if the fork was deleted
+    pub repo: Option<PullRequestRepo>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct PullRequestRepo {
+    pub full_name: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        artifact::Artifacts,
+        test_util::{mock_api, self_signed_cert, MockServer, Response, TempDir},
+    };
+
+    #[test]
+    fn test_parse_json() {
+        let body = br#"{"artifacts":[{"id":1,"name":"app","archive_download_url":"https://example.com","workflow_run":{"head_sha":"abc"}}]}"#;
+        let artifacts: Artifacts = parse_json(body).unwrap();
+        assert_eq!(artifacts.into_filtered_by_rev("abc")[0].name, "app");
+    }
+
+    #[test]
+    fn test_parse_json_malformed_artifacts() {
+        let body = br#"{"artifacts":[{"id":1,"name":"app","archive_download_url":"https://example.com","workflow_run":{"head_sha":"abc"}},{"id":2,"name":"docs","archive_download_url":"https://example.com","workflow_run":{"head_sha":1}}]}"#;
+        let err = parse_json::<Artifacts>(body).unwrap_err();
+        let report = format!("{:?}", err);
+        assert!(
+            report.contains("path: artifacts[1].workflow_run.head_sha"),
+            "{}",
+            report
+        );
+        assert!(report.contains("near: "), "{}", report);
+        assert!(
+            report.contains(&format!("body length: {}", body.len())),
+            "{}",
+            report
+        );
+    }
+
+    #[test]
+    fn test_json_snippet() {
+        let bytes = b"{\n  \"a\": 1,\n  \"b\": x\n}";
+        // `x` is at line 3, column 8
+        assert_eq!(json_snippet(bytes, 3, 8), String::from_utf8_lossy(bytes));
+        let long = format!("{{\"a\": \"{}\", \"b\": x}}", "y".repeat(100));
+        let column = long.find('x').unwrap() + 1;
+        let snippet = json_snippet(long.as_bytes(), 1, column);
+        // 40 bytes before `x`, and the rest of the body after it
+        assert_eq!(snippet.len(), 42);
+        assert!(snippet.ends_with("\"b\": x}"));
+        // out of range positions don't panic
+        assert_eq!(json_snippet(b"{}", 10, 10), "{}");
+    }
+
+    #[test]
+    fn test_deprecation() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(Deprecation::from_headers(&headers), None);
+        headers.insert(
+            "Sunset",
+            HeaderValue::from_static("Wed, 11 Nov 2026 23:59:59 GMT"),
+        );
+        assert_eq!(
+            Deprecation::from_headers(&headers),
+            Some(Deprecation {
+                sunset: Some("Wed, 11 Nov 2026 23:59:59 GMT")
+            })
+        );
+        let mut headers = HeaderMap::new();
+        headers.insert("Deprecation", HeaderValue::from_static("true"));
+        assert_eq!(
+            Deprecation::from_headers(&headers),
+            Some(Deprecation { sunset: None })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dump_raw() {
+        let dir = TempDir::new();
+        let server = MockServer::start(|_| {
+            Response::json(r#"{"total_count":1,"echo":"Bearer secret-token"}"#)
+        })
+        .await;
+        let api = Api::new(
+            "secret-token".to_string(),
+            ApiOptions {
+                dump_raw: Some(dir.join("raw")),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let runs: WorkflowRuns = api
+            .get_json(&server.url("/repos/foo/bar/actions/runs"))
+            .await
+            .unwrap();
+        assert_eq!(runs.total_count, 1);
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].method, "GET");
+        assert_eq!(requests[0].path, "/repos/foo/bar/actions/runs");
+        assert_eq!(
+            requests[0].header("authorization"),
+            Some("Bearer secret-token")
+        );
+        assert!(requests[0].body.is_empty());
+
+        let files = std::fs::read_dir(dir.join("raw"))
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect::<Vec<_>>();
+        assert_eq!(files.len(), 1);
+        let name = files[0].file_name().unwrap().to_str().unwrap();
+        assert!(
+            name.ends_with("_repos_foo_bar_actions_runs.json"),
+            "{}",
+            name
+        );
+        assert_eq!(
+            std::fs::read_to_string(&files[0]).unwrap(),
+            r#"{"total_count":1,"echo":"Bearer [REDACTED]"}"#
+        );
+    }
+
+    #[test]
+    fn test_redact() {
+        assert_eq!(
+            redact(b"token abc token", "token"),
+            b"[REDACTED] abc [REDACTED]"
+        );
+        assert_eq!(redact(b"abc", ""), b"abc");
+        assert_eq!(redact(b"tok", "token"), b"tok");
+    }
+
+    #[tokio::test]
+    async fn test_custom_tls() {
+        let dir = TempDir::new();
+        let cert = self_signed_cert();
+        let cert_path = dir.join("cert.pem");
+        std::fs::write(&cert_path, &cert.pem).unwrap();
+        let server = MockServer::start_tls(&cert, |_| Response::json(r#"{"total_count":0}"#)).await;
+        let url = server.url("/repos/foo/bar/actions/runs");
+        let get = |options: ApiOptions| {
+            let url = url.clone();
+            async move {
+                let api = Api::new("token".to_string(), options)?;
+                api.get_json::<WorkflowRuns>(&url).await
+            }
+        };
+
+        assert!(get(ApiOptions::default()).await.is_err());
+        get(ApiOptions {
+            ca_cert: Some(cert_path),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        get(ApiOptions {
+            insecure: true,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        std::fs::write(dir.join("bad.pem"), "not a certificate").unwrap();
+        let err = get(ApiOptions {
+            ca_cert: Some(dir.join("bad.pem")),
+            ..Default::default()
+        })
+        .await
+        .unwrap_err();
+        assert!(format!("{:?}", err).contains("expected PEM or DER format"));
+    }
+
+    #[test]
+    fn test_parse_rate_limit() {
+        let bytes = br#"{
+            "resources": {
+                "core": { "limit": 5000, "used": 1, "remaining": 4999, "reset": 1700000000 },
+                "search": { "limit": 30, "used": 0, "remaining": 30, "reset": 1700000000 }
+            },
+            "rate": { "limit": 5000, "used": 1, "remaining": 4999, "reset": 1700000000 }
+        }"#;
+        let response: RateLimitResponse = parse_json(bytes).unwrap();
+        let rate_limit = response.resources.core;
+        assert_eq!(rate_limit.limit, 5000);
+        assert_eq!(rate_limit.remaining, 4999);
+        assert_eq!(rate_limit.reset, 1700000000);
+        assert_eq!(rate_limit.scopes, None);
+    }
+
+    #[test]
+    fn test_parse_fingerprint() {
+        let hex = "ab".repeat(32);
+        assert_eq!(parse_fingerprint(&hex), Ok(hex.clone()));
+        let colons = vec!["AB"; 32].join(":");
+        assert_eq!(parse_fingerprint(&colons), Ok(hex));
+        assert!(parse_fingerprint("abcd").is_err());
+        assert!(parse_fingerprint(&"zz".repeat(32)).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_pin_cert() {
+        let dir = TempDir::new();
+        let cert = self_signed_cert();
+        let cert_path = dir.join("cert.pem");
+        std::fs::write(&cert_path, &cert.pem).unwrap();
+        let der = openssl::x509::X509::from_pem(&cert.pem)
+            .unwrap()
+            .to_der()
+            .unwrap();
+        let server = MockServer::start_tls(&cert, |_| Response::json(r#"{"total_count":0}"#)).await;
+        let get = |pin_cert: String| {
+            let (api_url, cert_path) = (server.url(""), cert_path.clone());
+            async move {
+                let api = Api::new(
+                    "token".to_string(),
+                    ApiOptions {
+                        api_url: Some(api_url),
+                        ca_cert: Some(cert_path),
+                        pin_cert: Some(pin_cert),
+                        ..Default::default()
+                    },
+                )?;
+                api.get_json::<WorkflowRuns>(&api.url("repos/foo/bar/actions/runs"))
+                    .await
+            }
+        };
+
+        get(sha256_hex(&der)).await.unwrap();
+        let err = get("0".repeat(64)).await.unwrap_err();
+        let err = format!("{:?}", err);
+        assert!(
+            err.contains(&format!("actual: {}", sha256_hex(&der))),
+            "{}",
+            err
+        );
+        assert!(err.contains("update --pin-cert"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn test_pin_cert_redirect() {
+        let dir = TempDir::new();
+        let cert = self_signed_cert();
+        let cert_path = dir.join("cert.pem");
+        std::fs::write(&cert_path, &cert.pem).unwrap();
+        let der = openssl::x509::X509::from_pem(&cert.pem)
+            .unwrap()
+            .to_der()
+            .unwrap();
+        let server = MockServer::start_tls(&cert, |request| match request.path.as_str() {
+            "/zip" => Response::new(302).header("Location", "/signed"),
+            _ => Response::new(200).body("archive"),
+        })
+        .await;
+        let api = Api::new(
+            "token".to_string(),
+            ApiOptions {
+                api_url: Some(server.url("")),
+                ca_cert: Some(cert_path),
+                pin_cert: Some(sha256_hex(&der)),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let request = api.get(&api.url("zip")).header(header::RANGE, "bytes=0-1");
+        let response = api.send(request).await.unwrap();
+        assert_eq!(response.text().await.unwrap(), "archive");
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].header("authorization"), Some("Bearer token"));
+        // followed without the token, with the other headers
+        assert_eq!(requests[1].path, "/signed");
+        assert_eq!(requests[1].header("authorization"), None);
+        assert_eq!(requests[1].header("range"), Some("bytes=0-1"));
+    }
+
+    #[tokio::test]
+    async fn test_pin_cert_only_api() {
+        let cert = self_signed_cert();
+        let mirror = MockServer::start(|_| Response::new(200).body("archive")).await;
+        let api = Api::new(
+            "token".to_string(),
+            ApiOptions {
+                api_url: Some("https://ghes.example.com/api/v3".to_string()),
+                pin_cert: Some("0".repeat(64)),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        // a mirror from --download-rewrite isn't checked against the pin
+        let response = api.send(api.get(&mirror.url("/zip"))).await.unwrap();
+        assert_eq!(response.text().await.unwrap(), "archive");
+
+        // the API server is
+        let server = MockServer::start_tls(&cert, |_| Response::json("{}")).await;
+        let api = Api::new(
+            "token".to_string(),
+            ApiOptions {
+                api_url: Some(server.url("")),
+                insecure: true,
+                pin_cert: Some("0".repeat(64)),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let err = api.send(api.get(&api.url("zip"))).await.unwrap_err();
+        assert!(matches!(err.current_context(), Error::PinCert));
+    }
+
+    #[test]
+    fn test_api_url() {
+        let api = Api::new("token".to_string(), ApiOptions::default()).unwrap();
+        assert_eq!(
+            api.url("repos/a/b/actions/artifacts"),
+            "https://api.github.com/repos/a/b/actions/artifacts"
+        );
+        let options = ApiOptions {
+            api_url: Some("https://ghes.example.com/api/v3/".to_string()),
+            ..Default::default()
+        };
+        let api = Api::new("token".to_string(), options).unwrap();
+        assert_eq!(
+            api.url("rate_limit"),
+            "https://ghes.example.com/api/v3/rate_limit"
+        );
+        assert_eq!(
+            api.redacted_url("https://storage.example.com/a.zip?sig=secret"),
+            "https://storage.example.com/a.zip?[REDACTED]"
+        );
+
+        let options = ApiOptions {
+            api_url: Some("ghes.example.com".to_string()),
+            ..Default::default()
+        };
+        assert!(Api::new("token".to_string(), options).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_last_rate_limit() {
+        let server = MockServer::start(|request| {
+            let (remaining, reset) = match request.path.as_str() {
+                "/first" => ("10", "100"),
+                "/lower" => ("5", "100"),
+                // out of order, from a request that started before /lower
+                "/higher" => ("8", "100"),
+                // the next window
+                _ => ("4999", "200"),
+            };
+            Response::json(r#"{"total_count":0}"#)
+                .header("X-RateLimit-Limit", "5000")
+                .header("X-RateLimit-Remaining", remaining)
+                .header("X-RateLimit-Reset", reset)
+        })
+        .await;
+        let api = Api::new("token".to_string(), ApiOptions::default()).unwrap();
+        assert!(api.last_rate_limit().is_none());
+        let remaining = |path: &'static str| {
+            let (api, url) = (&api, server.url(path));
+            async move {
+                let _: WorkflowRuns = api.get_json(&url).await.unwrap();
+                let rate_limit = api.last_rate_limit().unwrap();
+                (rate_limit.remaining, rate_limit.reset)
+            }
+        };
+
+        assert_eq!(remaining("/first").await, (10, 100));
+        assert_eq!(remaining("/lower").await, (5, 100));
+        assert_eq!(remaining("/higher").await, (5, 100));
+        assert_eq!(remaining("/reset").await, (4999, 200));
+    }
+
+    #[tokio::test]
+    async fn test_secondary_rate_limit() {
+        let limited = AtomicUsize::new(0);
+        let server = MockServer::start(move |request| match request.path.as_str() {
+            "/forbidden" => Response::new(403).body(r#"{"message":"Resource not accessible"}"#),
+            // the first request hits the secondary rate limit
+            _ if limited.fetch_add(1, Ordering::SeqCst) == 0 => Response::new(403)
+                .header("Retry-After", "1")
+                .body(r#"{"message":"You have exceeded a secondary rate limit."}"#),
+            _ => Response::json(r#"{"total_count":1}"#),
+        })
+        .await;
+        let retry = RetryPolicy {
+            retries: 1,
+            ..Default::default()
+        };
+        let api = Api::new(
+            "token".to_string(),
+            ApiOptions {
+                list_retry: retry,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let start = std::time::Instant::now();
+        let runs: WorkflowRuns = api.get_json(&server.url("/runs")).await.unwrap();
+        assert_eq!(runs.total_count, 1);
+        // waited as long as Retry-After said
+        assert!(start.elapsed() >= Duration::from_secs(1));
+        assert_eq!(server.requests().len(), 2);
+
+        // other 403s are not retried
+        let err = api
+            .get_json::<WorkflowRuns>(&server.url("/forbidden"))
+            .await
+            .unwrap_err();
+        assert!(matches!(err.current_context(), Error::Request));
+        assert!(format!("{:?}", err).contains("Resource not accessible"));
+        assert_eq!(server.requests().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_get_rate_limit() {
+        let requests = AtomicUsize::new(0);
+        let server = MockServer::start(move |request| {
+            if request.path != "/rate_limit" {
+                return Response::new(404);
+            }
+            let response = Response::json(
+                r#"{"resources":{"core":{"limit":5000,"remaining":4321,"reset":1700000000}}}"#,
+            );
+            // fine-grained tokens don't have scopes
+            if requests.fetch_add(1, Ordering::SeqCst) == 0 {
+                response.header("X-OAuth-Scopes", "repo, read:org")
+            } else {
+                response
+            }
+        })
+        .await;
+        let api = mock_api(&server);
+
+        let rate_limit = get_rate_limit(&api).await.unwrap();
+        assert_eq!(
+            (rate_limit.limit, rate_limit.remaining, rate_limit.reset),
+            (5000, 4321, 1700000000)
+        );
+        let scopes = rate_limit.scopes.unwrap();
+        assert_eq!(scopes, ["repo", "read:org"]);
+        assert_eq!(get_rate_limit(&api).await.unwrap().scopes, None);
+    }
+}
@@ -0,0 +1,172 @@
+//! Inventory of artifacts pulled before, by digest, for --against
+
+use std::{collections::HashSet, path::PathBuf};
+
+use error_stack::{report, Result, ResultExt};
+use serde_json::Value;
+use tokio::fs;
+
+use crate::{artifact::Artifact, atomic_file, manifest::ManifestEntry, Error};
+
+/// A `metadata.json` from --metadata-only (a list of artifacts), or a `manifest.json`
+/// (an object with the list in `artifacts`). Artifacts are known by their `digest`
+#[derive(Debug)]
+pub struct Inventory {
+    path: PathBuf,
+    value: Value,
+    digests: HashSet<String>,
+}
+
+impl Inventory {
+    /// Load the inventory, or start an empty one in the format of `metadata.json`
+    /// if the file doesn't exist
+    pub async fn load(path: PathBuf) -> Result<Self, Error> {
+        let value = if path.exists() {
+            let bytes = fs::read(&path)
+                .await
+                .change_context(Error::Inventory)
+                .attach_printable_lazy(|| format!("path: {}", path.display()))?;
+            serde_json::from_slice(&bytes)
+                .change_context(Error::Inventory)
+                .attach_printable_lazy(|| format!("path: {}", path.display()))?
+        } else {
+            Value::Array(Vec::new())
+        };
+        let mut inventory = Self {
+            path,
+            value,
+            digests: HashSet::new(),
+        };
+        let digests = inventory
+            .entries()?
+            .iter()
+            .filter_map(|entry| entry.get("digest")?.as_str().map(String::from))
+            .collect();
+        inventory.digests = digests;
+        Ok(inventory)
+    }
+
+    /// Check if an artifact with the same content was pulled before
+    pub fn contains(&self, artifact: &Artifact) -> bool {
+        artifact
+            .digest
+            .as_ref()
+            .is_some_and(|digest| self.digests.contains(digest))
+    }
+
+    /// Add the downloaded artifact, in the format of the file
+    ///
+    /// `path` is where the artifact is extracted to, relative to the output directory,
+    /// for the manifest format
+    pub fn add(&mut self, artifact: &Artifact, path: PathBuf) -> Result<(), Error> {
+        let entry = if self.value.is_array() {
+            serde_json::to_value(artifact).change_context(Error::Inventory)?
+        } else {
+            let entry = ManifestEntry {
+                id: artifact.id,
+                name: artifact.name.clone(),
+                path,
+                complete: true,
+                normalized_zip_sha256: None,
+                digest: artifact.digest.clone(),
+            };
+            serde_json::to_value(entry).change_context(Error::Inventory)?
+        };
+        if let Some(digest) = &artifact.digest {
+            self.digests.insert(digest.clone());
+        }
+        self.entries()?.push(entry);
+        Ok(())
+    }
+
+    /// Save the inventory
+    pub async fn save(&self) -> Result<(), Error> {
+        let json = serde_json::to_vec_pretty(&self.value).change_context(Error::Inventory)?;
+        atomic_file::write(&self.path, json)
+            .await
+            .change_context(Error::Inventory)
+            .attach_printable_lazy(|| format!("path: {}", self.path.display()))
+    }
+
+    fn entries(&mut self) -> Result<&mut Vec<Value>, Error> {
+        let entries = match &mut self.value {
+            Value::Array(entries) => Some(entries),
+            Value::Object(object) => object.get_mut("artifacts").and_then(Value::as_array_mut),
+            _ => None,
+        };
+        entries
+            .ok_or_else(|| report!(Error::Inventory))
+            .attach_printable(format!("path: {}", self.path.display()))
+            .attach_printable("expected a metadata.json or manifest.json")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_util::{temp_files, TempDir};
+
+    fn artifact(id: u64, digest: Option<&str>) -> Artifact {
+        serde_json::from_value(serde_json::json!({
+            "id": id,
+            "name": format!("artifact-{}", id),
+            "archive_download_url": "",
+            "digest": digest,
+            "workflow_run": { "head_sha": "abc" },
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_metadata_inventory() {
+        let dir = TempDir::new();
+        let path = dir.join("metadata.json");
+        let mut inventory = Inventory::load(path.clone()).await.unwrap();
+        assert!(!inventory.contains(&artifact(1, Some("sha256:a"))));
+
+        inventory
+            .add(&artifact(1, Some("sha256:a")), PathBuf::from("artifact-1"))
+            .unwrap();
+        inventory.save().await.unwrap();
+        assert!(temp_files(&dir.join("")).is_empty());
+
+        let inventory = Inventory::load(path.clone()).await.unwrap();
+        // known by digest, not by ID
+        assert!(inventory.contains(&artifact(2, Some("sha256:a"))));
+        assert!(!inventory.contains(&artifact(1, Some("sha256:b"))));
+        // without a digest, it's always downloaded
+        assert!(!inventory.contains(&artifact(1, None)));
+        let json: Value = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        assert_eq!(json[0]["id"], 1);
+        assert_eq!(json[0]["digest"], "sha256:a");
+    }
+
+    #[tokio::test]
+    async fn test_manifest_inventory() {
+        let dir = TempDir::new();
+        let path = dir.join("manifest.json");
+        std::fs::write(
+            &path,
+            r#"{"repo":"foo/bar","rev":"abc","artifacts":[
+                {"id":1,"name":"app","path":"app","complete":true,"digest":"sha256:a"}
+            ]}"#,
+        )
+        .unwrap();
+        let mut inventory = Inventory::load(path.clone()).await.unwrap();
+        assert!(inventory.contains(&artifact(3, Some("sha256:a"))));
+        inventory
+            .add(&artifact(2, Some("sha256:b")), PathBuf::from("docs"))
+            .unwrap();
+        inventory.save().await.unwrap();
+
+        let json: Value = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        assert_eq!(json["repo"], "foo/bar");
+        assert_eq!(json["artifacts"][1]["path"], "docs");
+        assert_eq!(json["artifacts"][1]["digest"], "sha256:b");
+
+        std::fs::write(&path, r#"{"repo":"foo/bar"}"#).unwrap();
+        let err = Inventory::load(path).await.unwrap_err();
+        assert!(format!("{:?}", err).contains("expected a metadata.json or manifest.json"));
+    }
+}
@@ -0,0 +1,233 @@
+use std::path::{Component, Path, PathBuf};
+
+use error_stack::{report, Result, ResultExt};
+
+use crate::{glob::glob_match, Error};
+
+/// Rules for where each artifact is extracted to, loaded from --layout-file
+///
+/// The file is TOML with a list of rules, for example:
+/// ```toml
+/// [[rule]]
+/// match = "app-*"
+/// to = "bin/{name}"
+/// ```
+/// The first rule whose pattern matches the artifact name is used.
+/// Artifacts not matched by any rule are extracted to `{name}`,
+/// or to the common prefix if --merge-prefix is used.
+#[derive(Debug, Default)]
+pub struct Layout {
+    rules: Vec<Rule>,
+    /// Separator for grouping artifacts by name prefix, for --merge-prefix
+    merge_separator: Option<String>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct LayoutFile {
+    #[serde(default)]
+    rule: Vec<Rule>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct Rule {
+    /// Glob pattern matched against the artifact name
+    #[serde(rename = "match")]
+    pattern: String,
+    /// Destination relative to the output directory
+    to: String,
+}
+
+/// Values that can be substituted into the destination template
+const PLACEHOLDERS: &[&str] = &["name", "rev"];
+
+impl Layout {
+    pub async fn load(path: &str) -> Result<Self, Error> {
+        Self::load_internal(path)
+            .await
+            .attach_printable_lazy(|| format!("layout file: {}", path))
+    }
+
+    async fn load_internal(path: &str) -> Result<Self, Error> {
+        let content = tokio::fs::read_to_string(path)
+            .await
+            .change_context(Error::Layout)?;
+        let file: LayoutFile = toml::from_str(&content).change_context(Error::Layout)?;
+        for (i, rule) in file.rule.iter().enumerate() {
+            rule.validate()
+                .attach_printable_lazy(|| format!("rule #{}", i + 1))?;
+        }
+        Ok(Self {
+            rules: file.rule,
+            merge_separator: None,
+        })
+    }
+
+    /// Extract artifacts not matched by any rule into a directory named by
+    /// the part of the name before the last separator, for example `app-linux`
+    /// and `app-win` are both extracted to `app` with separator `-`,
+    /// and `my-app-linux` is extracted to `my-app`
+    pub fn with_merge_prefix(mut self, separator: Option<String>) -> Self {
+        self.merge_separator = separator.filter(|s| !s.is_empty());
+        self
+    }
+
+    /// Get the destination of the artifact, relative to the output directory
+    pub fn destination(&self, name: &str, rev: &str) -> PathBuf {
+        let rule = self
+            .rules
+            .iter()
+            .find(|rule| glob_match(&rule.pattern, name));
+        match rule {
+            Some(rule) => PathBuf::from(rule.to.replace("{name}", name).replace("{rev}", rev)),
+            None => {
+                let group = self
+                    .merge_separator
+                    .as_ref()
+                    .and_then(|separator| name.rsplit_once(separator.as_str()))
+                    .map(|(prefix, _)| prefix)
+                    .filter(|prefix| !prefix.is_empty())
+                    .unwrap_or(name);
+                PathBuf::from(group)
+            }
+        }
+    }
+}
+
+impl Rule {
+    fn validate(&self) -> Result<(), Error> {
+        if self.pattern.is_empty() {
+            return Err(report!(Error::Layout)).attach_printable("pattern cannot be empty");
+        }
+        if self.to.is_empty() {
+            return Err(report!(Error::Layout)).attach_printable("destination cannot be empty");
+        }
+        let mut rest = self.to.as_str();
+        while let Some(start) = rest.find('{') {
+            let Some(len) = rest[start..].find('}') else {
+                return Err(report!(Error::Layout)).attach_printable("unclosed `{` in destination");
+            };
+            let placeholder = &rest[start + 1..start + len];
+            if !PLACEHOLDERS.contains(&placeholder) {
+                return Err(report!(Error::Layout))
+                    .attach_printable(format!("unknown placeholder `{{{}}}`", placeholder))
+                    .attach_printable(format!("available: {}", PLACEHOLDERS.join(", ")));
+            }
+            rest = &rest[start + len + 1..];
+        }
+        let is_relative = Path::new(&self.to)
+            .components()
+            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+        if !is_relative {
+            return Err(report!(Error::Layout)).attach_printable(
+                "destination must be a relative path inside the output directory",
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_util::TempDir;
+
+    fn rule(pattern: &str, to: &str) -> Rule {
+        Rule {
+            pattern: pattern.to_string(),
+            to: to.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_load() {
+        let dir = TempDir::new();
+        let path = dir.join("layout.toml");
+        std::fs::write(
+            &path,
+            r#"
+[[rule]]
+match = "app-*"
+to = "bin/{name}"
+
+[[rule]]
+match = "docs"
+to = "share/{rev}/docs"
+"#,
+        )
+        .unwrap();
+        let layout = Layout::load(path.to_str().unwrap()).await.unwrap();
+        assert_eq!(
+            layout.destination("app-linux", "abc"),
+            PathBuf::from("bin/app-linux")
+        );
+        assert_eq!(
+            layout.destination("docs", "abc"),
+            PathBuf::from("share/abc/docs")
+        );
+        // not matched by any rule
+        assert_eq!(layout.destination("lib", "abc"), PathBuf::from("lib"));
+    }
+
+    #[tokio::test]
+    async fn test_load_invalid() {
+        let dir = TempDir::new();
+        let path = dir.join("layout.toml");
+        std::fs::write(&path, "[[rule]]\nmatch = \"app-*\"\nto = \"../bin\"\n").unwrap();
+        let err = Layout::load(path.to_str().unwrap()).await.unwrap_err();
+        let report = format!("{:?}", err);
+        assert!(report.contains("rule #1"), "{}", report);
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(rule("app-*", "bin/{name}").validate().is_ok());
+        assert!(rule("app-*", "./{rev}/{name}").validate().is_ok());
+        assert!(rule("", "bin").validate().is_err());
+        assert!(rule("app-*", "").validate().is_err());
+        assert!(rule("app-*", "bin/{name").validate().is_err());
+        assert!(rule("app-*", "bin/{sha}").validate().is_err());
+        assert!(rule("app-*", "../bin").validate().is_err());
+        assert!(rule("app-*", "/bin").validate().is_err());
+    }
+
+    #[test]
+    fn test_merge_prefix() {
+        let layout = Layout::default().with_merge_prefix(Some("-".to_string()));
+        for name in ["app-linux", "app-mac", "app-win"] {
+            assert_eq!(layout.destination(name, "abc"), PathBuf::from("app"));
+        }
+        // no separator in the name, or nothing before it
+        assert_eq!(layout.destination("docs", "abc"), PathBuf::from("docs"));
+        assert_eq!(layout.destination("-linux", "abc"), PathBuf::from("-linux"));
+        // rules come first
+        let layout = Layout {
+            rules: vec![rule("app-win", "windows")],
+            merge_separator: Some("-".to_string()),
+        };
+        assert_eq!(
+            layout.destination("app-win", "abc"),
+            PathBuf::from("windows")
+        );
+        assert_eq!(layout.destination("app-mac", "abc"), PathBuf::from("app"));
+    }
+
+    #[test]
+    fn test_merge_prefix_multiple_separators() {
+        let layout = Layout::default().with_merge_prefix(Some("-".to_string()));
+        assert_eq!(
+            layout.destination("my-app-linux", "abc"),
+            PathBuf::from("my-app")
+        );
+        assert_eq!(
+            layout.destination("my-app-win", "abc"),
+            PathBuf::from("my-app")
+        );
+
+        let layout = Layout::default().with_merge_prefix(Some("--".to_string()));
+        assert_eq!(
+            layout.destination("my-app--linux-x64", "abc"),
+            PathBuf::from("my-app")
+        );
+    }
+}
@@ -0,0 +1,1144 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+    process::ExitCode,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+
+use clap::Parser;
+use error_stack::{report, Result, ResultExt};
+use tokio::{fs, io::AsyncWriteExt, process::Command, spawn, task::JoinSet};
+use tokio_util::sync::CancellationToken;
+
+mod actions;
+mod artifact;
+use artifact::{get_artifacts_since, sort_artifacts};
+mod atomic_file;
+mod checksum;
+use checksum::{read_sums, sha256_hex};
+mod cli;
+use cli::Cli;
+mod compare;
+use compare::Diff;
+mod config;
+pub use config::{Config, ConfigBuilder};
+mod download;
+use download::{check_digests, download_artifacts, Pull, Records};
+mod error;
+pub use error::Error;
+mod extract;
+use extract::ExtractOptions;
+mod freeze;
+mod git;
+use git::{get_repo, get_rev};
+mod git_cache;
+use git_cache::GitCache;
+mod github;
+use github::{get_rate_limit, has_workflow_runs, Api, ApiOptions};
+mod glob;
+mod inventory;
+use inventory::Inventory;
+mod layout;
+use layout::Layout;
+mod list;
+use list::{
+    print_artifact_list, print_probe, print_rate_limit_remaining, print_recent_artifacts,
+    print_workflow_list,
+};
+mod manifest;
+use manifest::{Manifest, ManifestEntry};
+mod memory;
+mod oidc;
+use oidc::OidcProvider;
+mod output;
+use output::{progress, warning, Output};
+mod output_dir;
+use output_dir::{create_output, sibling_path, swap_output};
+mod owner;
+mod plan;
+use plan::{Extraction, Filters, Plan, PlannedArtifact};
+mod preview;
+mod retention;
+use retention::clean_older_than;
+mod retry;
+use retry::RetryPolicy;
+mod sbom;
+use sbom::Sbom;
+mod select;
+use select::{filter_artifacts, list_artifacts};
+mod shared_cache;
+use shared_cache::SharedCache;
+mod signed_url;
+mod sink;
+mod size_report;
+mod state;
+use state::{Selection, State};
+mod summaries;
+#[cfg(test)]
+mod test_util;
+mod timings;
+use timings::{timed, Timings};
+mod url;
+mod webhook;
+use webhook::{Webhook, WebhookEvent};
+
+/// Run magnesis with the options from the command line
+#[tokio::main]
+pub async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let github_actions = cli.github_actions || actions::is_github_actions();
+    let start = Instant::now();
+    let output = Output::new(cli.format, cli.quiet);
+    output::scope(output, async {
+        if let Err(err) = run_internal(Config { cli }).await {
+            eprintln!("---");
+            eprintln!("error: {:?}", err);
+            if github_actions {
+                actions::error(&format!("{:#}", err));
+            }
+            return ExitCode::FAILURE;
+        }
+        progress!("---");
+        progress!("done in {:.02}s", start.elapsed().as_secs_f64());
+        ExitCode::SUCCESS
+    })
+    .await
+}
+
+/// Pull the artifacts with the options in the config
+///
+/// Progress and warnings are printed like on the command line.
+/// Runs at the same time don't share anything, like the warnings counted for --strict
+pub async fn run(config: Config) -> Result<(), Error> {
+    let output = Output::new(config.cli.format, config.cli.quiet);
+    output::scope(output, run_internal(config)).await
+}
+
+/// What the pulls in a run share, like the revisions of --compare and --branches
+#[derive(Debug, Clone, Default)]
+struct RunContext {
+    cancel: CancellationToken,
+    /// When the run has to finish, for --deadline
+    deadline: Option<Instant>,
+    /// Outputs pulled in the run, which --clean-older-than never removes
+    pulling: Arc<BTreeSet<PathBuf>>,
+}
+
+async fn run_internal(config: Config) -> Result<(), Error> {
+    let mut cli = config.cli;
+    cli.github_actions |= actions::is_github_actions();
+    let strict = cli.strict;
+    let deadline = cli.deadline.map(Duration::from_secs);
+    let context = RunContext {
+        deadline: deadline.map(|deadline| Instant::now() + deadline),
+        ..Default::default()
+    };
+    spawn(output::inherit(cancel_on_ctrl_c(context.cancel.clone())));
+    let run = async {
+        match (cli.compare.take(), cli.branches.take()) {
+            (Some(revs), _) => compare(cli, revs, &context).await,
+            (None, Some(branches)) => pull_revs(&cli, &branches, &context).await,
+            (None, None) => main_internal(cli, &context).await,
+        }
+    };
+    // retries stop before the deadline, this stops everything else
+    match deadline {
+        Some(deadline) => tokio::time::timeout(deadline, run)
+            .await
+            .unwrap_or_else(|_| Err(report!(Error::Deadline))),
+        None => run.await,
+    }
+    .and_then(|()| check_strict(strict))
+}
+
+/// Cancel the downloads on the first Ctrl-C, so they stop and clean up,
+/// and exit right away on the second one
+async fn cancel_on_ctrl_c(cancel: CancellationToken) {
+    if tokio::signal::ctrl_c().await.is_err() {
+        return;
+    }
+    warning!("cancelling, press Ctrl-C again to exit now");
+    cancel.cancel();
+    if tokio::signal::ctrl_c().await.is_ok() {
+        std::process::exit(130);
+    }
+}
+
+async fn main_internal(cli: Cli, context: &RunContext) -> Result<(), Error> {
+    let token = if cli.oidc {
+        OidcProvider::from_env()?
+            .get_token(cli.oidc_audience.as_deref(), cli.oidc_exchange.as_deref())
+            .await?
+    } else {
+        get_token()?
+    };
+    let (repo, pr, run) = match &cli.from_url {
+        Some(url) => (Some(url.repo.clone()), url.pr, url.run),
+        None => (cli.repo.clone(), cli.pr, None),
+    };
+    let layout = match &cli.layout_file {
+        Some(path) => Layout::load(path).await?,
+        None => Layout::default(),
+    }
+    .with_merge_prefix(cli.merge_prefix.clone());
+    let mut extract_options = extract_options(&cli);
+    let git_timeout = Duration::from_secs(cli.git_timeout);
+    let mut timings = Timings::default();
+    let output_path = PathBuf::from(&cli.output);
+    // the hash is of the plan, so it's made the same way
+    let plan = cli.plan || cli.print_layout_hash;
+    // listing and planning don't touch the output
+    let previous_state = match &cli.state_dir {
+        Some(dir) => State::load(dir).await?,
+        None => None,
+    };
+    let keep_output = cli.resume || cli.only_changed || previous_state.is_some();
+    let pulls_output =
+        !cli.list && !cli.list_workflows && !plan && !cli.probe && cli.list_since.is_none();
+    let max_age = cli.clean_older_than;
+    if let (Some(max_age), true) = (max_age, pulls_output) {
+        clean_older_than(&output_path, max_age, &context.pulling).await?;
+    }
+    let staged_output = cli
+        .atomic_output
+        .then(|| sibling_path(&output_path, "magnesis-new"));
+    let create_path = match &staged_output {
+        Some(path) => path.display().to_string(),
+        None => cli.output.clone(),
+    };
+    let output = pulls_output.then(|| {
+        spawn(output::inherit(create_output(
+            create_path,
+            cli.gitignore,
+            max_age.is_some(),
+            keep_output,
+        )))
+    });
+    let mut git_cache = if cli.no_cache {
+        None
+    } else {
+        GitCache::load().await
+    };
+    let cached_repo = git_cache.as_ref().and_then(|cache| cache.repo.clone());
+    // and if the repo is derived from git, so it can be cached
+    let repo = spawn(output::inherit(timed(async move {
+        match (repo, cached_repo) {
+            (Some(repo), _) => Ok((repo, false)),
+            (None, Some(repo)) => Ok((repo, true)),
+            (None, None) => get_default_repo(git_timeout).await,
+        }
+    })));
+    let is_head = cli.rev == "HEAD";
+    let cached_head = git_cache
+        .as_ref()
+        .and_then(|cache| cache.head.clone())
+        .filter(|_| is_head);
+    let local_rev = (!cli.remote_rev
+        && !cli.probe
+        && cli.list_since.is_none()
+        && cli.check_suite.is_none()
+        && pr.is_none()
+        && run.is_none())
+    .then(|| {
+        let rev = cli.rev.clone();
+        spawn(output::inherit(timed(async move {
+            match cached_head {
+                Some(head) => Ok(head),
+                None => get_rev(rev, git_timeout).await,
+            }
+        })))
+    });
+
+    let retry_log_file = cli.retry_log_file.clone().map(Arc::from);
+    let api = Arc::new(Api::new(
+        token,
+        ApiOptions {
+            dump_raw: cli.dump_raw.as_ref().map(PathBuf::from),
+            list_retry: RetryPolicy {
+                retries: cli.list_retries,
+                timeout: None,
+                deadline: context.deadline,
+                log_file: retry_log_file.clone(),
+            },
+            download_retry: RetryPolicy {
+                retries: cli.retries,
+                timeout: cli.download_timeout.map(Duration::from_secs),
+                deadline: context.deadline,
+                log_file: retry_log_file,
+            },
+            insecure: cli.insecure,
+            ca_cert: cli.ca_cert.as_ref().map(PathBuf::from),
+            strict_schema: cli.strict_schema,
+            api_url: cli.api_url.clone(),
+            pin_cert: cli.pin_cert.clone(),
+            cancel: context.cancel.clone(),
+        },
+    )?);
+    api.verify_pin().await?;
+
+    let (repo, elapsed) = repo.await.change_context(Error::Repo)?;
+    timings.repo = elapsed;
+    let (repo, from_git) = repo.attach_printable(
+        "please specify the repo with --repo or see GitHub README for more details",
+    )?;
+    if cli.verify_repo {
+        check_origin_repo(&repo, git_timeout, cli.strict).await?;
+    }
+    if cli.probe {
+        let rate_limit = get_rate_limit(&api).await?;
+        print_probe(&repo, &rate_limit, cli.format);
+        return Ok(());
+    }
+    if let Some(max_age) = cli.list_since {
+        let artifacts = get_artifacts_since(&api, &repo, max_age).await?;
+        print_recent_artifacts(&api, &repo, &artifacts, cli.format).await?;
+        return Ok(());
+    }
+    progress!("getting artifacts from repo `{}`", repo);
+    if let Some(cache) = &mut git_cache {
+        if from_git {
+            cache.repo = Some(repo.clone());
+        }
+    }
+
+    let selection = Selection {
+        rev: cli.rev.clone(),
+        pr,
+        run,
+        check_suite: cli.check_suite,
+    };
+    let mut complete = BTreeSet::new();
+    let mut attempts = BTreeMap::new();
+    let (mut artifacts, rev) = match previous_state {
+        Some(state) => {
+            state.check_same_pull(&repo, &selection)?;
+            progress!(
+                "continuing pull of revision `{}` from saved state",
+                state.rev
+            );
+            complete = state.complete;
+            attempts = state.attempts;
+            (state.artifacts, state.rev)
+        }
+        None => {
+            let listing =
+                list_artifacts(&api, &repo, &cli, &selection, local_rev, &mut timings).await?;
+            if let Some(cache) = &mut git_cache {
+                if is_head && listing.rev_from_git {
+                    cache.head = Some(listing.rev.clone());
+                }
+            }
+            (listing.artifacts, listing.rev)
+        }
+    };
+    if let Some(cache) = &git_cache {
+        cache.save().await;
+    }
+    let listing = cli.state_dir.is_some().then(|| artifacts.clone());
+    if let Some(rewrite) = &cli.download_rewrite {
+        for artifact in &mut artifacts {
+            if let Some(url) = rewrite.apply(&artifact.archive_download_url) {
+                artifact.archive_download_url = url;
+            }
+        }
+    }
+    let start = Instant::now();
+    let (mut artifacts, marker) = filter_artifacts(&api, &repo, artifacts, &cli).await?;
+    timings.filter = start.elapsed();
+
+    let output = match output {
+        Some(output) => output.await.change_context(Error::CreateOutput)??,
+        None if plan => {
+            let plan = Plan {
+                repo,
+                output: output_path.clone(),
+                filters: Filters {
+                    check_suite: cli.check_suite,
+                    pr,
+                    run,
+                    name: cli.name.clone(),
+                    label: cli.label.clone(),
+                    job: cli.job.clone(),
+                    attempt: cli.attempt,
+                    contains: cli.contains.clone(),
+                    title_match: cli.title_match.clone(),
+                    changed_path: cli.changed_path.clone(),
+                    select_script: cli.select_script.clone(),
+                    index: cli.index.clone(),
+                    jobs: cli.jobs,
+                },
+                on_conflict: cli.on_conflict,
+                extraction: Extraction::from(&extract_options),
+                artifacts: artifacts
+                    .iter()
+                    .map(|artifact| PlannedArtifact::new(artifact, &output_path, &layout, &rev))
+                    .collect(),
+                rev,
+            };
+            if cli.print_layout_hash {
+                plan.print_hash(cli.format);
+            } else {
+                plan.print(cli.format);
+            }
+            return Ok(());
+        }
+        None if cli.list_workflows => {
+            print_workflow_list(&api, &repo, &artifacts, cli.format).await?;
+            return Ok(());
+        }
+        None => {
+            print_artifact_list(&artifacts, cli.format);
+            return Ok(());
+        }
+    };
+    progress!("created output at `{}`", output.display());
+
+    if artifacts.is_empty() {
+        if let Some(marker) = &marker {
+            progress!(
+                "no artifacts created after `{}`, nothing to download",
+                marker
+            );
+            return Ok(());
+        }
+        if cli.tolerate_missing && !has_workflow_runs(&api, &repo, &rev).await? {
+            progress!(
+                "no workflow runs found for revision `{}`, nothing to download",
+                rev
+            );
+            return Ok(());
+        }
+        return Err(report!(Error::GetArtifacts))
+            .attach_printable("no artifacts found for the specified revision");
+    }
+    progress!("found {} artifacts", artifacts.len());
+    let inventory = match &cli.against {
+        Some(path) => Some(Inventory::load(path.clone()).await?),
+        None => None,
+    };
+    if let Some(inventory) = &inventory {
+        artifacts.retain(|artifact| {
+            let known = inventory.contains(artifact);
+            if known {
+                progress!(
+                    "skipping `{}`, its digest is in the inventory",
+                    artifact.name
+                );
+            }
+            !known
+        });
+        if artifacts.is_empty() {
+            progress!("all artifacts are in the inventory, nothing to download");
+            return Ok(());
+        }
+    }
+    let newest = artifacts
+        .iter()
+        .filter_map(|artifact| artifact.created_at.clone())
+        .max();
+
+    if cli.metadata_only {
+        // the artifacts are sorted unless --index picked the order
+        if !cli.index.is_empty() {
+            sort_artifacts(&mut artifacts);
+        }
+        let path = output.join("metadata.json");
+        let json = serde_json::to_vec_pretty(&artifacts).change_context(Error::Metadata)?;
+        fs::write(&path, json)
+            .await
+            .change_context(Error::Metadata)
+            .attach_printable_lazy(|| format!("path: {}", path.display()))?;
+        progress!("saved metadata to `{}`", path.display());
+        return Ok(());
+    }
+
+    if cli.require_digest {
+        check_digests(&artifacts)?;
+    }
+
+    let previous = if cli.resume {
+        let previous = Manifest::load(&output).await?;
+        if let Some(previous) = &previous {
+            previous.check_same_source(&repo, &rev)?;
+        }
+        previous
+    } else {
+        None
+    };
+    let manifest = (cli.manifest || cli.resume).then(|| {
+        Manifest::new(
+            repo.clone(),
+            rev.clone(),
+            artifacts
+                .iter()
+                .map(|artifact| {
+                    let previous = previous
+                        .as_ref()
+                        .and_then(|previous| previous.complete_entry(artifact.id));
+                    ManifestEntry {
+                        id: artifact.id,
+                        name: artifact.name.clone(),
+                        path: layout.destination(&artifact.name, &rev),
+                        complete: previous.is_some(),
+                        digest: artifact.digest.clone(),
+                        normalized_zip_sha256: previous
+                            .and_then(|entry| entry.normalized_zip_sha256.clone()),
+                    }
+                })
+                .collect(),
+        )
+    });
+    if let Some(manifest) = &manifest {
+        manifest.save(&output).await?;
+    }
+
+    if cli.only_changed {
+        extract_options.previous_sums = Some(read_sums(&output).await?);
+    }
+    let state = match (&cli.state_dir, listing) {
+        (Some(dir), Some(listing)) => {
+            let state = State {
+                repo: repo.clone(),
+                selection,
+                rev: rev.clone(),
+                artifacts: listing,
+                complete,
+                attempts,
+            };
+            state.save(dir).await?;
+            Some(state)
+        }
+        _ => None,
+    };
+    let run_ids = artifacts
+        .iter()
+        .filter_map(|artifact| artifact.workflow_run.id)
+        .collect::<BTreeSet<_>>();
+    let mut records = Records {
+        manifest,
+        state,
+        inventory,
+        webhook: cli.progress_webhook.clone().map(Webhook::new),
+    };
+    let pull = Pull {
+        repo: &repo,
+        rev: &rev,
+        output: &output,
+        layout: &layout,
+    };
+    let downloads =
+        download_artifacts(&api, &cli, &pull, artifacts, extract_options, &mut records).await?;
+    timings.artifacts = downloads.timings;
+    let mut sizes = downloads.sizes;
+
+    if let Some(path) = &cli.sbom {
+        Sbom::new(downloads.components).save(path).await?;
+        progress!("saved SBOM to `{}`", path.display());
+    }
+
+    if cli.with_summaries {
+        let dir = output.join("summaries");
+        let count = summaries::save_summaries(&api, &repo, &run_ids, &dir).await?;
+        progress!("saved {} job summaries to `{}`", count, dir.display());
+    }
+
+    if let Some(verify_cmd) = &cli.verify_cmd {
+        run_verify_cmd(verify_cmd, &output).await?;
+    }
+
+    if let (Some(dir), Some(_)) = (&cli.state_dir, &records.state) {
+        State::remove(dir).await?;
+    }
+
+    let output = match &staged_output {
+        Some(staged) => {
+            swap_output(staged, &output_path).await?;
+            output_path
+        }
+        None => output,
+    };
+
+    if let Some(owner) = cli.owner {
+        owner::chown(output.clone(), owner).await?;
+        progress!(
+            "changed owner of output at `{}` to {}:{}",
+            output.display(),
+            owner.uid,
+            owner.gid
+        );
+    }
+
+    if cli.freeze {
+        freeze::freeze(output.clone(), cli.freeze_keep_metadata).await?;
+        progress!("made output at `{}` read-only", output.display());
+    }
+
+    if let (Some(path), Some(newest)) = (&cli.newer_than_file, newest) {
+        fs::write(path, format!("{}\n", newest))
+            .await
+            .change_context(Error::Marker)
+            .attach_printable_lazy(|| format!("path: {}", path.display()))?;
+    }
+
+    if let Some(path) = &cli.emit_env {
+        let output = output.display().to_string();
+        let count = timings.artifacts.len().to_string();
+        write_env_file(
+            path,
+            &[
+                ("MAGNESIS_OUTPUT_DIR", &output),
+                ("MAGNESIS_REPO", &repo),
+                ("MAGNESIS_REV", &rev),
+                ("MAGNESIS_ARTIFACT_COUNT", &count),
+            ],
+        )
+        .await?;
+    }
+
+    if let Some(webhook) = &records.webhook {
+        let event = WebhookEvent::End {
+            downloaded: timings.artifacts.len(),
+        };
+        webhook.post(&event).await;
+    }
+
+    if cli.github_actions {
+        actions::notice(&format!(
+            "downloaded {} artifacts from `{}` at `{}`",
+            timings.artifacts.len(),
+            repo,
+            rev
+        ));
+    }
+
+    if cli.trace_timings {
+        timings.print();
+    }
+
+    if cli.step_summary {
+        actions::write_step_summary(&sizes.markdown(&repo, &rev, &downloads.skipped)).await?;
+    }
+
+    if cli.output_size_report {
+        sizes.print();
+    }
+
+    if cli.print_rate_limit {
+        let rate_limit = match api.last_rate_limit() {
+            Some(rate_limit) => rate_limit,
+            None => get_rate_limit(&api).await?,
+        };
+        print_rate_limit_remaining(&rate_limit);
+    }
+
+    Ok(())
+}
+
+/// Options for extracting the artifacts, before the ones that depend on the pull are set
+fn extract_options(cli: &Cli) -> ExtractOptions {
+    let normalized_zip = cli
+        .normalized_zip
+        .clone()
+        .map(|dir| dir.unwrap_or_else(|| sibling_path(Path::new(&cli.output), "normalized")));
+    ExtractOptions {
+        on_conflict: cli.on_conflict,
+        on_case_collision: cli.on_case_collision,
+        symlink_policy: cli.symlink_policy,
+        zip_index: cli.zip_index,
+        normalized_zip,
+        inner_path: cli.inner_path.clone(),
+        max_entries: Some(cli.max_entries),
+        max_uncompressed: Some(cli.max_uncompressed),
+        normalize_eol: cli.normalize_eol,
+        text_globs: cli.text_glob.clone(),
+        file_mode: cli.file_mode,
+        dir_mode: cli.dir_mode,
+        hash: cli.only_changed,
+        previous_sums: None,
+        expect_files: cli.expect_files.clone(),
+        written: Default::default(),
+        case_paths: Default::default(),
+        shared_cache: cli.shared_cache.clone().map(SharedCache::new),
+        dedup: None,
+        sink: None,
+    }
+}
+
+/// Pull the two revisions into subdirectories of the output and print the difference
+async fn compare(cli: Cli, revs: Vec<String>, context: &RunContext) -> Result<(), Error> {
+    pull_revs(&cli, &revs, context).await?;
+    // clap makes sure there are 2 revisions
+    let diff = Diff::new(rev_output(&cli, &revs[0]), rev_output(&cli, &revs[1])).await?;
+    diff.print(cli.format);
+    Ok(())
+}
+
+/// Pull each revision into its own subdirectory of the output, at the same time
+async fn pull_revs(cli: &Cli, revs: &[String], context: &RunContext) -> Result<(), Error> {
+    let mut outputs = BTreeMap::new();
+    for rev in revs {
+        let output = rev_output(cli, rev);
+        if let Some(other) = outputs.insert(output.clone(), rev) {
+            return Err(report!(Error::CreateOutput)).attach_printable(format!(
+                "`{}` and `{}` would both be pulled into `{}`",
+                other,
+                rev,
+                output.display()
+            ));
+        }
+    }
+    // created once here, so the pulls don't race to create it
+    fs::create_dir_all(&cli.output)
+        .await
+        .change_context(Error::CreateOutput)?;
+
+    // so --clean-older-than in one pull doesn't remove the others
+    let context = RunContext {
+        pulling: Arc::new(outputs.keys().cloned().collect()),
+        ..context.clone()
+    };
+    let mut handles = JoinSet::new();
+    for (output, rev) in outputs {
+        progress!("pulling `{}` into `{}`", rev, output.display());
+        let cli = Cli {
+            output: output.display().to_string(),
+            rev: rev.clone(),
+            ..cli.clone()
+        };
+        let context = context.clone();
+        handles.spawn(output::inherit(async move {
+            main_internal(cli, &context).await
+        }));
+    }
+    while let Some(result) = handles.join_next().await {
+        result.change_context(Error::DownloadArtifact)??;
+    }
+    Ok(())
+}
+
+/// Subdirectory of the output for the revision
+fn rev_output(cli: &Cli, rev: &str) -> PathBuf {
+    // branches can have `/` in them
+    PathBuf::from(&cli.output).join(rev.replace('/', "_"))
+}
+
+/// Get the repo from the origin remote, or from `GITHUB_REPOSITORY` (set in GitHub Actions)
+/// if that fails, with the reasons from both if neither works
+///
+/// Also returns if the repo is from git
+async fn get_default_repo(git_timeout: Duration) -> Result<(String, bool), Error> {
+    let origin = get_repo(git_timeout).await;
+    default_repo(origin, std::env::var("GITHUB_REPOSITORY").ok())
+}
+
+fn default_repo(
+    origin: Result<String, Error>,
+    github_repository: Option<String>,
+) -> Result<(String, bool), Error> {
+    let err = match origin {
+        Ok(repo) => return Ok((repo, true)),
+        Err(err) => err,
+    };
+    let reason = match github_repository {
+        Some(repo)
+            if repo
+                .split_once('/')
+                .is_some_and(|(owner, name)| !owner.is_empty() && !name.is_empty()) =>
+        {
+            progress!("using repo `{}` from GITHUB_REPOSITORY", repo);
+            return Ok((repo, false));
+        }
+        Some(repo) => format!("GITHUB_REPOSITORY is not `OWNER/REPO`: {}", repo),
+        None => "GITHUB_REPOSITORY is not set".to_string(),
+    };
+    Err(err.attach_printable(reason))
+}
+
+/// Check the repo against the origin remote for --verify-repo
+async fn check_origin_repo(repo: &str, git_timeout: Duration, strict: bool) -> Result<(), Error> {
+    let origin = match get_repo(git_timeout).await {
+        Ok(origin) => origin,
+        Err(err) => {
+            warning!("could not get the origin repo for --verify-repo: {}", err);
+            return Ok(());
+        }
+    };
+    verify_repo_matches(repo, &origin, strict)
+}
+
+/// Warn if the repo is not the origin repo, or fail with --strict
+fn verify_repo_matches(repo: &str, origin: &str, strict: bool) -> Result<(), Error> {
+    if origin.eq_ignore_ascii_case(repo) {
+        return Ok(());
+    }
+    if strict {
+        return Err(report!(Error::Repo)).attach_printable(format!(
+            "--repo `{}` is not the origin repo `{}`",
+            repo, origin
+        ));
+    }
+    warning!("--repo `{}` is not the origin repo `{}`", repo, origin);
+    Ok(())
+}
+
+/// Fail if --strict is used and there were warnings
+fn check_strict(strict: bool) -> Result<(), Error> {
+    let count = output::warning_count();
+    if strict && count > 0 {
+        return Err(report!(Error::Strict))
+            .attach_printable(format!("there were {} warnings", count));
+    }
+    Ok(())
+}
+
+/// Append the variables to the --emit-env file
+async fn write_env_file(path: &Path, vars: &[(&str, &str)]) -> Result<(), Error> {
+    let content = vars
+        .iter()
+        .map(|(key, value)| env_var_line(key, value))
+        .collect::<String>();
+    let write = async {
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await?;
+        file.write_all(content.as_bytes()).await?;
+        // the write finishes in the background otherwise
+        file.flush().await
+    };
+    write
+        .await
+        .change_context(Error::EmitEnv)
+        .attach_printable_lazy(|| format!("path: {}", path.display()))
+}
+
+/// Format the variable for a `GITHUB_ENV`-style file
+///
+/// Values with line breaks use the `KEY<<DELIMITER` form, with a delimiter that
+/// doesn't appear in the value, so they can't add other variables
+fn env_var_line(key: &str, value: &str) -> String {
+    if !value.contains(['\n', '\r']) {
+        return format!("{}={}\n", key, value);
+    }
+    let mut seed = format!("{}:{}:{:?}", key, std::process::id(), SystemTime::now());
+    let delimiter = loop {
+        let delimiter = format!("MAGNESIS_EOF_{}", &sha256_hex(seed.as_bytes())[..16]);
+        if !value.contains(&delimiter) {
+            break delimiter;
+        }
+        seed = delimiter;
+    };
+    format!("{}<<{}\n{}\n{}\n", key, delimiter, value, delimiter)
+}
+
+/// Run the --verify-cmd in a shell, failing if it doesn't succeed
+async fn run_verify_cmd(cmd: &str, output: &Path) -> Result<(), Error> {
+    progress!("running verify command `{}`", cmd);
+    let mut command = if cfg!(windows) {
+        let mut command = Command::new("cmd");
+        command.arg("/C").arg(cmd);
+        command
+    } else {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(cmd);
+        command
+    };
+    let status = command
+        .env("MAGNESIS_OUTPUT_DIR", output)
+        .status()
+        .await
+        .change_context(Error::Command)
+        .attach_printable_lazy(|| format!("command: {}", cmd))?;
+    if !status.success() {
+        return Err(report!(Error::Command))
+            .attach_printable(format!("command: {}", cmd))
+            .attach_printable(format!("status: {}", status));
+    }
+    Ok(())
+}
+
+fn get_token() -> Result<String, Error> {
+    let message = "please specify the PAT in the GITHUB_TOKEN environment variable";
+    let token = std::env::var("GITHUB_TOKEN")
+        .change_context(Error::NoToken)
+        .attach_printable(message)?;
+    if token.is_empty() {
+        return Err(report!(Error::NoToken)).attach_printable(message);
+    }
+    Ok(token)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        OnceLock,
+    };
+
+    use super::*;
+    use crate::test_util::{zip_file, InFlight, MockServer, Response, TempDir};
+
+    #[tokio::test]
+    async fn test_run_verify_cmd() {
+        let dir = TempDir::new();
+        std::fs::write(dir.join("a.txt"), "a").unwrap();
+        run_verify_cmd("test -f \"$MAGNESIS_OUTPUT_DIR/a.txt\"", &dir.join(""))
+            .await
+            .unwrap();
+        let err = run_verify_cmd("test -f \"$MAGNESIS_OUTPUT_DIR/b.txt\"", &dir.join(""))
+            .await
+            .unwrap_err();
+        assert!(format!("{:?}", err).contains("status: exit status: 1"));
+    }
+
+    #[tokio::test]
+    async fn test_check_strict() {
+        output::scope(Output::new(output::Format::Text, false), async {
+            assert!(check_strict(true).is_ok());
+            warning!("something is off");
+            assert!(check_strict(false).is_ok());
+            let err = check_strict(true).unwrap_err();
+            assert!(matches!(err.current_context(), Error::Strict));
+        })
+        .await;
+    }
+
+    #[test]
+    fn test_rev_output() {
+        let cli = Config::builder()
+            .output("out")
+            .arg("--branches")
+            .arg("main,release/1.0")
+            .build()
+            .unwrap()
+            .cli;
+        assert_eq!(
+            cli.branches.as_deref(),
+            Some(&["main".to_string(), "release/1.0".to_string()][..])
+        );
+        assert_eq!(rev_output(&cli, "main"), Path::new("out/main"));
+        assert_eq!(
+            rev_output(&cli, "release/1.0"),
+            Path::new("out/release_1.0")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_write_env_file() {
+        let dir = TempDir::new();
+        let path = dir.join("env");
+        std::fs::write(&path, "EXISTING=1\n").unwrap();
+        write_env_file(
+            &path,
+            &[("MAGNESIS_REPO", "foo/bar"), ("MAGNESIS_REV", "abc")],
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "EXISTING=1\nMAGNESIS_REPO=foo/bar\nMAGNESIS_REV=abc\n"
+        );
+        let err = write_env_file(&dir.join("missing/env"), &[])
+            .await
+            .unwrap_err();
+        assert!(matches!(err.current_context(), Error::EmitEnv));
+    }
+
+    #[test]
+    fn test_verify_repo_matches() {
+        assert!(verify_repo_matches("foo/bar", "foo/bar", true).is_ok());
+        // GitHub names are case-insensitive
+        assert!(verify_repo_matches("Foo/Bar", "foo/bar", true).is_ok());
+        let err = verify_repo_matches("foo/other", "foo/bar", true).unwrap_err();
+        let err = format!("{:?}", err);
+        assert!(
+            err.contains("--repo `foo/other` is not the origin repo `foo/bar`"),
+            "{}",
+            err
+        );
+        // only a warning without --strict
+        assert!(verify_repo_matches("foo/other", "foo/bar", false).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_pull_revs_collision() {
+        let dir = TempDir::new();
+        let output = dir.join("out").display().to_string();
+        let cli = Cli::try_parse_from(["magnesis", "--output", &output]).unwrap();
+        let revs = ["feature/a".to_string(), "feature_a".to_string()];
+        let err = pull_revs(&cli, &revs, &RunContext::default())
+            .await
+            .unwrap_err();
+        let err = format!("{:?}", err);
+        assert!(
+            err.contains("`feature/a` and `feature_a` would both be pulled into"),
+            "{}",
+            err
+        );
+        // nothing is pulled
+        assert!(!dir.join("out").exists());
+    }
+
+    #[test]
+    fn test_default_repo() {
+        let no_origin = || Err(report!(Error::Repo).attach_printable("no remote named `origin`"));
+        // git comes first
+        let repo = default_repo(Ok("foo/git".to_string()), Some("foo/env".to_string()));
+        assert_eq!(repo.unwrap(), ("foo/git".to_string(), true));
+        let repo = default_repo(no_origin(), Some("foo/env".to_string()));
+        assert_eq!(repo.unwrap(), ("foo/env".to_string(), false));
+
+        // the reasons from both
+        let err = format!("{:?}", default_repo(no_origin(), None).unwrap_err());
+        assert!(err.contains("no remote named `origin`"), "{}", err);
+        assert!(err.contains("GITHUB_REPOSITORY is not set"), "{}", err);
+        let err = default_repo(no_origin(), Some("foo".to_string())).unwrap_err();
+        let err = format!("{:?}", err);
+        assert!(err.contains("no remote named `origin`"), "{}", err);
+        assert!(
+            err.contains("GITHUB_REPOSITORY is not `OWNER/REPO`: foo"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_env_var_line() {
+        assert_eq!(env_var_line("KEY", "value"), "KEY=value\n");
+    }
+
+    #[test]
+    fn test_env_var_line_multiline() {
+        let line = env_var_line("KEY", "a\nINJECTED=1");
+        let (first, rest) = line.split_once('\n').unwrap();
+        let delimiter = first.strip_prefix("KEY<<").unwrap();
+        assert!(!delimiter.is_empty());
+        assert_eq!(rest, format!("a\nINJECTED=1\n{}\n", delimiter));
+        assert!(env_var_line("KEY", "a\rb").starts_with("KEY<<"));
+    }
+
+    #[tokio::test]
+    async fn test_tolerate_missing() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let sha = "a".repeat(40);
+        let server = {
+            let runs = Arc::clone(&runs);
+            let runs_path = format!("/repos/foo/bar/actions/runs?head_sha={}&per_page=1", sha);
+            MockServer::start(move |request| match request.path.as_str() {
+                "/repos/foo/bar/actions/artifacts?per_page=100&page=1" => {
+                    Response::json(r#"{"total_count":0,"artifacts":[]}"#)
+                }
+                path if path == runs_path => Response::json(format!(
+                    r#"{{"total_count":{},"workflow_runs":[]}}"#,
+                    runs.load(Ordering::SeqCst)
+                )),
+                _ => Response::new(404),
+            })
+            .await
+        };
+        std::env::set_var("GITHUB_TOKEN", "token");
+        let dir = TempDir::new();
+        let config = |tolerate: bool| {
+            let mut builder = Config::builder()
+                .repo("foo/bar")
+                .rev(&sha)
+                .output(dir.join("out"))
+                .arg("--api-url")
+                .arg(server.url(""))
+                .arg("--remote-rev")
+                .arg("--no-cache")
+                .arg("--quiet");
+            if tolerate {
+                builder = builder.arg("--tolerate-missing");
+            }
+            builder.build().unwrap()
+        };
+
+        // no run at all is only an error without the flag
+        run(config(true)).await.unwrap();
+        let err = run(config(false)).await.unwrap_err();
+        assert!(matches!(err.current_context(), Error::GetArtifacts));
+        // runs without artifacts are still an error
+        runs.store(1, Ordering::SeqCst);
+        let err = run(config(true)).await.unwrap_err();
+        assert!(matches!(err.current_context(), Error::GetArtifacts));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_pull_revs_concurrently() {
+        let base_url = Arc::new(OnceLock::<String>::new());
+        let in_flight = Arc::new(InFlight::default());
+        let server = {
+            let base_url = Arc::clone(&base_url);
+            let in_flight = Arc::clone(&in_flight);
+            MockServer::start(move |request| {
+                let base_url = base_url.get().unwrap();
+                match request.path.as_str() {
+                    "/repos/foo/bar/commits/main" => in_flight
+                        .answer(|| Response::json(format!(r#"{{"sha":"{}"}}"#, "a".repeat(40)))),
+                    "/repos/foo/bar/commits/release/1.0" => in_flight
+                        .answer(|| Response::json(format!(r#"{{"sha":"{}"}}"#, "b".repeat(40)))),
+                    "/repos/foo/bar/actions/artifacts?per_page=100&page=1" => {
+                        let artifact = |id: u64, sha: &str| {
+                            serde_json::json!({
+                                "id": id,
+                                "name": "app",
+                                "archive_download_url": format!("{}/download/{}", base_url, id),
+                                "workflow_run": { "head_sha": sha.repeat(40) },
+                            })
+                        };
+                        let listing = serde_json::json!({
+                            "total_count": 2,
+                            "artifacts": [artifact(1, "a"), artifact(2, "b")],
+                        });
+                        Response::json(listing.to_string())
+                    }
+                    "/download/1" => Response::new(200).body(zip_file(&[("app.txt", b"main")])),
+                    "/download/2" => Response::new(200).body(zip_file(&[("app.txt", b"release")])),
+                    _ => Response::new(404),
+                }
+            })
+            .await
+        };
+        base_url.set(server.url("")).unwrap();
+        std::env::set_var("GITHUB_TOKEN", "token");
+
+        let dir = TempDir::new();
+        let output = dir.join("out");
+        // all pulled an hour ago, but --clean-older-than only removes `old`
+        for name in ["old", "main", "release_1.0"] {
+            std::fs::create_dir_all(output.join(name)).unwrap();
+            std::fs::File::create(output.join(name).join(retention::MARKER))
+                .unwrap()
+                .set_modified(SystemTime::now() - Duration::from_secs(3600))
+                .unwrap();
+        }
+        let config = Config::builder()
+            .repo("foo/bar")
+            .output(&output)
+            .arg("--api-url")
+            .arg(server.url(""))
+            .arg("--branches")
+            .arg("main,release/1.0")
+            .arg("--remote-rev")
+            .arg("--no-cache")
+            .arg("--clean-older-than")
+            .arg("0s")
+            .arg("--quiet")
+            .build()
+            .unwrap();
+        run(config).await.unwrap();
+
+        // the revisions are resolved at the same time
+        assert_eq!(in_flight.max(), 2);
+        // and neither pull removes the output of the other
+        let read = |path: &str| std::fs::read_to_string(output.join(path)).unwrap();
+        assert_eq!(read("main/app/app.txt"), "main");
+        assert_eq!(read("release_1.0/app/app.txt"), "release");
+        assert!(output.join("main").join(retention::MARKER).exists());
+        assert!(output.join("release_1.0").join(retention::MARKER).exists());
+        assert!(!output.join("old").exists());
+    }
+}
@@ -0,0 +1,342 @@
+//! Printing what would be pulled instead of pulling it, for --list, --list-workflows,
+//! --list-since and --probe
+
+use std::{
+    collections::{hash_map::Entry, BTreeMap, HashMap},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use error_stack::Result;
+
+use crate::{
+    artifact::Artifact,
+    github::{get_run, Api, RateLimit},
+    output::{info, print_json, print_json_line, Format},
+    Error,
+};
+
+/// Print the artifacts with the indices for --index, for --list
+pub fn print_artifact_list(artifacts: &[Artifact], format: Format) {
+    #[derive(serde::Serialize)]
+    struct ListItem<'a> {
+        index: usize,
+        id: u64,
+        name: &'a str,
+        label: Option<&'a str>,
+    }
+    let items = artifacts.iter().enumerate().map(|(i, artifact)| ListItem {
+        index: i + 1,
+        id: artifact.id,
+        name: &artifact.name,
+        label: artifact.label(),
+    });
+    match format {
+        Format::Json => {
+            print_json(&items.collect::<Vec<_>>());
+            return;
+        }
+        Format::Jsonl => {
+            items.for_each(|item| print_json_line(&item));
+            return;
+        }
+        Format::Text => {}
+    }
+    if artifacts.is_empty() {
+        println!("no artifacts found for the specified revision");
+        return;
+    }
+    for (i, artifact) in artifacts.iter().enumerate() {
+        match artifact.label() {
+            Some(label) => println!("{:>4}  {} ({})", i + 1, artifact.display_name(), label),
+            None => println!("{:>4}  {}", i + 1, artifact.name),
+        }
+    }
+}
+
+/// Seconds since the Unix epoch
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Print the remaining rate limit for --print-rate-limit
+pub fn print_rate_limit_remaining(rate_limit: &RateLimit) {
+    info!(
+        "rate limit: {}/{} remaining, resets in {}s",
+        rate_limit.remaining,
+        rate_limit.limit,
+        rate_limit.reset.saturating_sub(unix_now())
+    );
+}
+
+pub fn print_probe(repo: &str, rate_limit: &RateLimit, format: Format) {
+    #[derive(serde::Serialize)]
+    struct ProbeItem<'a> {
+        repo: &'a str,
+        rate_limit: &'a RateLimit,
+    }
+    let item = ProbeItem { repo, rate_limit };
+    match format {
+        Format::Json => print_json(&item),
+        Format::Jsonl => print_json_line(&item),
+        Format::Text => {
+            println!("repo: {}", repo);
+            println!(
+                "rate limit: {}/{} remaining, resets in {}s",
+                rate_limit.remaining,
+                rate_limit.limit,
+                rate_limit.reset.saturating_sub(unix_now())
+            );
+            match &rate_limit.scopes {
+                Some(scopes) => println!("scopes: {}", scopes.join(", ")),
+                None => println!("scopes: not reported (fine-grained or GitHub Actions token)"),
+            }
+        }
+    }
+}
+
+/// Artifacts uploaded by runs of a workflow, for --list-workflows
+#[derive(Debug, PartialEq, Eq, serde::Serialize)]
+struct WorkflowItem {
+    name: String,
+    path: String,
+    artifacts: usize,
+}
+
+/// Print the workflows of the runs that uploaded the artifacts, for --list-workflows
+pub async fn print_workflow_list(
+    api: &Api,
+    repo: &str,
+    artifacts: &[Artifact],
+    format: Format,
+) -> Result<(), Error> {
+    let items = count_workflows(api, repo, artifacts).await?;
+    match format {
+        Format::Json => print_json(&items),
+        Format::Jsonl => items.iter().for_each(print_json_line),
+        Format::Text => {
+            if items.is_empty() {
+                println!("no artifacts found for the specified revision");
+            }
+            for item in items {
+                println!("{:>4}  {} ({})", item.artifacts, item.name, item.path);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Count the artifacts of each workflow, sorted by workflow. Each run is looked up once
+async fn count_workflows(
+    api: &Api,
+    repo: &str,
+    artifacts: &[Artifact],
+) -> Result<Vec<WorkflowItem>, Error> {
+    let mut runs = BTreeMap::new();
+    for artifact in artifacts {
+        if let Some(id) = artifact.workflow_run.id {
+            *runs.entry(id).or_insert(0) += 1;
+        }
+    }
+    let mut workflows = BTreeMap::<(String, String), usize>::new();
+    for (id, count) in runs {
+        let run = get_run(api, repo, id).await?;
+        let key = (run.name.unwrap_or_default(), run.path.unwrap_or_default());
+        *workflows.entry(key).or_default() += count;
+    }
+    Ok(workflows
+        .into_iter()
+        .map(|((name, path), artifacts)| WorkflowItem {
+            name,
+            path,
+            artifacts,
+        })
+        .collect())
+}
+
+#[derive(Debug, serde::Serialize)]
+struct RecentArtifact<'a> {
+    id: u64,
+    name: &'a str,
+    created_at: Option<&'a str>,
+}
+
+/// Artifacts from the same workflow and commit, for --list-since
+#[derive(Debug, serde::Serialize)]
+struct RecentGroup<'a> {
+    workflow: String,
+    path: String,
+    commit: &'a str,
+    artifacts: Vec<RecentArtifact<'a>>,
+}
+
+/// Print the artifacts grouped by the workflow and commit of their run, for --list-since
+pub async fn print_recent_artifacts(
+    api: &Api,
+    repo: &str,
+    artifacts: &[Artifact],
+    format: Format,
+) -> Result<(), Error> {
+    // name and path of the workflow of each run
+    let mut workflows = HashMap::<u64, (String, String)>::new();
+    for id in artifacts
+        .iter()
+        .filter_map(|artifact| artifact.workflow_run.id)
+    {
+        if let Entry::Vacant(entry) = workflows.entry(id) {
+            let run = get_run(api, repo, id).await?;
+            entry.insert((run.name.unwrap_or_default(), run.path.unwrap_or_default()));
+        }
+    }
+    let groups = group_recent_artifacts(artifacts, &workflows);
+    match format {
+        Format::Json => print_json(&groups),
+        Format::Jsonl => groups.iter().for_each(print_json_line),
+        Format::Text => {
+            if groups.is_empty() {
+                println!("no artifacts created in the time range");
+            }
+            for group in groups {
+                println!("{} ({}) at {}", group.workflow, group.path, group.commit);
+                for artifact in group.artifacts {
+                    println!(
+                        "  {}  {}",
+                        artifact.created_at.unwrap_or_default(),
+                        artifact.name
+                    );
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Group the artifacts by the workflow and commit of their run, sorted by workflow
+fn group_recent_artifacts<'a>(
+    artifacts: &'a [Artifact],
+    workflows: &HashMap<u64, (String, String)>,
+) -> Vec<RecentGroup<'a>> {
+    let mut groups = BTreeMap::<(String, String, &str), Vec<RecentArtifact>>::new();
+    for artifact in artifacts {
+        let (workflow, path) = artifact
+            .workflow_run
+            .id
+            .and_then(|id| workflows.get(&id).cloned())
+            .unwrap_or_default();
+        groups
+            .entry((workflow, path, &artifact.workflow_run.head_sha))
+            .or_default()
+            .push(RecentArtifact {
+                id: artifact.id,
+                name: &artifact.name,
+                created_at: artifact.created_at.as_deref(),
+            });
+    }
+    groups
+        .into_iter()
+        .map(|((workflow, path, commit), artifacts)| RecentGroup {
+            workflow,
+            path,
+            commit,
+            artifacts,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_util::{mock_api, MockServer, Response};
+
+    #[tokio::test]
+    async fn test_count_workflows() {
+        let server = MockServer::start(|request| {
+            let run = |id: u64, name: &str| {
+                Response::json(format!(
+                    r#"{{"id":{},"head_sha":"abc","name":"{}","path":".github/workflows/{}.yml"}}"#,
+                    id, name, name
+                ))
+            };
+            match request.path.as_str() {
+                "/repos/foo/bar/actions/runs/10" => run(10, "build"),
+                "/repos/foo/bar/actions/runs/11" => run(11, "build"),
+                "/repos/foo/bar/actions/runs/20" => run(20, "docs"),
+                _ => Response::new(404),
+            }
+        })
+        .await;
+        let artifact = |id: u64, run_id: u64| -> Artifact {
+            serde_json::from_value(serde_json::json!({
+                "id": id,
+                "name": format!("app-{}", id),
+                "archive_download_url": "",
+                "workflow_run": { "id": run_id, "head_sha": "abc" },
+            }))
+            .unwrap()
+        };
+        let artifacts = [
+            artifact(1, 10),
+            artifact(2, 20),
+            artifact(3, 10),
+            // another run of the same workflow
+            artifact(4, 11),
+        ];
+
+        let items = count_workflows(&mock_api(&server), "foo/bar", &artifacts)
+            .await
+            .unwrap();
+        let item = |name: &str, artifacts: usize| WorkflowItem {
+            name: name.to_string(),
+            path: format!(".github/workflows/{}.yml", name),
+            artifacts,
+        };
+        assert_eq!(items, [item("build", 3), item("docs", 1)]);
+        assert_eq!(server.requests().len(), 3);
+    }
+
+    #[test]
+    fn test_group_recent_artifacts() {
+        let artifact = |id: u64, run_id: Option<u64>, head_sha: &str| -> Artifact {
+            serde_json::from_value(serde_json::json!({
+                "id": id,
+                "name": format!("app-{}", id),
+                "archive_download_url": "",
+                "workflow_run": { "id": run_id, "head_sha": head_sha },
+            }))
+            .unwrap()
+        };
+        let artifacts = [
+            artifact(1, Some(10), "abc"),
+            artifact(2, Some(20), "abc"),
+            // another run of the same workflow at the same commit
+            artifact(3, Some(11), "abc"),
+            artifact(4, Some(10), "def"),
+            artifact(5, None, "abc"),
+        ];
+        let workflow = |name: &str| (name.to_string(), format!(".github/workflows/{}.yml", name));
+        let workflows = HashMap::from([
+            (10, workflow("build")),
+            (11, workflow("build")),
+            (20, workflow("docs")),
+        ]);
+        let groups = group_recent_artifacts(&artifacts, &workflows)
+            .into_iter()
+            .map(|group| {
+                let ids = group.artifacts.iter().map(|a| a.id).collect::<Vec<_>>();
+                (group.workflow, group.commit, ids)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            groups,
+            [
+                (String::new(), "abc", vec![5]),
+                ("build".to_string(), "abc", vec![1, 3]),
+                ("build".to_string(), "def", vec![4]),
+                ("docs".to_string(), "abc", vec![2]),
+            ]
+        );
+    }
+}
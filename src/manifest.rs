@@ -0,0 +1,176 @@
+use std::path::{Path, PathBuf};
+
+use error_stack::{report, Result, ResultExt};
+use tokio::fs;
+
+use crate::{atomic_file, Error};
+
+/// Record of the artifacts pulled into the output, saved as `manifest.json`
+///
+/// It is updated as each artifact finishes, so an interrupted pull
+/// can be continued with --resume.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct Manifest {
+    pub repo: String,
+    pub rev: String,
+    pub artifacts: Vec<ManifestEntry>,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct ManifestEntry {
+    pub id: u64,
+    pub name: String,
+    /// Where the artifact is extracted to, relative to the output directory
+    pub path: PathBuf,
+    pub complete: bool,
+    /// Digest of the archive from the API, like `sha256:...`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub digest: Option<String>,
+    /// SHA-256 of the normalized archive, for --normalized-zip
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub normalized_zip_sha256: Option<String>,
+}
+
+impl Manifest {
+    pub const FILE_NAME: &'static str = "manifest.json";
+
+    /// Create a manifest with the entries sorted by name, so the file doesn't
+    /// depend on the order of --index or the order downloads finish
+    pub fn new(repo: String, rev: String, mut artifacts: Vec<ManifestEntry>) -> Self {
+        artifacts.sort_by(|a, b| a.name.cmp(&b.name).then(a.id.cmp(&b.id)));
+        Self {
+            repo,
+            rev,
+            artifacts,
+        }
+    }
+
+    /// Load the manifest from the output directory, if there is one
+    pub async fn load(output: &Path) -> Result<Option<Self>, Error> {
+        let path = output.join(Self::FILE_NAME);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let bytes = fs::read(&path)
+            .await
+            .change_context(Error::Manifest)
+            .attach_printable_lazy(|| format!("path: {}", path.display()))?;
+        let manifest = serde_json::from_slice(&bytes)
+            .change_context(Error::Manifest)
+            .attach_printable_lazy(|| format!("path: {}", path.display()))?;
+        Ok(Some(manifest))
+    }
+
+    /// Check the manifest is from a pull of the same repo and revision
+    pub fn check_same_source(&self, repo: &str, rev: &str) -> Result<(), Error> {
+        if self.repo != repo || self.rev != rev {
+            return Err(report!(Error::Manifest))
+                .attach_printable(format!(
+                    "the existing manifest is for `{}` at `{}`",
+                    self.repo, self.rev
+                ))
+                .attach_printable("run without --resume to start over");
+        }
+        Ok(())
+    }
+
+    pub fn is_complete(&self, id: u64) -> bool {
+        self.complete_entry(id).is_some()
+    }
+
+    /// Get the entry of the artifact, if it's complete
+    pub fn complete_entry(&self, id: u64) -> Option<&ManifestEntry> {
+        self.artifacts
+            .iter()
+            .find(|entry| entry.id == id && entry.complete)
+    }
+
+    pub fn set_complete(&mut self, id: u64, normalized_zip_sha256: Option<String>) {
+        if let Some(entry) = self.artifacts.iter_mut().find(|entry| entry.id == id) {
+            entry.complete = true;
+            entry.normalized_zip_sha256 = normalized_zip_sha256;
+        }
+    }
+
+    /// Save the manifest to the output directory
+    pub async fn save(&self, output: &Path) -> Result<(), Error> {
+        let path = output.join(Self::FILE_NAME);
+        let json = serde_json::to_vec_pretty(self).change_context(Error::Manifest)?;
+        atomic_file::write(&path, json)
+            .await
+            .change_context(Error::Manifest)
+            .attach_printable_lazy(|| format!("path: {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_util::{temp_files, TempDir};
+
+    fn entry(id: u64, complete: bool) -> ManifestEntry {
+        ManifestEntry {
+            id,
+            name: format!("artifact-{}", id),
+            path: PathBuf::from(format!("artifact-{}", id)),
+            complete,
+            digest: None,
+            normalized_zip_sha256: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resume_partial() {
+        let dir = TempDir::new();
+        assert!(Manifest::load(&dir.join("")).await.unwrap().is_none());
+
+        let mut manifest = Manifest {
+            repo: "foo/bar".to_string(),
+            rev: "abc".to_string(),
+            artifacts: vec![entry(1, false), entry(2, false), entry(3, false)],
+        };
+        manifest.set_complete(2, None);
+        manifest.set_complete(3, Some("abc".to_string()));
+        manifest.save(&dir.join("")).await.unwrap();
+        assert!(temp_files(&dir.join("")).is_empty());
+
+        let loaded = Manifest::load(&dir.join("")).await.unwrap().unwrap();
+        loaded.check_same_source("foo/bar", "abc").unwrap();
+        assert!(!loaded.is_complete(1));
+        assert!(loaded.is_complete(2));
+        assert!(!loaded.is_complete(4));
+        // kept for the next --resume
+        let entry = loaded.complete_entry(3).unwrap();
+        assert_eq!(entry.normalized_zip_sha256.as_deref(), Some("abc"));
+        assert!(loaded
+            .complete_entry(2)
+            .unwrap()
+            .normalized_zip_sha256
+            .is_none());
+
+        let err = loaded.check_same_source("foo/bar", "def").unwrap_err();
+        assert!(format!("{:?}", err).contains("run without --resume"));
+    }
+
+    #[test]
+    fn test_new_sorted() {
+        let mut docs = entry(1, false);
+        docs.name = "docs".to_string();
+        let mut app = entry(3, true);
+        app.name = "app".to_string();
+        let mut app2 = entry(2, false);
+        app2.name = "app".to_string();
+        let manifest = Manifest::new(
+            "foo/bar".to_string(),
+            "abc".to_string(),
+            vec![docs, app, app2],
+        );
+        let ids = manifest
+            .artifacts
+            .iter()
+            .map(|entry| entry.id)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, [2, 3, 1]);
+    }
+}
@@ -0,0 +1,106 @@
+//! Limiting how much memory downloads use at the same time, for --max-memory
+
+use error_stack::{Result, ResultExt};
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+use crate::Error;
+
+/// Budget of memory shared by the downloads, in KiB so it fits in the permits of a semaphore
+#[derive(Debug)]
+pub struct MemoryBudget {
+    permits: Semaphore,
+    max_kib: u32,
+}
+
+impl MemoryBudget {
+    pub fn new(max_bytes: u64) -> Self {
+        let max_kib = to_kib(max_bytes).max(1);
+        Self {
+            permits: Semaphore::new(max_kib as usize),
+            max_kib,
+        }
+    }
+
+    /// Wait until `bytes` fits in the budget, and reserve it until the permit is dropped
+    ///
+    /// Anything larger than the whole budget waits for everything else to finish,
+    /// and then runs alone
+    pub async fn reserve(&self, bytes: u64) -> Result<SemaphorePermit<'_>, Error> {
+        let kib = to_kib(bytes).min(self.max_kib);
+        self.permits
+            .acquire_many(kib)
+            .await
+            .change_context(Error::DownloadArtifact)
+    }
+}
+
+fn to_kib(bytes: u64) -> u32 {
+    bytes.div_ceil(1024).try_into().unwrap_or(u32::MAX)
+}
+
+/// Parse a size like `512M` or `2G`. Units are powers of 1024, and no unit means bytes
+pub fn parse_size(s: &str) -> std::result::Result<u64, String> {
+    let upper = s.trim().to_ascii_uppercase();
+    let number = upper.trim_end_matches(['B', 'I']);
+    let (number, shift) = match number.char_indices().last() {
+        Some((i, 'K')) => (&number[..i], 10),
+        Some((i, 'M')) => (&number[..i], 20),
+        Some((i, 'G')) => (&number[..i], 30),
+        Some((i, 'T')) => (&number[..i], 40),
+        _ => (number, 0),
+    };
+    number
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(1 << shift))
+        .ok_or_else(|| {
+            format!(
+                "invalid size `{}`, expected a number like `512M` or `2G`",
+                s
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("100"), Ok(100));
+        assert_eq!(parse_size("4k"), Ok(4 << 10));
+        assert_eq!(parse_size("512M"), Ok(512 << 20));
+        assert_eq!(parse_size("2GiB"), Ok(2 << 30));
+        assert_eq!(parse_size(" 1 TB "), Ok(1 << 40));
+        assert!(parse_size("").is_err());
+        assert!(parse_size("2X").is_err());
+        assert!(parse_size("-1G").is_err());
+        assert!(parse_size("99999999999T").is_err());
+    }
+
+    #[test]
+    fn test_to_kib() {
+        assert_eq!(to_kib(0), 0);
+        assert_eq!(to_kib(1), 1);
+        assert_eq!(to_kib(1024), 1);
+        assert_eq!(to_kib(1025), 2);
+        assert_eq!(to_kib(u64::MAX), u32::MAX);
+    }
+
+    #[tokio::test]
+    async fn test_reserve() {
+        let budget = MemoryBudget::new(10 * 1024);
+        let first = budget.reserve(6 * 1024).await.unwrap();
+        // doesn't fit until the first one is dropped
+        assert!(budget.permits.try_acquire_many(5).is_err());
+        let second = budget.reserve(4 * 1024).await.unwrap();
+        drop(first);
+        drop(second);
+        // larger than the whole budget, runs alone
+        let large = budget.reserve(100 * 1024).await.unwrap();
+        assert_eq!(budget.permits.available_permits(), 0);
+        drop(large);
+        assert_eq!(budget.permits.available_permits(), 10);
+    }
+}
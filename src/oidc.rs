@@ -0,0 +1,147 @@
+//! Getting the API token with the OIDC provider of GitHub Actions, for --oidc
+//!
+//! The runner gives out OIDC tokens to workflows with the `id-token: write` permission.
+//! The GitHub API doesn't accept them directly, so they are usually exchanged
+//! for an access token with a token broker, given with --oidc-exchange.
+
+use error_stack::{report, Result, ResultExt};
+use reqwest::Client;
+
+use crate::{github::parse_json, output::progress, Error};
+
+/// Endpoint of the OIDC provider, from the environment of the runner
+#[derive(Debug)]
+pub struct OidcProvider {
+    request_url: String,
+    request_token: String,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct OidcToken {
+    value: String,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct ExchangedToken {
+    token: String,
+}
+
+impl OidcProvider {
+    pub fn from_env() -> Result<Self, Error> {
+        let var = |name: &str| {
+            std::env::var(name)
+                .ok()
+                .filter(|value| !value.is_empty())
+                .ok_or_else(|| report!(Error::Oidc))
+                .attach_printable_lazy(|| format!("{} is not set", name))
+                .attach_printable(
+                    "make sure magnesis is running in GitHub Actions, with `id-token: write` permission",
+                )
+        };
+        Ok(Self {
+            request_url: var("ACTIONS_ID_TOKEN_REQUEST_URL")?,
+            request_token: var("ACTIONS_ID_TOKEN_REQUEST_TOKEN")?,
+        })
+    }
+
+    /// Get the token to use for the API
+    ///
+    /// The OIDC token is requested for the audience, and exchanged for an access token
+    /// with the exchange URL if there is one. Otherwise, the OIDC token is used as-is.
+    pub async fn get_token(
+        &self,
+        audience: Option<&str>,
+        exchange_url: Option<&str>,
+    ) -> Result<String, Error> {
+        let client = Client::new();
+        progress!("requesting OIDC token");
+        let mut request = client
+            .get(&self.request_url)
+            .bearer_auth(&self.request_token);
+        if let Some(audience) = audience {
+            request = request.query(&[("audience", audience)]);
+        }
+        let bytes = request
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .change_context(Error::Oidc)?
+            .bytes()
+            .await
+            .change_context(Error::Oidc)?;
+        let oidc_token: OidcToken = parse_json(&bytes).change_context(Error::Oidc)?;
+
+        let Some(exchange_url) = exchange_url else {
+            return Ok(oidc_token.value);
+        };
+        progress!("exchanging OIDC token at `{}`", exchange_url);
+        let bytes = client
+            .post(exchange_url)
+            .bearer_auth(&oidc_token.value)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .change_context(Error::Oidc)
+            .attach_printable_lazy(|| format!("url: {}", exchange_url))?
+            .bytes()
+            .await
+            .change_context(Error::Oidc)?;
+        let exchanged: ExchangedToken = parse_json(&bytes)
+            .change_context(Error::Oidc)
+            .attach_printable_lazy(|| format!("url: {}", exchange_url))?;
+        Ok(exchanged.token)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_util::{MockServer, Response};
+
+    #[tokio::test]
+    async fn test_get_token() {
+        let server = MockServer::start(|request| {
+            match (request.method.as_str(), request.header("authorization")) {
+                ("GET", Some("Bearer request-token")) => {
+                    Response::json(r#"{"count":1,"value":"oidc-token"}"#)
+                }
+                ("POST", Some("Bearer oidc-token")) => {
+                    Response::json(r#"{"token":"access-token"}"#)
+                }
+                _ => Response::new(401),
+            }
+        })
+        .await;
+        let provider = OidcProvider {
+            request_url: server.url("/token?api-version=2.0"),
+            request_token: "request-token".to_string(),
+        };
+
+        let token = provider.get_token(Some("magnesis"), None).await.unwrap();
+        assert_eq!(token, "oidc-token");
+        let exchange_url = server.url("/exchange");
+        let token = provider.get_token(None, Some(&exchange_url)).await.unwrap();
+        assert_eq!(token, "access-token");
+
+        let paths = server
+            .requests()
+            .into_iter()
+            .map(|request| request.path)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            [
+                "/token?api-version=2.0&audience=magnesis",
+                "/token?api-version=2.0",
+                "/exchange"
+            ]
+        );
+
+        let provider = OidcProvider {
+            request_url: server.url("/token"),
+            request_token: "wrong".to_string(),
+        };
+        assert!(provider.get_token(None, None).await.is_err());
+    }
+}
@@ -0,0 +1,177 @@
+//! Printing progress messages separately from the result
+//!
+//! Results (for example from --list and --plan) are always printed to stdout.
+//! Progress is printed to stdout too, unless the result is machine-readable,
+//! in which case progress goes to stderr to keep stdout valid.
+//! --quiet turns off progress, but never the result.
+//! Reports asked for with a flag, like --trace-timings, are always printed to stderr.
+//! Warnings are always printed to stderr, and counted so --strict can fail the run.
+//!
+//! Each run has its own [`Output`], so runs in the same process don't share these.
+//! Tasks spawned in a run need [`inherit`] to print with the output of the run.
+
+use std::{
+    fmt,
+    future::Future,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+tokio::task_local! {
+    static OUTPUT: Arc<Output>;
+}
+
+/// Format of the result printed to stdout
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    /// Human-readable text
+    #[default]
+    Text,
+    /// JSON
+    Json,
+    /// JSON lines, one object per artifact, printed in the order they are processed
+    Jsonl,
+}
+
+/// Where the progress of a run goes, and how many warnings it printed
+#[derive(Debug, Default)]
+pub struct Output {
+    progress_to_stderr: bool,
+    quiet: bool,
+    warnings: AtomicUsize,
+}
+
+impl Output {
+    pub fn new(format: Format, quiet: bool) -> Arc<Self> {
+        Arc::new(Self {
+            progress_to_stderr: format != Format::Text,
+            quiet,
+            warnings: AtomicUsize::new(0),
+        })
+    }
+}
+
+/// Run the future with the output, for everything it prints
+pub async fn scope<F: Future>(output: Arc<Output>, future: F) -> F::Output {
+    OUTPUT.scope(output, future).await
+}
+
+/// Wrap a future to be spawned, so it prints with the output of the current run
+pub fn inherit<F: Future>(future: F) -> impl Future<Output = F::Output> {
+    OUTPUT.scope(current(), future)
+}
+
+/// Wrap a closure for `spawn_blocking`, so it prints with the output of the current run
+pub fn inherit_blocking<T>(f: impl FnOnce() -> T) -> impl FnOnce() -> T {
+    let output = current();
+    move || OUTPUT.sync_scope(output, f)
+}
+
+/// Output of the current run, or the defaults outside of a run
+fn current() -> Arc<Output> {
+    OUTPUT.try_with(Arc::clone).unwrap_or_default()
+}
+
+pub fn print_progress(args: fmt::Arguments) {
+    let output = current();
+    if output.quiet {
+        return;
+    }
+    if output.progress_to_stderr {
+        eprintln!("{}", args);
+    } else {
+        println!("{}", args);
+    }
+}
+
+/// Print a progress message, like `println!`
+macro_rules! progress {
+    ($($arg:tt)*) => {
+        $crate::output::print_progress(format_args!($($arg)*))
+    };
+}
+pub(crate) use progress;
+
+pub fn print_info(args: fmt::Arguments) {
+    eprintln!("{}", args);
+}
+
+/// Print a report asked for with a flag, like `eprintln!`. Not turned off by --quiet
+macro_rules! info {
+    ($($arg:tt)*) => {
+        $crate::output::print_info(format_args!($($arg)*))
+    };
+}
+pub(crate) use info;
+
+pub fn print_warning(args: fmt::Arguments) {
+    current().warnings.fetch_add(1, Ordering::Relaxed);
+    eprintln!("warning: {}", args);
+}
+
+/// Number of warnings printed so far in the current run
+pub fn warning_count() -> usize {
+    current().warnings.load(Ordering::Relaxed)
+}
+
+/// Print a warning, like `eprintln!`
+macro_rules! warning {
+    ($($arg:tt)*) => {
+        $crate::output::print_warning(format_args!($($arg)*))
+    };
+}
+pub(crate) use warning;
+
+/// Print the value as JSON on one line to stdout, for --format jsonl
+pub fn print_json_line<T: serde::Serialize>(value: &T) {
+    match serde_json::to_string(value) {
+        Ok(json) => println!("{}", json),
+        Err(e) => eprintln!("error: failed to serialize output: {}", e),
+    }
+}
+
+/// Print the value as pretty JSON to stdout
+pub fn print_json<T: serde::Serialize>(value: &T) {
+    match serde_json::to_string_pretty(value) {
+        Ok(json) => println!("{}", json),
+        Err(e) => eprintln!("error: failed to serialize output: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_new_progress_to_stderr() {
+        // stdout only has the JSON lines, so progress can't go there
+        assert!(Output::new(Format::Jsonl, false).progress_to_stderr);
+        assert!(Output::new(Format::Json, false).progress_to_stderr);
+        let output = Output::new(Format::Text, true);
+        assert!(!output.progress_to_stderr);
+        assert!(output.quiet);
+        assert!(!Output::new(Format::Text, false).quiet);
+    }
+
+    #[tokio::test]
+    async fn test_warnings_per_run() {
+        let a = Output::new(Format::Text, false);
+        let b = Output::new(Format::Text, false);
+        scope(Arc::clone(&a), async {
+            warning!("in a");
+            // spawned tasks count for the run that spawned them
+            tokio::spawn(inherit(async { warning!("in a task of a") }))
+                .await
+                .unwrap();
+            tokio::task::spawn_blocking(inherit_blocking(|| warning!("blocking in a")))
+                .await
+                .unwrap();
+            assert_eq!(warning_count(), 3);
+        })
+        .await;
+        scope(Arc::clone(&b), async { assert_eq!(warning_count(), 0) }).await;
+        assert_eq!(a.warnings.load(Ordering::Relaxed), 3);
+    }
+}
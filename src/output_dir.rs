@@ -0,0 +1,214 @@
+//! Creating the output directory, and replacing and updating what's in it
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use error_stack::{Report, Result, ResultExt};
+use tokio::fs;
+
+use crate::{freeze, output::progress, retention, Error};
+
+/// Create the output directory, removing what's in it unless `keep` is true
+///
+/// With `marker`, the --clean-older-than marker is written in it
+pub async fn create_output(
+    output: String,
+    gitignore: bool,
+    marker: bool,
+    keep: bool,
+) -> Result<PathBuf, Error> {
+    let path = PathBuf::from(&output);
+    if path.exists() {
+        // in case it was frozen by a previous pull
+        freeze::thaw(path.clone()).await?;
+    }
+    if path.exists() && !keep {
+        progress!("removing existing output at `{}`", output);
+        fs::remove_dir_all(&path)
+            .await
+            .change_context(Error::CreateOutput)?;
+    }
+    fs::create_dir_all(&path)
+        .await
+        .change_context(Error::CreateOutput)?;
+    if gitignore {
+        fs::write(path.join(".gitignore"), "*\n")
+            .await
+            .change_context(Error::CreateOutput)?;
+    }
+    if marker {
+        fs::write(path.join(retention::MARKER), "")
+            .await
+            .change_context(Error::CreateOutput)?;
+    }
+    Ok(path)
+}
+
+/// Path next to `path` with the suffix added to its name
+pub fn sibling_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".{}", suffix));
+    path.with_file_name(name)
+}
+
+/// Replace the output with the staged one, for --atomic-output
+///
+/// The old output is moved aside before the staged one is moved in, since a directory
+/// can't be renamed over another one, and is deleted after
+pub async fn swap_output(staged: &Path, output: &Path) -> Result<(), Error> {
+    let old = sibling_path(output, "magnesis-old");
+    let swap = async {
+        if old.exists() {
+            freeze::thaw(old.clone()).await?;
+            fs::remove_dir_all(&old)
+                .await
+                .change_context(Error::CreateOutput)?;
+        }
+        if output.exists() {
+            freeze::thaw(output.to_path_buf()).await?;
+            fs::rename(output, &old)
+                .await
+                .change_context(Error::CreateOutput)?;
+        }
+        fs::rename(staged, output)
+            .await
+            .change_context(Error::CreateOutput)?;
+        if old.exists() {
+            fs::remove_dir_all(&old)
+                .await
+                .change_context(Error::CreateOutput)?;
+        }
+        Ok::<_, Report<Error>>(())
+    };
+    swap.await
+        .attach_printable_lazy(|| format!("path: {}", output.display()))?;
+    progress!("replaced output at `{}`", output.display());
+    Ok(())
+}
+
+/// Remove files from the previous pull that are not in the current pull
+pub async fn remove_deleted_files(
+    previous_sums: &HashMap<PathBuf, String>,
+    sums: &[(PathBuf, String)],
+) -> Result<(), Error> {
+    let current = sums.iter().map(|(path, _)| path).collect::<HashSet<_>>();
+    for path in previous_sums.keys() {
+        if current.contains(path) || !path.exists() {
+            continue;
+        }
+        progress!("removing deleted file `{}`", path.display());
+        fs::remove_file(path)
+            .await
+            .change_context(Error::Extract)
+            .attach_printable_lazy(|| format!("path: {}", path.display()))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_util::TempDir;
+
+    #[tokio::test]
+    async fn test_create_output_gitignore() {
+        let dir = TempDir::new();
+        let output = dir.join("out");
+        std::fs::create_dir_all(&output).unwrap();
+        std::fs::write(output.join("old.txt"), "old").unwrap();
+
+        let path = create_output(output.display().to_string(), true, true, false)
+            .await
+            .unwrap();
+        assert_eq!(path, output);
+        assert_eq!(
+            std::fs::read_to_string(output.join(".gitignore")).unwrap(),
+            "*\n"
+        );
+        // the old output is removed
+        assert!(!output.join("old.txt").exists());
+        assert!(output.join(retention::MARKER).exists());
+
+        create_output(output.display().to_string(), false, false, false)
+            .await
+            .unwrap();
+        assert!(!output.join(".gitignore").exists());
+        assert!(!output.join(retention::MARKER).exists());
+    }
+
+    #[tokio::test]
+    async fn test_create_output_resume_keeps_partial() {
+        let dir = TempDir::new();
+        let output = dir.join("out");
+        std::fs::create_dir_all(output.join("done")).unwrap();
+        std::fs::write(output.join("done/a.txt"), "a").unwrap();
+
+        create_output(output.display().to_string(), false, false, true)
+            .await
+            .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(output.join("done/a.txt")).unwrap(),
+            "a"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_remove_deleted_files() {
+        let dir = TempDir::new();
+        std::fs::write(dir.join("kept.txt"), "kept").unwrap();
+        std::fs::write(dir.join("deleted.txt"), "deleted").unwrap();
+        let previous_sums = HashMap::from([
+            (dir.join("kept.txt"), "1".to_string()),
+            (dir.join("deleted.txt"), "2".to_string()),
+            (dir.join("gone.txt"), "3".to_string()),
+        ]);
+        let sums = vec![(dir.join("kept.txt"), "1".to_string())];
+        remove_deleted_files(&previous_sums, &sums).await.unwrap();
+        assert!(dir.join("kept.txt").exists());
+        assert!(!dir.join("deleted.txt").exists());
+    }
+
+    #[test]
+    fn test_sibling_path() {
+        assert_eq!(
+            sibling_path(Path::new("out/dist"), "magnesis-new"),
+            Path::new("out/dist.magnesis-new")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_swap_output() {
+        let dir = TempDir::new();
+        let output = dir.join("dist");
+        let staged = sibling_path(&output, "magnesis-new");
+        std::fs::create_dir_all(&output).unwrap();
+        std::fs::write(output.join("old.txt"), "old").unwrap();
+        std::fs::create_dir_all(&staged).unwrap();
+        std::fs::write(staged.join("new.txt"), "new").unwrap();
+        // the old output is untouched while the new one is staged
+        assert_eq!(
+            std::fs::read_to_string(output.join("old.txt")).unwrap(),
+            "old"
+        );
+        assert!(!output.join("new.txt").exists());
+
+        swap_output(&staged, &output).await.unwrap();
+        assert_eq!(
+            std::fs::read_to_string(output.join("new.txt")).unwrap(),
+            "new"
+        );
+        assert!(!output.join("old.txt").exists());
+        assert!(!staged.exists());
+        assert!(!sibling_path(&output, "magnesis-old").exists());
+
+        // works when there is no output yet
+        let output = dir.join("first");
+        let staged = sibling_path(&output, "magnesis-new");
+        std::fs::create_dir_all(&staged).unwrap();
+        swap_output(&staged, &output).await.unwrap();
+        assert!(output.is_dir());
+    }
+}
@@ -0,0 +1,99 @@
+//! Changing the owner of the output after a pull, for --owner
+
+use std::{path::PathBuf, str::FromStr};
+
+use error_stack::{Result, ResultExt};
+
+use crate::Error;
+
+/// User and group IDs, in the format `UID:GID`
+#[derive(Debug, Clone, Copy)]
+pub struct Owner {
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl FromStr for Owner {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let invalid = || format!("invalid owner `{}`, expected `UID:GID` like `1000:1000`", s);
+        let (uid, gid) = s.split_once(':').ok_or_else(invalid)?;
+        Ok(Self {
+            uid: uid.trim().parse().map_err(|_| invalid())?,
+            gid: gid.trim().parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+/// Change the owner of the output and everything in it. Symlinks themselves are changed,
+/// not what they point to
+///
+/// This usually needs root, like when pulling in a container into a mounted volume
+#[cfg(unix)]
+pub async fn chown(output: PathBuf, owner: Owner) -> Result<(), Error> {
+    use std::{fs, io, os::unix::fs::lchown, path::Path};
+
+    fn walk(path: &Path, owner: Owner) -> io::Result<()> {
+        lchown(path, Some(owner.uid), Some(owner.gid))?;
+        if fs::symlink_metadata(path)?.is_dir() {
+            for entry in fs::read_dir(path)? {
+                walk(&entry?.path(), owner)?;
+            }
+        }
+        Ok(())
+    }
+
+    tokio::task::spawn_blocking(move || {
+        walk(&output, owner).attach_printable_lazy(|| format!("path: {}", output.display()))
+    })
+    .await
+    .change_context(Error::Owner)?
+    .change_context(Error::Owner)
+}
+
+#[cfg(not(unix))]
+pub async fn chown(_output: PathBuf, _owner: Owner) -> Result<(), Error> {
+    Err(error_stack::report!(Error::Owner)).attach_printable("--owner is only supported on Unix")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_owner() {
+        let owner: Owner = "1000:100".parse().unwrap();
+        assert_eq!((owner.uid, owner.gid), (1000, 100));
+        for s in ["1000", "1000:", ":100", "user:group", "-1:0"] {
+            let err = s.parse::<Owner>().unwrap_err();
+            assert!(err.contains("expected `UID:GID`"), "{}", err);
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_chown() {
+        use std::os::unix::fs::{symlink, MetadataExt};
+
+        use crate::test_util::TempDir;
+
+        let dir = TempDir::new();
+        let output = dir.join("out");
+        std::fs::create_dir_all(output.join("sub")).unwrap();
+        std::fs::write(output.join("sub/a.txt"), "a").unwrap();
+        symlink("sub/a.txt", output.join("link")).unwrap();
+        // the current user can always give files to itself
+        let metadata = std::fs::metadata(&output).unwrap();
+        let owner = Owner {
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+        };
+        chown(output.clone(), owner).await.unwrap();
+        let metadata = std::fs::symlink_metadata(output.join("link")).unwrap();
+        assert_eq!((metadata.uid(), metadata.gid()), (owner.uid, owner.gid));
+
+        let err = chown(dir.join("missing"), owner).await.unwrap_err();
+        assert!(format!("{:?}", err).contains("missing"));
+    }
+}
@@ -0,0 +1,288 @@
+use std::path::{Path, PathBuf};
+
+use crate::{
+    artifact::Artifact,
+    checksum::sha256_hex,
+    extract::{ExtractOptions, LineEnding, OnCaseCollision, OnConflict, SymlinkPolicy},
+    layout::Layout,
+    output::{print_json, print_json_line, Format},
+};
+
+/// Description of what would be pulled, printed by --plan
+#[derive(Debug, serde::Serialize)]
+pub struct Plan {
+    pub repo: String,
+    pub rev: String,
+    pub output: PathBuf,
+    pub filters: Filters,
+    pub on_conflict: OnConflict,
+    pub extraction: Extraction,
+    pub artifacts: Vec<PlannedArtifact>,
+}
+
+/// Options that select which artifacts are pulled, and how
+#[derive(Debug, serde::Serialize)]
+pub struct Filters {
+    pub check_suite: Option<u64>,
+    pub pr: Option<u64>,
+    pub run: Option<u64>,
+    pub name: Vec<String>,
+    pub label: Vec<String>,
+    pub job: Vec<String>,
+    pub attempt: Option<u64>,
+    pub contains: Vec<String>,
+    pub title_match: Option<String>,
+    pub changed_path: Vec<String>,
+    pub select_script: Option<PathBuf>,
+    pub index: Vec<usize>,
+    /// Number of artifacts downloaded at the same time
+    pub jobs: usize,
+}
+
+/// Options that change what is written for each artifact
+#[derive(Debug, serde::Serialize)]
+pub struct Extraction {
+    pub on_case_collision: OnCaseCollision,
+    pub symlink_policy: SymlinkPolicy,
+    pub zip_index: bool,
+    pub normalized_zip: Option<PathBuf>,
+    pub inner_path: Option<PathBuf>,
+    pub normalize_eol: Option<LineEnding>,
+    pub text_globs: Vec<String>,
+    pub file_mode: Option<u32>,
+    pub dir_mode: Option<u32>,
+}
+
+impl From<&ExtractOptions> for Extraction {
+    fn from(options: &ExtractOptions) -> Self {
+        Self {
+            on_case_collision: options.on_case_collision,
+            symlink_policy: options.symlink_policy,
+            zip_index: options.zip_index,
+            normalized_zip: options.normalized_zip.clone(),
+            inner_path: options.inner_path.clone(),
+            normalize_eol: options.normalize_eol,
+            text_globs: options.text_globs.clone(),
+            file_mode: options.file_mode,
+            dir_mode: options.dir_mode,
+        }
+    }
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct PlannedArtifact {
+    pub id: u64,
+    pub name: String,
+    pub destination: PathBuf,
+}
+
+impl PlannedArtifact {
+    pub fn new(artifact: &Artifact, output: &Path, layout: &Layout, rev: &str) -> Self {
+        Self {
+            id: artifact.id,
+            name: artifact.name.clone(),
+            destination: output.join(layout.destination(&artifact.name, rev)),
+        }
+    }
+}
+
+impl Plan {
+    /// SHA-256 of the plan as JSON, which only changes if the plan does
+    pub fn hash(&self) -> String {
+        sha256_hex(&serde_json::to_vec(self).unwrap_or_default())
+    }
+
+    /// Print the hash for --print-layout-hash
+    pub fn print_hash(&self, format: Format) {
+        #[derive(serde::Serialize)]
+        struct HashItem {
+            layout_hash: String,
+        }
+        let item = HashItem {
+            layout_hash: self.hash(),
+        };
+        match format {
+            Format::Json => print_json(&item),
+            Format::Jsonl => print_json_line(&item),
+            Format::Text => println!("{}", item.layout_hash),
+        }
+    }
+
+    pub fn print(&self, format: Format) {
+        match format {
+            Format::Json => {
+                print_json(self);
+                return;
+            }
+            Format::Jsonl => {
+                for artifact in &self.artifacts {
+                    print_json_line(artifact);
+                }
+                return;
+            }
+            Format::Text => {}
+        }
+        println!("repo:        {}", self.repo);
+        println!("rev:         {}", self.rev);
+        println!("output:      {}", self.output.display());
+        if let Some(check_suite) = self.filters.check_suite {
+            println!("check suite: {}", check_suite);
+        }
+        if let Some(pr) = self.filters.pr {
+            println!("pr:          #{}", pr);
+        }
+        if let Some(run) = self.filters.run {
+            println!("run:         {}", run);
+        }
+        if !self.filters.name.is_empty() {
+            println!("name:        {}", self.filters.name.join(", "));
+        }
+        if !self.filters.label.is_empty() {
+            println!("label:       {}", self.filters.label.join(", "));
+        }
+        if !self.filters.job.is_empty() {
+            println!("job:         {}", self.filters.job.join(", "));
+        }
+        if let Some(attempt) = self.filters.attempt {
+            println!("attempt:     {}", attempt);
+        }
+        if !self.filters.contains.is_empty() {
+            println!("contains:    {}", self.filters.contains.join(", "));
+        }
+        if let Some(title_match) = &self.filters.title_match {
+            println!("run title:   {}", title_match);
+        }
+        if !self.filters.changed_path.is_empty() {
+            println!("changed:     {}", self.filters.changed_path.join(", "));
+        }
+        if let Some(script) = &self.filters.select_script {
+            println!("script:      {}", script.display());
+        }
+        if !self.filters.index.is_empty() {
+            let index = self
+                .filters
+                .index
+                .iter()
+                .map(|i| i.to_string())
+                .collect::<Vec<_>>();
+            println!("index:       {}", index.join(","));
+        }
+        println!("jobs:        {}", self.filters.jobs);
+        println!("on conflict: {:?}", self.on_conflict);
+        let extraction = &self.extraction;
+        if extraction.zip_index {
+            println!("zip index:   yes");
+        }
+        if let Some(dir) = &extraction.normalized_zip {
+            println!("normalized:  {}", dir.display());
+        }
+        if let Some(inner_path) = &extraction.inner_path {
+            println!("inner path:  {}", inner_path.display());
+        }
+        if let Some(eol) = extraction.normalize_eol {
+            println!(
+                "eol:         {:?} ({})",
+                eol,
+                extraction.text_globs.join(", ")
+            );
+        }
+        println!("artifacts:   {}", self.artifacts.len());
+        for artifact in &self.artifacts {
+            println!(
+                "  `{}` -> `{}`",
+                artifact.name,
+                artifact.destination.display()
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plan(names: &[&str]) -> Plan {
+        let layout = Layout::default().with_merge_prefix(Some("-".to_string()));
+        let output = Path::new("out");
+        let artifacts = names
+            .iter()
+            .enumerate()
+            .map(|(id, name)| {
+                let artifact: Artifact = serde_json::from_value(serde_json::json!({
+                    "id": id,
+                    "name": name,
+                    "archive_download_url": "",
+                    "workflow_run": { "head_sha": "abc" },
+                }))
+                .unwrap();
+                PlannedArtifact::new(&artifact, output, &layout, "abc")
+            })
+            .collect();
+        Plan {
+            repo: "foo/bar".to_string(),
+            rev: "abc".to_string(),
+            output: output.to_path_buf(),
+            filters: Filters {
+                check_suite: None,
+                run: None,
+                pr: Some(1),
+                index: vec![],
+                name: vec![],
+                label: vec![],
+                job: vec![],
+                attempt: None,
+                contains: vec![],
+                title_match: None,
+                changed_path: vec![],
+                select_script: None,
+                jobs: 4,
+            },
+            on_conflict: OnConflict::Overwrite,
+            extraction: Extraction::from(&ExtractOptions::default()),
+            artifacts,
+        }
+    }
+
+    #[test]
+    fn test_plan_destinations() {
+        let plan = plan(&["app-linux", "docs"]);
+        let json = serde_json::to_value(&plan).unwrap();
+        assert_eq!(json["on_conflict"], "overwrite");
+        assert_eq!(json["filters"]["pr"], 1);
+        assert_eq!(json["filters"]["jobs"], 4);
+        assert_eq!(json["artifacts"][0]["name"], "app-linux");
+        assert_eq!(json["artifacts"][0]["destination"], "out/app");
+        assert_eq!(json["artifacts"][1]["destination"], "out/docs");
+    }
+
+    #[test]
+    fn test_hash() {
+        let hash = plan(&["app-linux", "docs"]).hash();
+        assert_eq!(hash.len(), 64);
+        assert_eq!(plan(&["app-linux", "docs"]).hash(), hash);
+        assert_ne!(plan(&["app-linux"]).hash(), hash);
+        let mut other = plan(&["app-linux", "docs"]);
+        other.on_conflict = OnConflict::Skip;
+        assert_ne!(other.hash(), hash);
+
+        let mut changed = plan(&["app-linux", "docs"]);
+        changed.filters.name.push("lib-*".to_string());
+        assert_ne!(changed.hash(), hash);
+
+        let mut changed = plan(&["app-linux", "docs"]);
+        changed.extraction.inner_path = Some(PathBuf::from("dist"));
+        assert_ne!(changed.hash(), hash);
+
+        let mut changed = plan(&["app-linux", "docs"]);
+        changed.extraction.zip_index = true;
+        assert_ne!(changed.hash(), hash);
+
+        let mut changed = plan(&["app-linux", "docs"]);
+        changed.extraction.normalized_zip = Some(PathBuf::from("out.normalized"));
+        assert_ne!(changed.hash(), hash);
+
+        let mut changed = plan(&["app-linux", "docs"]);
+        changed.filters.jobs = 1;
+        assert_ne!(changed.hash(), hash);
+    }
+}
@@ -0,0 +1,347 @@
+//! Listing the files in an artifact without downloading the whole archive
+//!
+//! A zip archive ends with the central directory, which has the names of all entries.
+//! Only the end of the archive is requested with a `Range` header, and if the
+//! central directory doesn't fit in it, the central directory is requested separately.
+//! Servers that answer with something other than the requested range get the whole
+//! archive requested instead.
+
+use std::io::Cursor;
+
+use error_stack::{report, Result, ResultExt};
+use reqwest::{header, StatusCode};
+use zip::ZipArchive;
+
+use crate::{github::Api, retry::Status, Error};
+
+/// Size of the end of central directory record, without the comment
+const EOCD_SIZE: usize = 22;
+/// Largest possible end of central directory record, with the longest comment
+const EOCD_MAX_SIZE: usize = EOCD_SIZE + u16::MAX as usize;
+const EOCD_SIGNATURE: &[u8] = b"PK\x05\x06";
+const ENTRY_SIGNATURE: &[u8] = b"PK\x01\x02";
+/// Size of a central directory entry, without the name, extra field and comment
+const ENTRY_SIZE: usize = 46;
+
+/// Get the paths of the entries in the artifact archive at the URL
+///
+/// Zip64 archives are downloaded whole, since their central directory is found differently
+pub async fn list_entries(api: &Api, url: &str) -> Result<Vec<String>, Error> {
+    if let Some(names) = list_entries_with_ranges(api, url).await? {
+        return Ok(names);
+    }
+    let response = api.send(api.get(url)).await?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(report!(Error::Request)).attach_printable(Status(status));
+    }
+    let bytes = response.bytes().await.change_context(Error::Request)?;
+    archive_entries(&bytes)
+}
+
+/// Get the paths of the entries by requesting the end of the archive and the central
+/// directory, or `None` if the ranges can't be used
+async fn list_entries_with_ranges(api: &Api, url: &str) -> Result<Option<Vec<String>>, Error> {
+    let Some(tail) = request_range(api, url, Range::Suffix(EOCD_MAX_SIZE as u64)).await? else {
+        return Ok(None);
+    };
+    let (tail_start, tail) = match tail {
+        Ranged::Whole(bytes) => return archive_entries(&bytes).map(Some),
+        Ranged::Part { start, bytes } => (start, bytes),
+    };
+
+    let Some((cd_offset, cd_size)) = find_central_directory(&tail)? else {
+        return Ok(None);
+    };
+    if cd_size == 0 {
+        return Ok(Some(Vec::new()));
+    }
+    if cd_offset >= tail_start {
+        let start = (cd_offset - tail_start) as usize;
+        let central_directory = tail
+            .get(start..start + cd_size as usize)
+            .ok_or_else(|| report!(Error::Preview))
+            .attach_printable("central directory is out of bounds")?;
+        return parse_central_directory(central_directory).map(Some);
+    }
+    let range = Range::Bytes(cd_offset, cd_offset + cd_size - 1);
+    match request_range(api, url, range).await? {
+        Some(Ranged::Whole(bytes)) => archive_entries(&bytes).map(Some),
+        Some(Ranged::Part { bytes, .. }) => parse_central_directory(&bytes).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Get the paths of the entries in the whole archive
+fn archive_entries(bytes: &[u8]) -> Result<Vec<String>, Error> {
+    let archive = ZipArchive::new(Cursor::new(bytes)).change_context(Error::Preview)?;
+    Ok(archive.file_names().map(String::from).collect())
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Range {
+    /// The last bytes
+    Suffix(u64),
+    /// From the first byte to the last byte, inclusive
+    Bytes(u64, u64),
+}
+
+impl Range {
+    fn header(self) -> String {
+        match self {
+            Self::Suffix(len) => format!("bytes=-{}", len),
+            Self::Bytes(start, end) => format!("bytes={}-{}", start, end),
+        }
+    }
+}
+
+/// Response to a ranged request
+enum Ranged {
+    /// The server doesn't support ranges and sent the whole archive
+    Whole(Vec<u8>),
+    /// The requested part, starting at `start` in the archive
+    Part { start: u64, bytes: Vec<u8> },
+}
+
+/// Request the range of the archive, or `None` if the server sent something else
+async fn request_range(api: &Api, url: &str, range: Range) -> Result<Option<Ranged>, Error> {
+    let response = api
+        .send(api.get(url).header(header::RANGE, range.header()))
+        .await?;
+    let status = response.status();
+    if status == StatusCode::OK {
+        let bytes = response.bytes().await.change_context(Error::Request)?;
+        return Ok(Some(Ranged::Whole(bytes.to_vec())));
+    }
+    if status != StatusCode::PARTIAL_CONTENT {
+        return Err(report!(Error::Request))
+            .attach_printable(Status(status))
+            .attach_printable(format!("range: {}", range.header()));
+    }
+    let content_range = response
+        .headers()
+        .get(header::CONTENT_RANGE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(parse_content_range);
+    let bytes = response.bytes().await.change_context(Error::Request)?;
+    let Some((start, end, size)) = content_range else {
+        return Ok(None);
+    };
+    let expected = match range {
+        Range::Suffix(len) => (size.saturating_sub(len), size.saturating_sub(1)),
+        Range::Bytes(start, end) => (start, end.min(size.saturating_sub(1))),
+    };
+    if (start, end) != expected || end + 1 - start != bytes.len() as u64 {
+        return Ok(None);
+    }
+    Ok(Some(Ranged::Part {
+        start,
+        bytes: bytes.to_vec(),
+    }))
+}
+
+/// Parse the first byte, last byte and size from `bytes <first>-<last>/<size>`
+fn parse_content_range(value: &str) -> Option<(u64, u64, u64)> {
+    let (range, size) = value.strip_prefix("bytes ")?.split_once('/')?;
+    let (start, end) = range.split_once('-')?;
+    let (start, end, size) = (start.parse().ok()?, end.parse().ok()?, size.parse().ok()?);
+    (start <= end && end < size).then_some((start, end, size))
+}
+
+/// Find the offset and size of the central directory from the end of the archive,
+/// or `None` for zip64 archives, which have them in another record
+fn find_central_directory(tail: &[u8]) -> Result<Option<(u64, u64)>, Error> {
+    if tail.len() < EOCD_SIZE {
+        return Err(report!(Error::Preview))
+            .attach_printable(format!("archive is too small: {} bytes", tail.len()));
+    }
+    // each candidate has at least EOCD_SIZE bytes after it
+    let eocd = (0..=tail.len() - EOCD_SIZE)
+        .rev()
+        .find(|i| tail[*i..].starts_with(EOCD_SIGNATURE))
+        .map(|i| &tail[i..])
+        .ok_or_else(|| report!(Error::Preview))
+        .attach_printable("end of central directory not found")?;
+    let cd_size = read_u32(eocd, 12);
+    let cd_offset = read_u32(eocd, 16);
+    if cd_size == u32::MAX || cd_offset == u32::MAX {
+        return Ok(None);
+    }
+    Ok(Some((cd_offset as u64, cd_size as u64)))
+}
+
+/// Get the entry names from the central directory
+fn parse_central_directory(mut bytes: &[u8]) -> Result<Vec<String>, Error> {
+    let mut names = Vec::new();
+    while bytes.starts_with(ENTRY_SIGNATURE) && bytes.len() >= ENTRY_SIZE {
+        let name_len = read_u16(bytes, 28) as usize;
+        let extra_len = read_u16(bytes, 30) as usize;
+        let comment_len = read_u16(bytes, 32) as usize;
+        let end = ENTRY_SIZE + name_len;
+        let name = bytes
+            .get(ENTRY_SIZE..end)
+            .ok_or_else(|| report!(Error::Preview))
+            .attach_printable("entry name is out of bounds")?;
+        names.push(String::from_utf8_lossy(name).into_owned());
+        bytes = bytes
+            .get(end + extra_len + comment_len..)
+            .unwrap_or_default();
+    }
+    Ok(names)
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        github::ApiOptions,
+        test_util::{zip_file, MockServer, Request, Response},
+    };
+
+    /// Answer the request with the part of the archive in its `Range` header
+    fn range_response(archive: &[u8], request: &Request) -> Response {
+        let len = archive.len();
+        let range = request
+            .header("range")
+            .unwrap()
+            .strip_prefix("bytes=")
+            .unwrap();
+        let (start, end) = match range.split_once('-').unwrap() {
+            ("", suffix) => (len.saturating_sub(suffix.parse().unwrap()), len - 1),
+            (start, end) => (start.parse().unwrap(), end.parse().unwrap()),
+        };
+        Response::new(206)
+            .header("Content-Range", &format!("bytes {}-{}/{}", start, end, len))
+            .body(&archive[start..=end])
+    }
+
+    #[tokio::test]
+    async fn test_list_entries() {
+        let small = zip_file(&[("a.txt", b"1"), ("dir/b.txt", b"2")]);
+        // the central directory of this one doesn't fit in the end that is requested first
+        let names = (0..2000)
+            .map(|i| format!("dir/file-{:04}.txt", i))
+            .collect::<Vec<_>>();
+        let files = names
+            .iter()
+            .map(|name| (name.as_str(), &b"1"[..]))
+            .collect::<Vec<_>>();
+        let large = zip_file(&files);
+        let server = MockServer::start(move |request| match request.path.as_str() {
+            "/small" => range_response(&small, request),
+            "/large" => range_response(&large, request),
+            _ => Response::new(404),
+        })
+        .await;
+        let api = Api::new("token".to_string(), ApiOptions::default()).unwrap();
+
+        let entries = list_entries(&api, &server.url("/small")).await.unwrap();
+        assert_eq!(entries, ["a.txt", "dir/b.txt"]);
+        let entries = list_entries(&api, &server.url("/large")).await.unwrap();
+        assert_eq!(entries, names);
+        let ranges = server
+            .requests()
+            .iter()
+            .map(|request| request.header("range").unwrap().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(ranges.len(), 3);
+        assert_eq!(ranges[0], ranges[1]);
+        assert!(ranges[2].starts_with("bytes=") && !ranges[2].starts_with("bytes=-"));
+    }
+
+    #[tokio::test]
+    async fn test_list_entries_without_range_support() {
+        let archive = zip_file(&[("a.txt", b"1")]);
+        let server = MockServer::start(move |_| Response::new(200).body(archive.clone())).await;
+        let api = Api::new("token".to_string(), ApiOptions::default()).unwrap();
+        let entries = list_entries(&api, &server.url("/")).await.unwrap();
+        assert_eq!(entries, ["a.txt"]);
+    }
+
+    #[tokio::test]
+    async fn test_list_entries_falls_back() {
+        let archive = zip_file(&[("a.txt", b"1"), ("dir/b.txt", b"2")]);
+        // the end of central directory says the offset is in the zip64 record
+        let mut zip64 = archive.clone();
+        let eocd = zip64.len() - EOCD_SIZE;
+        zip64[eocd + 16..eocd + 20].copy_from_slice(&u32::MAX.to_le_bytes());
+        let server = MockServer::start(move |request| {
+            if request.header("range").is_none() {
+                return Response::new(200).body(archive.clone());
+            }
+            match request.path.as_str() {
+                "/no-content-range" => Response::new(206).body(&archive[..10]),
+                // the start of the archive instead of the end
+                "/wrong-range" => Response::new(206)
+                    .header("Content-Range", &format!("bytes 0-9/{}", archive.len()))
+                    .body(&archive[..10]),
+                "/zip64" => range_response(&zip64, request),
+                _ => Response::new(404),
+            }
+        })
+        .await;
+        let api = Api::new("token".to_string(), ApiOptions::default()).unwrap();
+
+        for path in ["/no-content-range", "/wrong-range", "/zip64"] {
+            let entries = list_entries(&api, &server.url(path)).await.unwrap();
+            assert_eq!(entries, ["a.txt", "dir/b.txt"], "{}", path);
+        }
+        // each with the ranged request, then the whole archive
+        assert_eq!(server.requests().len(), 6);
+    }
+
+    #[test]
+    fn test_parse_content_range() {
+        assert_eq!(parse_content_range("bytes 0-9/100"), Some((0, 9, 100)));
+        assert_eq!(parse_content_range("bytes */100"), None);
+        assert_eq!(parse_content_range("bytes 0-100/100"), None);
+        assert_eq!(parse_content_range("bytes 9-0/100"), None);
+        assert_eq!(parse_content_range("0-9/100"), None);
+    }
+
+    #[test]
+    fn test_central_directory() {
+        let zip = zip_file(&[("a.txt", b"hello"), ("dir/b.txt", b"hello")]);
+        let (offset, size) = find_central_directory(&zip).unwrap().unwrap();
+        let central_directory = &zip[offset as usize..(offset + size) as usize];
+        let names = parse_central_directory(central_directory).unwrap();
+        assert_eq!(names, ["a.txt", "dir/b.txt"]);
+    }
+
+    #[test]
+    fn test_short_tail() {
+        assert!(find_central_directory(b"").is_err());
+        assert!(find_central_directory(b"PK\x05\x06").is_err());
+        assert!(find_central_directory(&[0; EOCD_SIZE]).is_err());
+    }
+
+    #[test]
+    fn test_signature_near_end() {
+        // a signature in the last bytes can't be a full record
+        let mut tail = vec![0; EOCD_SIZE];
+        tail.extend_from_slice(EOCD_SIGNATURE);
+        assert!(find_central_directory(&tail).is_err());
+    }
+
+    #[test]
+    fn test_truncated_entry() {
+        let zip = zip_file(&[("a.txt", b"hello")]);
+        let (offset, size) = find_central_directory(&zip).unwrap().unwrap();
+        let central_directory = &zip[offset as usize..(offset + size) as usize - 2];
+        assert!(parse_central_directory(central_directory).is_err());
+    }
+}
@@ -0,0 +1,171 @@
+//! Removing old outputs next to the output, for --clean-older-than
+
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use error_stack::{Result, ResultExt};
+use tokio::fs;
+
+use crate::{freeze, output::progress, Error};
+
+/// File written in outputs pulled with --clean-older-than, so only those are removed.
+/// Its modification time is when the output was pulled
+pub const MARKER: &str = ".magnesis";
+
+/// Remove the directories next to `output` that have the marker and were pulled
+/// longer than `max_age` ago
+///
+/// `output` itself is not removed, and neither are the outputs in `pulling`,
+/// which are pulled at the same time, like the revisions of --compare and --branches
+pub async fn clean_older_than(
+    output: &Path,
+    max_age: Duration,
+    pulling: &BTreeSet<PathBuf>,
+) -> Result<(), Error> {
+    let parent = match output.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    if !parent.exists() {
+        return Ok(());
+    }
+    let mut entries = fs::read_dir(parent)
+        .await
+        .change_context(Error::Clean)
+        .attach_printable_lazy(|| format!("path: {}", parent.display()))?;
+    while let Some(entry) = entries.next_entry().await.change_context(Error::Clean)? {
+        let path = entry.path();
+        if path.file_name() == output.file_name() || !path.is_dir() || pulling.contains(&path) {
+            continue;
+        }
+        let Some(age) = pulled_ago(&path).await else {
+            continue;
+        };
+        if age <= max_age {
+            continue;
+        }
+        progress!(
+            "removing `{}`, pulled {}h ago",
+            path.display(),
+            age.as_secs() / 3600
+        );
+        let removed = async {
+            freeze::thaw(path.clone()).await?;
+            fs::remove_dir_all(&path)
+                .await
+                .change_context(Error::Clean)
+                .attach_printable_lazy(|| format!("path: {}", path.display()))
+        }
+        .await;
+        // the other revisions of the run clean the same directory
+        if removed.is_err() && !path.exists() {
+            continue;
+        }
+        removed?;
+    }
+    Ok(())
+}
+
+/// How long ago the directory was pulled, or `None` if it doesn't have the marker
+async fn pulled_ago(dir: &Path) -> Option<Duration> {
+    let modified = fs::metadata(dir.join(MARKER)).await.ok()?.modified().ok()?;
+    Some(
+        SystemTime::now()
+            .duration_since(modified)
+            .unwrap_or_default(),
+    )
+}
+
+/// Parse a duration like `30m`, `12h` or `7d`. No unit means seconds
+pub fn parse_duration(s: &str) -> std::result::Result<Duration, String> {
+    let s = s.trim();
+    let (number, unit) = match s.char_indices().last() {
+        Some((i, c)) if c.is_ascii_alphabetic() => (&s[..i], c),
+        _ => (s, 's'),
+    };
+    let multiplier = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        'w' => 7 * 24 * 60 * 60,
+        _ => return Err(format!("invalid unit in `{}`, expected s, m, h, d or w", s)),
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("invalid duration `{}`, expected a number like `7d`", s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("30"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_duration("30s"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_duration("30m"), Ok(Duration::from_secs(30 * 60)));
+        assert_eq!(parse_duration("12h"), Ok(Duration::from_secs(12 * 3600)));
+        assert_eq!(parse_duration("7d"), Ok(Duration::from_secs(7 * 86400)));
+        assert_eq!(parse_duration("2w"), Ok(Duration::from_secs(14 * 86400)));
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("d").is_err());
+        assert!(parse_duration("7y").is_err());
+        assert!(parse_duration("-1d").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_clean_older_than() {
+        let dir = TempDir::new();
+        for name in ["output", "old", "recent", "unmarked"] {
+            std::fs::create_dir_all(dir.join(name)).unwrap();
+        }
+        let an_hour_ago = SystemTime::now() - Duration::from_secs(3600);
+        for name in ["output", "old", "recent"] {
+            let marker = std::fs::File::create(dir.join(name).join(MARKER)).unwrap();
+            if name != "recent" {
+                marker.set_modified(an_hour_ago).unwrap();
+            }
+        }
+        std::fs::write(dir.join("old.txt"), "").unwrap();
+
+        clean_older_than(
+            &dir.join("output"),
+            Duration::from_secs(60),
+            &BTreeSet::new(),
+        )
+        .await
+        .unwrap();
+        assert!(dir.join("output").exists());
+        assert!(!dir.join("old").exists());
+        assert!(dir.join("recent").exists());
+        assert!(dir.join("unmarked").exists());
+        assert!(dir.join("old.txt").exists());
+    }
+
+    #[tokio::test]
+    async fn test_clean_keeps_pulling() {
+        let dir = TempDir::new();
+        for name in ["output", "other-rev"] {
+            std::fs::create_dir_all(dir.join(name)).unwrap();
+            let marker = std::fs::File::create(dir.join(name).join(MARKER)).unwrap();
+            marker
+                .set_modified(SystemTime::now() - Duration::from_secs(3600))
+                .unwrap();
+        }
+        let pulling = BTreeSet::from([dir.join("output"), dir.join("other-rev")]);
+
+        clean_older_than(&dir.join("output"), Duration::from_secs(60), &pulling)
+            .await
+            .unwrap();
+        assert!(dir.join("other-rev").exists());
+    }
+}
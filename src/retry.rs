@@ -0,0 +1,496 @@
+use std::{
+    fmt,
+    future::Future,
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use error_stack::{report, Report, Result};
+use reqwest::StatusCode;
+use tokio::io::AsyncWriteExt;
+use tokio_util::sync::CancellationToken;
+
+use crate::{output::progress, Error};
+
+/// How many times to retry a failed request
+///
+/// The timeout is for each attempt, and an attempt that times out is retried like
+/// a network error, up to `retries` times. The --deadline is over all of them:
+/// an attempt is cut short when the deadline passes, and nothing is retried
+/// if the deadline would pass while waiting to retry
+#[derive(Debug, Clone, Default)]
+pub struct RetryPolicy {
+    pub retries: u32,
+    /// Timeout for each attempt
+    pub timeout: Option<Duration>,
+    /// When the whole run has to finish, for --deadline
+    pub deadline: Option<Instant>,
+    /// File to append a line to for every retry, for --retry-log-file
+    pub log_file: Option<Arc<Path>>,
+}
+
+impl RetryPolicy {
+    /// Delay before the 1-based retry attempt, doubling each time up to 32s
+    pub fn delay(&self, attempt: u32) -> Duration {
+        Duration::from_secs(1 << attempt.saturating_sub(1).min(5))
+    }
+
+    /// Run the request to the URL, retrying it if it fails in a way that might succeed next time
+    ///
+    /// The URL is only for the messages and the --retry-log-file, so it should already
+    /// be redacted with [`redact_url`].
+    /// When `cancel` is cancelled, the attempt or the wait to retry stops with [`Error::Cancelled`]
+    pub async fn run<T, F, Fut>(
+        &self,
+        url: &str,
+        cancel: &CancellationToken,
+        mut request: F,
+    ) -> Result<T, Error>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, Error>>,
+    {
+        let mut attempt = 0;
+        loop {
+            let result = tokio::select! {
+                result = self.attempt(&mut request) => result,
+                () = cancel.cancelled() => return Err(report!(Error::Cancelled)),
+            };
+            match result {
+                Ok(value) => return Ok(value),
+                Err(err) if attempt < self.retries && is_retryable(&err) => {
+                    attempt += 1;
+                    let delay = retry_after(&err).unwrap_or_else(|| self.delay(attempt));
+                    if self
+                        .deadline
+                        .is_some_and(|deadline| Instant::now() + delay >= deadline)
+                    {
+                        return Err(err.attach_printable(format!(
+                            "not retrying `{}`, --deadline would pass",
+                            url
+                        )));
+                    }
+                    if matches!(err.current_context(), Error::SecondaryRateLimit) {
+                        progress!("hit the secondary rate limit, backing off");
+                    }
+                    progress!(
+                        "retrying `{}` in {}s ({}/{})",
+                        url,
+                        delay.as_secs(),
+                        attempt,
+                        self.retries
+                    );
+                    self.log(url, attempt, &err, delay).await;
+                    tokio::select! {
+                        () = tokio::time::sleep(delay) => {}
+                        () = cancel.cancelled() => return Err(report!(Error::Cancelled)),
+                    }
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Run one attempt of the request, with the timeout and the time left before the deadline
+    async fn attempt<T, F, Fut>(&self, request: &mut F) -> Result<T, Error>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, Error>>,
+    {
+        let until_deadline = self
+            .deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        let (limit, error) = match (self.timeout, until_deadline) {
+            (Some(timeout), Some(left)) if timeout < left => (timeout, Error::Timeout),
+            (_, Some(left)) => (left, Error::Deadline),
+            (Some(timeout), None) => (timeout, Error::Timeout),
+            (None, None) => return request().await,
+        };
+        match tokio::time::timeout(limit, request()).await {
+            Ok(result) => result,
+            Err(_) => {
+                Err(report!(error).attach_printable(format!("after {:.1}s", limit.as_secs_f64())))
+            }
+        }
+    }
+
+    /// Append the retry to the --retry-log-file, if it can be written.
+    /// A log that can't be written doesn't fail the request
+    async fn log(&self, url: &str, attempt: u32, err: &Report<Error>, delay: Duration) {
+        let Some(path) = &self.log_file else {
+            return;
+        };
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let line = log_line(timestamp, url, attempt, self.retries, err, delay);
+        let file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path.as_ref())
+            .await;
+        if let Ok(mut file) = file {
+            if file.write_all(line.as_bytes()).await.is_ok() {
+                // the write finishes in the background otherwise
+                let _ = file.flush().await;
+            }
+        }
+    }
+}
+
+/// Remove the query from URLs outside the API at `api_url`, since signed URLs
+/// for downloading archives have the signature there
+pub fn redact_url(url: &str, api_url: &str) -> String {
+    let api_url = api_url.trim_end_matches('/');
+    if url
+        .strip_prefix(api_url)
+        .is_some_and(|rest| rest.starts_with('/'))
+    {
+        return url.to_string();
+    }
+    match url.find(['?', '#']) {
+        Some(end) => format!("{}?[REDACTED]", &url[..end]),
+        None => url.to_string(),
+    }
+}
+
+/// Line in the retry log, as tab-separated timestamp, URL, attempt, reason and delay
+fn log_line(
+    timestamp: u64,
+    url: &str,
+    attempt: u32,
+    retries: u32,
+    err: &Report<Error>,
+    delay: Duration,
+) -> String {
+    format!(
+        "{}\t{}\t{}/{}\t{}\t{}s\n",
+        timestamp,
+        url,
+        attempt,
+        retries,
+        reason(err),
+        delay.as_secs()
+    )
+}
+
+/// Short description of why the request failed, for the retry log
+fn reason(err: &Report<Error>) -> String {
+    if let Some(status) = err.downcast_ref::<Status>() {
+        return status.to_string();
+    }
+    if let Some(err) = err.downcast_ref::<reqwest::Error>() {
+        return err.to_string();
+    }
+    err.current_context().to_string()
+}
+
+/// HTTP status of a failed response, attached to the error
+#[derive(Debug)]
+pub struct Status(pub StatusCode);
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "status: {}", self.0)
+    }
+}
+
+/// How long the server asked to wait before retrying, attached to the error
+#[derive(Debug)]
+pub struct RetryAfter(pub Duration);
+
+impl fmt::Display for RetryAfter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "retry after: {}s", self.0.as_secs())
+    }
+}
+
+/// Delay GitHub asks for before retrying, instead of the usual backoff
+///
+/// Without `Retry-After`, GitHub says to wait at least a minute after a secondary rate limit
+fn retry_after(err: &Report<Error>) -> Option<Duration> {
+    if let Some(RetryAfter(delay)) = err.downcast_ref::<RetryAfter>() {
+        return Some(*delay);
+    }
+    matches!(err.current_context(), Error::SecondaryRateLimit).then_some(Duration::from_secs(60))
+}
+
+/// Check if the error is from network issues or a server error,
+/// which could go away if the request is retried
+fn is_retryable(err: &Report<Error>) -> bool {
+    if matches!(
+        err.current_context(),
+        Error::Timeout | Error::SecondaryRateLimit
+    ) {
+        return true;
+    }
+    let is_retryable_status =
+        |status: StatusCode| status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS;
+    if let Some(Status(status)) = err.downcast_ref::<Status>() {
+        return is_retryable_status(*status);
+    }
+    if let Some(err) = err.downcast_ref::<reqwest::Error>() {
+        if let Some(status) = err.status() {
+            return is_retryable_status(status);
+        }
+        return err.is_timeout() || err.is_connect() || err.is_request() || err.is_body();
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use error_stack::report;
+
+    use super::*;
+
+    /// Run a request that always fails with the status, returning how many times it was sent
+    async fn count_attempts(policy: &RetryPolicy, status: StatusCode) -> u32 {
+        let attempts = AtomicU32::new(0);
+        let result: Result<(), Error> = policy
+            .run("test", &CancellationToken::new(), || async {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                Err(report!(Error::Request).attach_printable(Status(status)))
+            })
+            .await;
+        assert!(result.is_err());
+        attempts.into_inner()
+    }
+
+    #[test]
+    fn test_is_retryable() {
+        let status = |status| report!(Error::Request).attach_printable(Status(status));
+        assert!(is_retryable(&status(StatusCode::BAD_GATEWAY)));
+        assert!(is_retryable(&status(StatusCode::TOO_MANY_REQUESTS)));
+        assert!(!is_retryable(&status(StatusCode::NOT_FOUND)));
+        assert!(!is_retryable(&status(StatusCode::FORBIDDEN)));
+        assert!(!is_retryable(&report!(Error::Request)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_policies_keep_own_count() {
+        let list = RetryPolicy {
+            retries: 1,
+            ..Default::default()
+        };
+        let download = RetryPolicy {
+            retries: 3,
+            ..Default::default()
+        };
+        let error = StatusCode::INTERNAL_SERVER_ERROR;
+        assert_eq!(count_attempts(&list, error).await, 2);
+        assert_eq!(count_attempts(&download, error).await, 4);
+        // the count starts over for each request
+        assert_eq!(count_attempts(&list, error).await, 2);
+        // not retried
+        assert_eq!(count_attempts(&download, StatusCode::NOT_FOUND).await, 1);
+    }
+
+    #[test]
+    fn test_delay() {
+        let policy = RetryPolicy {
+            retries: 10,
+            ..Default::default()
+        };
+        assert_eq!(policy.delay(1), Duration::from_secs(1));
+        assert_eq!(policy.delay(3), Duration::from_secs(4));
+        assert_eq!(policy.delay(10), Duration::from_secs(32));
+    }
+
+    #[test]
+    fn test_log_line() {
+        let err = report!(Error::Request).attach_printable(Status(StatusCode::BAD_GATEWAY));
+        let url = "https://api.github.com/repos/a/b/actions/artifacts/1/zip";
+        assert_eq!(
+            log_line(1700000000, url, 2, 3, &err, Duration::from_secs(2)),
+            format!("1700000000\t{}\t2/3\tstatus: 502 Bad Gateway\t2s\n", url)
+        );
+        let err = report!(Error::Request);
+        assert_eq!(reason(&err), "request failed");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_timeout_is_retried() {
+        let policy = RetryPolicy {
+            retries: 2,
+            timeout: Some(Duration::from_secs(5)),
+            ..Default::default()
+        };
+        let attempts = AtomicU32::new(0);
+        // the first attempt hangs, the second one finishes in time
+        let result = policy
+            .run("test", &CancellationToken::new(), || async {
+                if attempts.fetch_add(1, Ordering::SeqCst) == 0 {
+                    tokio::time::sleep(Duration::from_secs(60)).await;
+                }
+                Ok(())
+            })
+            .await;
+        assert!(result.is_ok());
+        assert_eq!(attempts.into_inner(), 2);
+
+        let policy = RetryPolicy {
+            retries: 1,
+            timeout: Some(Duration::from_secs(5)),
+            ..Default::default()
+        };
+        let attempts = AtomicU32::new(0);
+        let result: Result<(), Error> = policy
+            .run("test", &CancellationToken::new(), || async {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_secs(60)).await;
+                Ok(())
+            })
+            .await;
+        let err = result.unwrap_err();
+        assert!(matches!(err.current_context(), Error::Timeout));
+        assert!(format!("{:?}", err).contains("after 5.0s"));
+        assert_eq!(attempts.into_inner(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_deadline() {
+        let policy = RetryPolicy {
+            retries: 5,
+            deadline: Some(Instant::now() + Duration::from_secs(10)),
+            ..Default::default()
+        };
+        // the attempt is cut short when the deadline passes
+        let result: Result<(), Error> = policy
+            .run("test", &CancellationToken::new(), || async {
+                tokio::time::sleep(Duration::from_secs(60)).await;
+                Ok(())
+            })
+            .await;
+        assert!(matches!(
+            result.unwrap_err().current_context(),
+            Error::Deadline
+        ));
+
+        // the 60s wait after a secondary rate limit would pass the deadline
+        let policy = RetryPolicy {
+            retries: 5,
+            deadline: Some(Instant::now() + Duration::from_secs(10)),
+            ..Default::default()
+        };
+        let attempts = AtomicU32::new(0);
+        let result: Result<(), Error> = policy
+            .run("test", &CancellationToken::new(), || async {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                Err(report!(Error::SecondaryRateLimit))
+            })
+            .await;
+        let err = result.unwrap_err();
+        assert!(format!("{:?}", err).contains("--deadline would pass"));
+        assert_eq!(attempts.into_inner(), 1);
+    }
+
+    #[test]
+    fn test_retry_after() {
+        let err = report!(Error::SecondaryRateLimit);
+        assert_eq!(retry_after(&err), Some(Duration::from_secs(60)));
+        let err = err.attach_printable(RetryAfter(Duration::from_secs(5)));
+        assert_eq!(retry_after(&err), Some(Duration::from_secs(5)));
+        assert!(is_retryable(&err));
+        assert_eq!(retry_after(&report!(Error::Request)), None);
+    }
+
+    fn retryable() -> Report<Error> {
+        report!(Error::Request).attach_printable(Status(StatusCode::BAD_GATEWAY))
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retries() {
+        let policy = RetryPolicy {
+            retries: 2,
+            ..Default::default()
+        };
+        let mut attempts = 0;
+        let result = policy
+            .run("https://api.github.com/", &CancellationToken::new(), || {
+                attempts += 1;
+                let attempt = attempts;
+                async move {
+                    if attempt < 3 {
+                        Err(retryable())
+                    } else {
+                        Ok(attempt)
+                    }
+                }
+            })
+            .await;
+        assert_eq!(result.unwrap(), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_cancel_stops_retrying() {
+        let policy = RetryPolicy {
+            retries: 10,
+            ..Default::default()
+        };
+        let cancel = CancellationToken::new();
+        let mut attempts = 0;
+        let run = policy.run("https://api.github.com/", &cancel, || {
+            attempts += 1;
+            async { Err::<(), _>(retryable()) }
+        });
+        let cancel_later = async {
+            // after the first attempt, while waiting to retry
+            tokio::time::sleep(Duration::from_millis(500)).await;
+            cancel.cancel();
+        };
+        let (result, ()) = tokio::join!(run, cancel_later);
+        let err = result.unwrap_err();
+        assert!(matches!(err.current_context(), Error::Cancelled));
+        assert_eq!(attempts, 1);
+    }
+
+    #[tokio::test]
+    async fn test_cancel_stops_attempt() {
+        let policy = RetryPolicy {
+            retries: 0,
+            ..Default::default()
+        };
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        let result = policy
+            .run("https://api.github.com/", &cancel, || {
+                std::future::pending::<Result<(), Error>>()
+            })
+            .await;
+        assert!(matches!(
+            result.unwrap_err().current_context(),
+            Error::Cancelled
+        ));
+    }
+
+    #[test]
+    fn test_redact_url() {
+        let api_url = "https://api.github.com";
+        let url = "https://api.github.com/repos/a/b/actions/artifacts?per_page=100&page=2";
+        assert_eq!(redact_url(url, api_url), url);
+        assert_eq!(
+            redact_url(
+                "https://storage.example.com/artifact.zip?sig=secret&se=1",
+                api_url
+            ),
+            "https://storage.example.com/artifact.zip?[REDACTED]"
+        );
+        assert_eq!(
+            redact_url("https://storage.example.com/artifact.zip", api_url),
+            "https://storage.example.com/artifact.zip"
+        );
+        // with --api-url
+        let api_url = "https://ghes.example.com/api/v3/";
+        let url = "https://ghes.example.com/api/v3/repos/a/b/actions/artifacts?page=2";
+        assert_eq!(redact_url(url, api_url), url);
+        assert_eq!(
+            redact_url("https://ghes.example.com/api/v3x/a?sig=secret", api_url),
+            "https://ghes.example.com/api/v3x/a?[REDACTED]"
+        );
+    }
+}
@@ -0,0 +1,178 @@
+//! Inventory of the pulled artifacts as a minimal CycloneDX document, for --sbom
+
+use std::path::Path;
+
+use error_stack::{Result, ResultExt};
+use tokio::fs;
+
+use crate::Error;
+
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Sbom {
+    bom_format: &'static str,
+    spec_version: &'static str,
+    version: u32,
+    metadata: Metadata,
+    components: Vec<Component>,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct Metadata {
+    tools: Tools,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct Tools {
+    components: Vec<Tool>,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct Tool {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    name: &'static str,
+    version: &'static str,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct Component {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    #[serde(rename = "bom-ref")]
+    bom_ref: String,
+    name: String,
+    hashes: Vec<Hash>,
+    #[serde(rename = "externalReferences")]
+    external_references: Vec<ExternalReference>,
+    properties: Vec<Property>,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct Hash {
+    alg: &'static str,
+    content: String,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct ExternalReference {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    url: String,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct Property {
+    name: &'static str,
+    value: String,
+}
+
+impl Sbom {
+    pub fn new(mut components: Vec<Component>) -> Self {
+        components.sort_by(|a, b| a.name.cmp(&b.name).then(a.bom_ref.cmp(&b.bom_ref)));
+        Self {
+            bom_format: "CycloneDX",
+            spec_version: "1.5",
+            version: 1,
+            metadata: Metadata {
+                tools: Tools {
+                    components: vec![Tool {
+                        kind: "application",
+                        name: env!("CARGO_PKG_NAME"),
+                        version: env!("CARGO_PKG_VERSION"),
+                    }],
+                },
+            },
+            components,
+        }
+    }
+
+    pub async fn save(&self, path: &Path) -> Result<(), Error> {
+        let json = serde_json::to_vec_pretty(self).change_context(Error::Sbom)?;
+        fs::write(path, json)
+            .await
+            .change_context(Error::Sbom)
+            .attach_printable_lazy(|| format!("path: {}", path.display()))
+    }
+}
+
+impl Component {
+    /// Component for an artifact archive, by its SHA-256 and where it came from
+    pub fn new(
+        id: u64,
+        name: &str,
+        sha256: &str,
+        repo: &str,
+        commit: &str,
+        download_url: &str,
+    ) -> Self {
+        Self {
+            kind: "file",
+            bom_ref: format!("artifact-{}", id),
+            name: name.to_string(),
+            hashes: vec![Hash {
+                alg: "SHA-256",
+                content: sha256.to_string(),
+            }],
+            external_references: vec![
+                ExternalReference {
+                    kind: "distribution",
+                    url: download_url.to_string(),
+                },
+                ExternalReference {
+                    kind: "vcs",
+                    url: format!("https://github.com/{}", repo),
+                },
+            ],
+            properties: vec![
+                Property {
+                    name: "magnesis:repo",
+                    value: repo.to_string(),
+                },
+                Property {
+                    name: "magnesis:commit",
+                    value: commit.to_string(),
+                },
+            ],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_util::TempDir;
+
+    #[tokio::test]
+    async fn test_save() {
+        let sbom = Sbom::new(vec![
+            Component::new(2, "docs", "bbb", "foo/bar", "abc", "https://example.com/2"),
+            Component::new(1, "app", "aaa", "foo/bar", "abc", "https://example.com/1"),
+        ]);
+        let dir = TempDir::new();
+        let path = dir.join("sbom.json");
+        sbom.save(&path).await.unwrap();
+
+        let json: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        assert_eq!(json["bomFormat"], "CycloneDX");
+        assert_eq!(json["specVersion"], "1.5");
+        assert_eq!(
+            json["metadata"]["tools"]["components"][0]["name"],
+            "magnesis"
+        );
+        let app = &json["components"][0];
+        assert_eq!(app["bom-ref"], "artifact-1");
+        assert_eq!(app["name"], "app");
+        assert_eq!(app["hashes"][0]["alg"], "SHA-256");
+        assert_eq!(app["hashes"][0]["content"], "aaa");
+        assert_eq!(app["externalReferences"][0]["url"], "https://example.com/1");
+        assert_eq!(
+            app["externalReferences"][1]["url"],
+            "https://github.com/foo/bar"
+        );
+        assert_eq!(app["properties"][1]["value"], "abc");
+        assert_eq!(json["components"][1]["name"], "docs");
+    }
+}
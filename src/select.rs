@@ -0,0 +1,811 @@
+//! Finding the artifacts to pull: listing them for the selection,
+//! then filtering them with the options
+
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    path::Path,
+    process::Stdio,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use error_stack::{report, Report, Result, ResultExt};
+use tokio::{
+    fs, io::AsyncWriteExt, process::Command, sync::Semaphore, task::JoinHandle, task::JoinSet,
+};
+
+use crate::{
+    artifact::{
+        filter_by_attempt, filter_by_job, get_artifacts, get_check_suite_artifacts,
+        get_workflow_run_artifacts, sort_artifacts, Artifact,
+    },
+    cli::Cli,
+    git::verify_commit,
+    github::{get_commit_files, get_commit_sha, get_pull_request_head, get_run, Api},
+    glob::glob_match,
+    output::{inherit, progress, warning},
+    preview::list_entries,
+    state::Selection,
+    timings::{timed, Timings},
+    Error,
+};
+
+/// How long to wait between listings for --retry-empty
+const RETRY_EMPTY_DELAY: Duration = Duration::from_secs(10);
+
+/// Artifacts listed for the selection, before they are filtered
+pub struct Listing {
+    pub artifacts: Vec<Artifact>,
+    /// Commit the artifacts are from
+    pub rev: String,
+    /// If the commit is from the local git repo, so it can be cached
+    pub rev_from_git: bool,
+}
+
+/// List the artifacts of the pull request, workflow run, check suite or revision
+///
+/// `local_rev` is the revision from the local git repo, if it can be used
+pub async fn list_artifacts(
+    api: &Arc<Api>,
+    repo: &str,
+    cli: &Cli,
+    selection: &Selection,
+    local_rev: Option<JoinHandle<(Result<String, Error>, Duration)>>,
+    timings: &mut Timings,
+) -> Result<Listing, Error> {
+    let jobs = cli.jobs;
+    let listing = |artifacts, rev| Listing {
+        artifacts,
+        rev,
+        rev_from_git: false,
+    };
+    match (selection.check_suite, selection.pr, selection.run) {
+        (_, Some(pr), _) => {
+            let head = get_pull_request_head(api, repo, pr).await?;
+            progress!(
+                "finding artifacts for pull request #{} at `{}`",
+                pr,
+                head.sha
+            );
+            let (artifacts, elapsed) = timed(get_artifacts(api, repo, jobs)).await;
+            timings.list = elapsed;
+            let mut artifacts = artifacts
+                .change_context(Error::GetArtifacts)?
+                .into_filtered_by_rev(&head.sha);
+            // pull requests from forks usually run in the base repo,
+            // but the fork could have its own runs
+            let head_repo = head
+                .repo
+                .map(|r| r.full_name)
+                .filter(|r| r != repo && artifacts.is_empty());
+            if let Some(head_repo) = head_repo {
+                progress!("no artifacts in `{}`, checking fork `{}`", repo, head_repo);
+                artifacts = get_artifacts(api, &head_repo, jobs)
+                    .await
+                    .change_context(Error::GetArtifacts)
+                    .attach_printable_lazy(|| {
+                        format!("the token needs read access to `{}`", head_repo)
+                    })?
+                    .into_filtered_by_rev(&head.sha);
+            }
+            Ok(listing(artifacts, head.sha))
+        }
+        (_, None, Some(run)) => {
+            progress!("finding artifacts for workflow run `{}`", run);
+            let (result, elapsed) = timed(get_workflow_run_artifacts(api, repo, run, jobs)).await;
+            timings.list = elapsed;
+            let (artifacts, rev) = result.change_context(Error::GetArtifacts)?;
+            Ok(listing(artifacts, rev))
+        }
+        (Some(check_suite), None, None) => {
+            progress!("finding artifacts for check suite `{}`", check_suite);
+            let (result, elapsed) =
+                timed(get_check_suite_artifacts(api, repo, check_suite, jobs)).await;
+            timings.list = elapsed;
+            let (artifacts, rev) = result.change_context(Error::GetArtifacts)?;
+            Ok(listing(artifacts, rev))
+        }
+        (None, None, None) => {
+            let (artifacts, elapsed) = timed(get_artifacts(api, repo, jobs)).await;
+            timings.list = elapsed;
+            let artifacts = artifacts.change_context(Error::GetArtifacts)?;
+            let rev_from_git = local_rev.is_some();
+            let (rev, elapsed) = match local_rev {
+                Some(local_rev) => local_rev.await.change_context(Error::Rev)?,
+                None => timed(get_commit_sha(api, repo, &selection.rev)).await,
+            };
+            timings.rev = elapsed;
+            let rev = rev.attach_printable(
+                "please specify the revision with --rev or see GitHub README for more details",
+            )?;
+            if cli.verify_reachable {
+                verify_commit(&rev, Duration::from_secs(cli.git_timeout)).await?;
+            }
+            progress!("finding artifacts for revision `{}`", rev);
+            let mut artifacts = artifacts.into_filtered_by_rev(&rev);
+            // artifacts can show up a bit after the run finishes uploading them
+            let deadline = Instant::now() + Duration::from_secs(cli.retry_empty);
+            while artifacts.is_empty() && Instant::now() < deadline {
+                let delay = RETRY_EMPTY_DELAY.min(deadline - Instant::now());
+                progress!(
+                    "no artifacts yet, listing again in {:.0}s",
+                    delay.as_secs_f64().ceil()
+                );
+                tokio::select! {
+                    () = tokio::time::sleep(delay) => {}
+                    () = api.cancel().cancelled() => return Err(report!(Error::Cancelled)),
+                }
+                artifacts = get_artifacts(api, repo, jobs)
+                    .await
+                    .change_context(Error::GetArtifacts)?
+                    .into_filtered_by_rev(&rev);
+            }
+            Ok(Listing {
+                artifacts,
+                rev,
+                rev_from_git,
+            })
+        }
+    }
+}
+
+/// Keep the artifacts that pass the filters from the options, sorted, or in the order of --index
+///
+/// Also returns the timestamp from the --newer-than-file, if there is one
+pub async fn filter_artifacts(
+    api: &Arc<Api>,
+    repo: &str,
+    mut artifacts: Vec<Artifact>,
+    cli: &Cli,
+) -> Result<(Vec<Artifact>, Option<String>), Error> {
+    if !cli.name.is_empty() {
+        for pattern in &cli.name {
+            if !artifacts
+                .iter()
+                .any(|artifact| glob_match(pattern, &artifact.name))
+            {
+                warning!("--name `{}` did not match any artifact", pattern);
+            }
+        }
+        artifacts.retain(|artifact| {
+            cli.name
+                .iter()
+                .any(|pattern| glob_match(pattern, &artifact.name))
+        });
+    }
+    if !cli.label.is_empty() {
+        artifacts.retain(|artifact| {
+            artifact
+                .label()
+                .is_some_and(|artifact_label| cli.label.iter().any(|l| l == artifact_label))
+        });
+        if artifacts.is_empty() {
+            warning!("no artifacts have a label from --label");
+        }
+    }
+    if !cli.job.is_empty() {
+        artifacts = filter_by_job(api, repo, artifacts, &cli.job).await?;
+    }
+    if let Some(attempt) = cli.attempt {
+        artifacts = filter_by_attempt(api, repo, artifacts, attempt).await?;
+    }
+    if !cli.contains.is_empty() && !artifacts.is_empty() {
+        artifacts = filter_by_contents(api, artifacts, &cli.contains, cli.jobs).await?;
+        if artifacts.is_empty() {
+            warning!("no artifacts contain files matching --contains");
+        }
+    }
+    if let Some(pattern) = &cli.title_match {
+        artifacts = filter_by_run_title(api, repo, artifacts, pattern).await?;
+        if artifacts.is_empty() {
+            warning!("no workflow runs have a title matching --title-match");
+        }
+    }
+    if !cli.changed_path.is_empty() && !artifacts.is_empty() {
+        artifacts = filter_by_changed_path(api, repo, artifacts, &cli.changed_path).await?;
+        if artifacts.is_empty() {
+            warning!("no head commits changed files matching --changed-path");
+        }
+    }
+    if let Some(script) = &cli.select_script {
+        if !artifacts.is_empty() {
+            artifacts = filter_by_script(script, artifacts).await?;
+            if artifacts.is_empty() {
+                warning!("--select-script excluded all artifacts");
+            }
+        }
+    }
+    let marker = match &cli.newer_than_file {
+        Some(path) => read_marker(path).await?,
+        None => None,
+    };
+    if let Some(marker) = &marker {
+        retain_newer_than(&mut artifacts, marker);
+    }
+    sort_artifacts(&mut artifacts);
+    if !cli.index.is_empty() {
+        artifacts = select_by_index(artifacts, &cli.index)?;
+    }
+    if let Some(warn_age) = cli.warn_age {
+        for artifact in &artifacts {
+            if let Some(age) = artifact.age().filter(|age| *age > warn_age) {
+                warning!(
+                    "`{}` was uploaded {} days ago and may expire soon",
+                    artifact.name,
+                    age.as_secs() / 86400
+                );
+            }
+        }
+    }
+    Ok((artifacts, marker))
+}
+
+/// Pick the artifacts at the 1-based indices, in the order of the indices
+fn select_by_index(artifacts: Vec<Artifact>, index: &[usize]) -> Result<Vec<Artifact>, Error> {
+    let len = artifacts.len();
+    if let Some(i) = index.iter().find(|i| **i == 0 || **i > len) {
+        return Err(report!(Error::InvalidIndex))
+            .attach_printable(format!("index: {}", i))
+            .attach_printable(format!("there are {} artifacts for the revision", len));
+    }
+    let mut artifacts = artifacts.into_iter().map(Some).collect::<Vec<_>>();
+    Ok(index
+        .iter()
+        .filter_map(|i| artifacts[i - 1].take())
+        .collect())
+}
+
+/// Keep the artifacts containing a file that matches any of the globs, for --contains
+async fn filter_by_contents(
+    api: &Arc<Api>,
+    artifacts: Vec<Artifact>,
+    patterns: &[String],
+    jobs: usize,
+) -> Result<Vec<Artifact>, Error> {
+    let permits = Arc::new(Semaphore::new(jobs.max(1)));
+    let mut handles = JoinSet::new();
+    for (i, artifact) in artifacts.iter().enumerate() {
+        let api = Arc::clone(api);
+        let permits = Arc::clone(&permits);
+        let url = artifact.archive_download_url.clone();
+        let name = artifact.name.clone();
+        handles.spawn(inherit(async move {
+            let _permit = permits.acquire().await.change_context(Error::Preview)?;
+            let entries = api
+                .list_retry()
+                .run(&api.redacted_url(&url), api.cancel(), || {
+                    list_entries(&api, &url)
+                })
+                .await
+                .attach_printable_lazy(|| format!("artifact: {}", name))?;
+            Ok::<_, Report<Error>>((i, entries))
+        }));
+    }
+    let mut keep = HashSet::new();
+    while let Some(result) = handles.join_next().await {
+        let (i, entries) = result.change_context(Error::Preview)??;
+        let matches = entries
+            .iter()
+            .any(|entry| patterns.iter().any(|pattern| glob_match(pattern, entry)));
+        if matches {
+            keep.insert(i);
+        }
+    }
+    Ok(artifacts
+        .into_iter()
+        .enumerate()
+        .filter_map(|(i, artifact)| keep.contains(&i).then_some(artifact))
+        .collect())
+}
+
+/// Keep the artifacts from workflow runs with a title matching the glob, for --title-match
+async fn filter_by_run_title(
+    api: &Api,
+    repo: &str,
+    artifacts: Vec<Artifact>,
+    pattern: &str,
+) -> Result<Vec<Artifact>, Error> {
+    let run_ids = artifacts
+        .iter()
+        .filter_map(|artifact| artifact.workflow_run.id)
+        .collect::<BTreeSet<_>>();
+    let mut titles = HashMap::new();
+    for id in run_ids {
+        let run = get_run(api, repo, id).await?;
+        titles.insert(id, run.display_title.unwrap_or_default());
+    }
+    Ok(select_by_run_title(artifacts, &titles, pattern))
+}
+
+fn select_by_run_title(
+    mut artifacts: Vec<Artifact>,
+    titles: &HashMap<u64, String>,
+    pattern: &str,
+) -> Vec<Artifact> {
+    artifacts.retain(|artifact| {
+        artifact
+            .workflow_run
+            .id
+            .and_then(|id| titles.get(&id))
+            .is_some_and(|title| glob_match(pattern, title))
+    });
+    artifacts
+}
+
+/// Keep the artifacts whose head commit changed a file matching any of the globs,
+/// for --changed-path
+async fn filter_by_changed_path(
+    api: &Api,
+    repo: &str,
+    artifacts: Vec<Artifact>,
+    patterns: &[String],
+) -> Result<Vec<Artifact>, Error> {
+    let shas = artifacts
+        .iter()
+        .map(|artifact| artifact.workflow_run.head_sha.clone())
+        .collect::<BTreeSet<_>>();
+    let mut changed_files = HashMap::new();
+    for sha in shas {
+        let files = get_commit_files(api, repo, &sha).await?;
+        changed_files.insert(sha, files);
+    }
+    Ok(select_by_changed_path(artifacts, &changed_files, patterns))
+}
+
+fn select_by_changed_path(
+    mut artifacts: Vec<Artifact>,
+    changed_files: &HashMap<String, Vec<String>>,
+    patterns: &[String],
+) -> Vec<Artifact> {
+    artifacts.retain(|artifact| {
+        changed_files
+            .get(&artifact.workflow_run.head_sha)
+            .is_some_and(|files| {
+                files
+                    .iter()
+                    .any(|file| patterns.iter().any(|pattern| glob_match(pattern, file)))
+            })
+    });
+    artifacts
+}
+
+/// Keep the artifacts the --select-script exits successfully for
+async fn filter_by_script(
+    script: &Path,
+    mut artifacts: Vec<Artifact>,
+) -> Result<Vec<Artifact>, Error> {
+    let mut keep = HashSet::new();
+    for artifact in &artifacts {
+        let json = serde_json::to_vec(artifact).change_context(Error::Command)?;
+        let mut child = Command::new(script)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .spawn()
+            .change_context(Error::Command)
+            .attach_printable_lazy(|| format!("script: {}", script.display()))?;
+        if let Some(mut stdin) = child.stdin.take() {
+            // the script can exit without reading it
+            let _ = stdin.write_all(&json).await;
+        }
+        let status = child
+            .wait()
+            .await
+            .change_context(Error::Command)
+            .attach_printable_lazy(|| format!("script: {}", script.display()))?;
+        if status.success() {
+            keep.insert(artifact.id);
+        } else {
+            progress!("`{}` excluded by --select-script", artifact.name);
+        }
+    }
+    artifacts.retain(|artifact| keep.contains(&artifact.id));
+    Ok(artifacts)
+}
+
+/// Read the timestamp from the --newer-than-file, or `None` if the file doesn't exist
+async fn read_marker(path: &Path) -> Result<Option<String>, Error> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let marker = fs::read_to_string(path)
+        .await
+        .change_context(Error::Marker)
+        .attach_printable_lazy(|| format!("path: {}", path.display()))?;
+    Ok(Some(marker.trim().to_string()).filter(|marker| !marker.is_empty()))
+}
+
+/// Keep the artifacts created after the timestamp from the --newer-than-file
+fn retain_newer_than(artifacts: &mut Vec<Artifact>, marker: &str) {
+    artifacts.retain(|artifact| {
+        artifact
+            .created_at
+            .as_deref()
+            .is_some_and(|created_at| created_at > marker)
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use clap::Parser;
+
+    use crate::test_util::{artifacts, mock_api, MockServer, Response, TempDir};
+
+    #[tokio::test]
+    async fn test_list_timings() {
+        let server = MockServer::start(|request| {
+            std::thread::sleep(Duration::from_millis(20));
+            match request.path.as_str() {
+                "/repos/foo/bar/actions/artifacts?per_page=100&page=1" => {
+                    Response::json(r#"{"total_count":0,"artifacts":[]}"#)
+                }
+                "/repos/foo/bar/commits/main" => {
+                    Response::json(format!(r#"{{"sha":"{}"}}"#, "a".repeat(40)))
+                }
+                _ => Response::new(404),
+            }
+        })
+        .await;
+        let cli = Cli::try_parse_from(["magnesis", "-o", "out"]).unwrap();
+        let selection = Selection {
+            rev: "main".to_string(),
+            pr: None,
+            run: None,
+            check_suite: None,
+        };
+        let mut timings = Timings::default();
+        let listing = list_artifacts(
+            &mock_api(&server),
+            "foo/bar",
+            &cli,
+            &selection,
+            None,
+            &mut timings,
+        )
+        .await
+        .unwrap();
+        assert_eq!(listing.rev, "a".repeat(40));
+        assert!(timings.list >= Duration::from_millis(20), "{:?}", timings);
+        assert!(timings.rev >= Duration::from_millis(20), "{:?}", timings);
+    }
+
+    #[tokio::test]
+    async fn test_list_short_sha() {
+        let full = format!("abc1234{}", "0".repeat(33));
+        let server = {
+            let full = full.clone();
+            MockServer::start(move |request| match request.path.as_str() {
+                "/repos/foo/bar/actions/artifacts?per_page=100&page=1" => {
+                    let artifact = |id: u64, sha: &str| {
+                        serde_json::json!({
+                            "id": id,
+                            "name": "app",
+                            "archive_download_url": "",
+                            "workflow_run": { "head_sha": sha },
+                        })
+                    };
+                    let listing = serde_json::json!({
+                        "total_count": 2,
+                        "artifacts": [artifact(1, &full), artifact(2, &"b".repeat(40))],
+                    });
+                    Response::json(listing.to_string())
+                }
+                "/repos/foo/bar/commits/abc1234" => {
+                    Response::json(format!(r#"{{"sha":"{}"}}"#, full))
+                }
+                _ => Response::new(404),
+            })
+            .await
+        };
+        let cli = Cli::try_parse_from(["magnesis", "-o", "out"]).unwrap();
+        let selection = Selection {
+            rev: "abc1234".to_string(),
+            pr: None,
+            run: None,
+            check_suite: None,
+        };
+        let listing = list_artifacts(
+            &mock_api(&server),
+            "foo/bar",
+            &cli,
+            &selection,
+            None,
+            &mut Timings::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(listing.rev, full);
+        let ids = listing
+            .artifacts
+            .iter()
+            .map(|artifact| artifact.id)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, [1]);
+    }
+
+    #[tokio::test]
+    async fn test_list_fork_pull_request() {
+        let head = "c".repeat(40);
+        let server = {
+            let head = head.clone();
+            MockServer::start(move |request| {
+                let listing = |id: u64, sha: &str| {
+                    let listing = serde_json::json!({
+                        "total_count": 1,
+                        "artifacts": [{
+                            "id": id,
+                            "name": "app",
+                            "archive_download_url": "",
+                            "workflow_run": { "head_sha": sha },
+                        }],
+                    });
+                    Response::json(listing.to_string())
+                };
+                match request.path.as_str() {
+                    "/repos/foo/bar/pulls/5" => Response::json(format!(
+                        r#"{{"head":{{"sha":"{}","repo":{{"full_name":"fork/bar"}}}}}}"#,
+                        head
+                    )),
+                    // the base repo only has artifacts of other commits
+                    "/repos/foo/bar/actions/artifacts?per_page=100&page=1" => {
+                        listing(1, &"a".repeat(40))
+                    }
+                    "/repos/fork/bar/actions/artifacts?per_page=100&page=1" => listing(2, &head),
+                    _ => Response::new(404),
+                }
+            })
+            .await
+        };
+        let cli = Cli::try_parse_from(["magnesis", "-o", "out"]).unwrap();
+        let selection = Selection {
+            rev: String::new(),
+            pr: Some(5),
+            run: None,
+            check_suite: None,
+        };
+        let listing = list_artifacts(
+            &mock_api(&server),
+            "foo/bar",
+            &cli,
+            &selection,
+            None,
+            &mut Timings::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(listing.rev, head);
+        let ids = listing
+            .artifacts
+            .iter()
+            .map(|artifact| artifact.id)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, [2]);
+    }
+
+    #[tokio::test]
+    async fn test_retry_empty() {
+        let sha = "a".repeat(40);
+        let listings = Arc::new(AtomicUsize::new(0));
+        let server = {
+            let sha = sha.clone();
+            let listings = Arc::clone(&listings);
+            MockServer::start(move |request| {
+                if request.path != "/repos/foo/bar/actions/artifacts?per_page=100&page=1" {
+                    return Response::new(404);
+                }
+                // the artifacts show up after the first listing
+                let artifacts = if listings.fetch_add(1, Ordering::SeqCst) == 0 {
+                    serde_json::json!([])
+                } else {
+                    serde_json::json!([{
+                        "id": 1,
+                        "name": "app",
+                        "archive_download_url": "",
+                        "workflow_run": { "head_sha": sha },
+                    }])
+                };
+                let listing = serde_json::json!({
+                    "total_count": artifacts.as_array().unwrap().len(),
+                    "artifacts": artifacts,
+                });
+                Response::json(listing.to_string())
+            })
+            .await
+        };
+        let cli = Cli::try_parse_from(["magnesis", "-o", "out", "--retry-empty", "1"]).unwrap();
+        let selection = Selection {
+            rev: sha,
+            pr: None,
+            run: None,
+            check_suite: None,
+        };
+        let listing = list_artifacts(
+            &mock_api(&server),
+            "foo/bar",
+            &cli,
+            &selection,
+            None,
+            &mut Timings::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(listing.artifacts.len(), 1);
+        assert_eq!(listings.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_select_by_index() {
+        let all = ["a", "b", "c", "d", "e"];
+        let selected = select_by_index(artifacts(&all), &[1, 3, 5]).unwrap();
+        let names = selected
+            .iter()
+            .map(|artifact| artifact.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["a", "c", "e"]);
+        // in the order of the indices, and repeated indices select the artifact once
+        let selected = select_by_index(artifacts(&all), &[4, 2, 2]).unwrap();
+        let names = selected
+            .iter()
+            .map(|artifact| artifact.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["d", "b"]);
+        assert!(select_by_index(artifacts(&all), &[0]).is_err());
+        assert!(select_by_index(artifacts(&all), &[6]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_newer_than_file() {
+        let dir = TempDir::new();
+        let path = dir.join("marker");
+        assert_eq!(read_marker(&path).await.unwrap(), None);
+        std::fs::write(&path, "2024-01-02T00:00:00Z\n").unwrap();
+        let marker = read_marker(&path).await.unwrap().unwrap();
+        assert_eq!(marker, "2024-01-02T00:00:00Z");
+
+        let mut artifacts = artifacts(&["old", "same", "new", "unknown"]);
+        for (artifact, created_at) in artifacts.iter_mut().zip([
+            Some("2024-01-01T00:00:00Z"),
+            Some("2024-01-02T00:00:00Z"),
+            Some("2024-01-03T00:00:00Z"),
+            None,
+        ]) {
+            artifact.created_at = created_at.map(str::to_string);
+        }
+        retain_newer_than(&mut artifacts, &marker);
+        assert_eq!(artifacts.len(), 1);
+        assert_eq!(artifacts[0].name, "new");
+    }
+
+    #[test]
+    fn test_select_by_run_title() {
+        let mut all = artifacts(&["a", "b", "c"]);
+        for (artifact, run_id) in all.iter_mut().zip([1, 2, 3]) {
+            artifact.workflow_run.id = Some(run_id);
+        }
+        let titles = HashMap::from([
+            (1, "Release v1.0".to_string()),
+            (2, "Nightly".to_string()),
+            (3, "Release v2.0 (dry run)".to_string()),
+        ]);
+        let names = |artifacts: Vec<Artifact>| {
+            artifacts
+                .into_iter()
+                .map(|artifact| artifact.name)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            names(select_by_run_title(all.clone(), &titles, "Release v*")),
+            ["a", "c"]
+        );
+        assert_eq!(
+            names(select_by_run_title(all.clone(), &titles, "Nightly")),
+            ["b"]
+        );
+        // artifacts without a run, or with an unknown run, are left out
+        all[0].workflow_run.id = None;
+        all[2].workflow_run.id = Some(4);
+        assert!(select_by_run_title(all, &titles, "Release*").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_filter_by_changed_path() {
+        let server = MockServer::start(|request| {
+            let files: Vec<String> = match request.path.as_str() {
+                // the matching file is on the second page
+                "/repos/foo/bar/commits/abc?per_page=100&page=1" => {
+                    (0..100).map(|i| format!("docs/{}.md", i)).collect()
+                }
+                "/repos/foo/bar/commits/abc?per_page=100&page=2" => vec!["src/lib.rs".to_string()],
+                "/repos/foo/bar/commits/def?per_page=100&page=1" => vec!["README.md".to_string()],
+                _ => return Response::new(404),
+            };
+            let files = files
+                .iter()
+                .map(|file| serde_json::json!({ "filename": file }))
+                .collect::<Vec<_>>();
+            let commit = serde_json::json!({ "sha": "abc", "files": files });
+            Response::json(commit.to_string())
+        })
+        .await;
+        let mut all = artifacts(&["a", "b"]);
+        for (artifact, sha) in all.iter_mut().zip(["abc", "def"]) {
+            artifact.workflow_run.head_sha = sha.to_string();
+        }
+
+        let filtered =
+            filter_by_changed_path(&mock_api(&server), "foo/bar", all, &["src/*".to_string()])
+                .await
+                .unwrap();
+        let names = filtered
+            .iter()
+            .map(|artifact| artifact.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["a"]);
+        assert_eq!(server.requests().len(), 3);
+    }
+
+    #[test]
+    fn test_select_by_changed_path() {
+        let mut all = artifacts(&["a", "b", "c"]);
+        for (artifact, sha) in all.iter_mut().zip(["abc", "def", "ghi"]) {
+            artifact.workflow_run.head_sha = sha.to_string();
+        }
+        let changed_files = HashMap::from([
+            ("abc".to_string(), vec!["src/lib.rs".to_string()]),
+            (
+                "def".to_string(),
+                vec!["README.md".to_string(), "docs/index.md".to_string()],
+            ),
+            ("ghi".to_string(), vec![]),
+        ]);
+        let names = |patterns: &[&str]| {
+            let patterns = patterns.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+            select_by_changed_path(all.clone(), &changed_files, &patterns)
+                .into_iter()
+                .map(|artifact| artifact.name)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(&["src/*"]), ["a"]);
+        assert_eq!(names(&["docs/*", "src/*.rs"]), ["a", "b"]);
+        assert!(names(&["*.toml"]).is_empty());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_filter_by_script() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = TempDir::new();
+        let script = dir.join("select.sh");
+        std::fs::write(&script, "#!/bin/sh\ngrep -q '\"name\":\"keep-'\n").unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let artifacts = ["keep-linux", "drop", "keep-windows"]
+            .into_iter()
+            .enumerate()
+            .map(|(id, name)| {
+                serde_json::from_value(serde_json::json!({
+                    "id": id,
+                    "name": name,
+                    "archive_download_url": "",
+                    "workflow_run": { "head_sha": "abc" },
+                }))
+                .unwrap()
+            })
+            .collect::<Vec<Artifact>>();
+
+        let selected = filter_by_script(&script, artifacts).await.unwrap();
+        let names = selected
+            .iter()
+            .map(|artifact| artifact.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["keep-linux", "keep-windows"]);
+
+        let err = filter_by_script(&dir.join("missing.sh"), selected)
+            .await
+            .unwrap_err();
+        assert!(format!("{:?}", err).contains("missing.sh"));
+    }
+}
@@ -0,0 +1,218 @@
+//! Cache shared between pulls (and projects) for --shared-cache
+//!
+//! Downloaded archives are kept by artifact ID, since artifacts don't change once uploaded.
+//! Extracted files are kept once by their content (and mode), and the output
+//! gets hard links to them instead of copies.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use error_stack::{report, Result, ResultExt};
+
+use crate::{atomic_file, extract::OnConflict, output::progress, sink::OutputSink, Error};
+
+#[derive(Debug)]
+pub struct SharedCache {
+    dir: PathBuf,
+}
+
+impl SharedCache {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    fn zip_path(&self, id: u64) -> PathBuf {
+        self.dir.join("zips").join(format!("{}.zip", id))
+    }
+
+    /// Get the archive of the artifact, if it was downloaded before
+    pub async fn read_zip(&self, id: u64) -> Option<Vec<u8>> {
+        tokio::fs::read(self.zip_path(id)).await.ok()
+    }
+
+    /// Save the downloaded archive of the artifact
+    pub async fn save_zip(&self, id: u64, bytes: &[u8]) -> Result<(), Error> {
+        let path = self.zip_path(id);
+        let write = async {
+            tokio::fs::create_dir_all(self.dir.join("zips")).await?;
+            atomic_file::write(&path, bytes.to_vec()).await
+        };
+        write
+            .await
+            .change_context(Error::SharedCache)
+            .attach_printable_lazy(|| format!("path: {}", path.display()))
+    }
+
+    /// Store the file content in the cache if it's not there yet, and link it to `path`
+    /// in the output. If the link can't be made, the content is written instead
+    ///
+    /// Returns `false` if `path` exists and is skipped because of `on_conflict`
+    pub fn link_file(
+        &self,
+        sink: &dyn OutputSink,
+        content: &[u8],
+        sha256: &str,
+        mode: Option<u32>,
+        path: &Path,
+        on_conflict: OnConflict,
+    ) -> Result<bool, Error> {
+        let object = self.store(content, sha256, mode)?;
+        if matches!(on_conflict, OnConflict::Overwrite | OnConflict::Newest) {
+            match sink.remove(path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                    return Err(report!(e)
+                        .change_context(Error::Extract)
+                        .attach_printable(format!("path: {}", path.display())));
+                }
+                _ => {}
+            }
+        }
+        let result = match sink.hard_link(&object, path) {
+            // for example, the cache is on another file system, or the output isn't local
+            Err(e) if e.kind() != io::ErrorKind::AlreadyExists => sink
+                .write_file(path, content, false)
+                .and_then(|()| match mode {
+                    Some(mode) => sink.set_mode(path, mode),
+                    None => Ok(()),
+                }),
+            result => result,
+        };
+        match result {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                if on_conflict == OnConflict::Skip {
+                    progress!("skipping existing file `{}`", path.display());
+                    return Ok(false);
+                }
+                Err(report!(e)
+                    .change_context(Error::Extract)
+                    .attach_printable(format!("path: {}", path.display()))
+                    .attach_printable("file already exists, see --on-conflict"))
+            }
+            Err(e) => Err(report!(e)
+                .change_context(Error::Extract)
+                .attach_printable(format!("path: {}", path.display()))),
+        }
+    }
+
+    /// Write the content to the cache, unless it's already there
+    fn store(&self, content: &[u8], sha256: &str, mode: Option<u32>) -> Result<PathBuf, Error> {
+        let objects = self.dir.join("objects");
+        let name = match mode {
+            Some(mode) if cfg!(unix) => format!("{}-{:o}", sha256, mode & 0o7777),
+            _ => sha256.to_string(),
+        };
+        let object = objects.join(name);
+        if object.exists() {
+            return Ok(object);
+        }
+        let write = || {
+            fs::create_dir_all(&objects)?;
+            // another artifact could be storing the same content at the same time
+            atomic_file::write_blocking(&object, content, mode)
+        };
+        write()
+            .change_context(Error::SharedCache)
+            .attach_printable_lazy(|| format!("path: {}", object.display()))?;
+        Ok(object)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        extract::{extract, ExtractOptions},
+        test_util::{zip_file, TempDir},
+    };
+
+    #[tokio::test]
+    async fn test_zip() {
+        let dir = TempDir::new();
+        let cache = SharedCache::new(dir.join("cache"));
+        assert!(cache.read_zip(1).await.is_none());
+        cache.save_zip(1, b"zip").await.unwrap();
+        assert_eq!(cache.read_zip(1).await.unwrap(), b"zip");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_second_pull_links_cached_file() {
+        use std::os::unix::fs::MetadataExt;
+
+        let dir = TempDir::new();
+        let options = ExtractOptions {
+            shared_cache: Some(SharedCache::new(dir.join("cache"))),
+            ..Default::default()
+        };
+        let zip = zip_file(&[("a.txt", b"hello")]);
+        extract(&zip, &dir.join("first"), &options, None, "").unwrap();
+        let object = fs::read_dir(dir.join("cache/objects"))
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .path();
+        let object_ino = fs::metadata(&object).unwrap().ino();
+        extract(&zip, &dir.join("second"), &options, None, "").unwrap();
+
+        for output in ["first", "second"] {
+            let metadata = fs::metadata(dir.join(output).join("a.txt")).unwrap();
+            assert_eq!(metadata.ino(), object_ino);
+        }
+        // the object plus the two links, and the object wasn't written again
+        let metadata = fs::metadata(&object).unwrap();
+        assert_eq!(metadata.nlink(), 3);
+        assert_eq!(metadata.ino(), object_ino);
+        assert_eq!(fs::read_dir(dir.join("cache/objects")).unwrap().count(), 1);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_linked_object_unchanged() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = TempDir::new();
+        let cache = SharedCache::new(dir.join("cache"));
+        let options = ExtractOptions {
+            shared_cache: Some(cache),
+            file_mode: Some(0o444),
+            ..Default::default()
+        };
+        let zip = zip_file(&[("a.txt", b"hello")]);
+        extract(&zip, &dir.join("first"), &options, None, "").unwrap();
+        extract(&zip, &dir.join("second"), &options, None, "").unwrap();
+        let object = fs::read_dir(dir.join("cache/objects"))
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .path();
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+
+        // replacing the output, which makes it writable first
+        crate::freeze::thaw(dir.join("first")).await.unwrap();
+        assert_eq!(mode(&object), 0o444);
+        // overwriting a file of the output without the cache
+        let options = ExtractOptions {
+            on_conflict: OnConflict::Overwrite,
+            ..Default::default()
+        };
+        let zip = zip_file(&[("a.txt", b"changed")]);
+        extract(&zip, &dir.join("first"), &options, None, "").unwrap();
+
+        assert_eq!(
+            fs::read_to_string(dir.join("first/a.txt")).unwrap(),
+            "changed"
+        );
+        assert_eq!(fs::read_to_string(&object).unwrap(), "hello");
+        assert_eq!(
+            fs::read_to_string(dir.join("second/a.txt")).unwrap(),
+            "hello"
+        );
+        assert_eq!(mode(&object), 0o444);
+    }
+}
@@ -0,0 +1,153 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use reqwest::Url;
+
+/// Refresh a signed URL if it expires within this time, since the download takes a while
+const EXPIRY_MARGIN: Duration = Duration::from_secs(60);
+
+/// Download URL of an artifact, which the API redirects to
+///
+/// The URL is signed by the storage service, and stops working after some time.
+/// The expiry is read from the query, for S3 (`X-Amz-Date` and `X-Amz-Expires`)
+/// and Azure (`se`) URLs.
+#[derive(Debug, Clone)]
+pub struct SignedUrl {
+    pub url: String,
+    /// `None` if the URL doesn't say when it expires
+    pub expires_at: Option<SystemTime>,
+}
+
+impl SignedUrl {
+    pub fn new(url: String) -> Self {
+        let expires_at = parse_expiry(&url);
+        Self { url, expires_at }
+    }
+
+    /// Check if the URL could expire before the download is done
+    pub fn expires_soon(&self) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| expires_at <= SystemTime::now() + EXPIRY_MARGIN)
+    }
+}
+
+fn parse_expiry(url: &str) -> Option<SystemTime> {
+    let url = Url::parse(url).ok()?;
+    let mut amz_date = None;
+    let mut amz_expires = None;
+    for (name, value) in url.query_pairs() {
+        match name.as_ref() {
+            "X-Amz-Date" => amz_date = parse_amz_date(&value),
+            "X-Amz-Expires" => amz_expires = value.parse().ok().map(Duration::from_secs),
+            "se" => return parse_rfc3339(&value),
+            _ => {}
+        }
+    }
+    Some(amz_date? + amz_expires?)
+}
+
+/// Parse a time like `20240101T000000Z`
+fn parse_amz_date(s: &str) -> Option<SystemTime> {
+    let s = s.strip_suffix('Z')?;
+    let (date, time) = s.split_once('T')?;
+    if date.len() != 8 || time.len() != 6 {
+        return None;
+    }
+    let number = |s: &str| s.parse::<u64>().ok();
+    to_system_time(
+        number(&date[..4])?,
+        number(&date[4..6])?,
+        number(&date[6..])?,
+        number(&time[..2])?,
+        number(&time[2..4])?,
+        number(&time[4..])?,
+    )
+}
+
+/// Parse a UTC time like `2024-01-01T00:00:00Z`, the format of the API timestamps
+pub fn parse_rfc3339(s: &str) -> Option<SystemTime> {
+    let s = s.strip_suffix('Z')?;
+    let (date, time) = s.split_once('T')?;
+    let mut date = date.split('-').map(|part| part.parse::<u64>().ok());
+    let mut time = time.split(':').map(|part| part.parse::<u64>().ok());
+    to_system_time(
+        date.next()??,
+        date.next()??,
+        date.next()??,
+        time.next()??,
+        time.next()??,
+        time.next()??,
+    )
+}
+
+fn to_system_time(
+    year: u64,
+    month: u64,
+    day: u64,
+    hour: u64,
+    minute: u64,
+    second: u64,
+) -> Option<SystemTime> {
+    if !(1970..10000).contains(&year)
+        || !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 60
+    {
+        return None;
+    }
+    // days since the epoch, from http://howardhinnant.github.io/date_algorithms.html
+    let (year, month) = if month <= 2 {
+        (year - 1, month + 9)
+    } else {
+        (year, month - 3)
+    };
+    let era = year / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * month + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+    let seconds = days * 86400 + hour * 3600 + minute * 60 + second;
+    Some(UNIX_EPOCH + Duration::from_secs(seconds))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unix_time(url: &str) -> Option<u64> {
+        let expires_at = SignedUrl::new(url.to_string()).expires_at?;
+        Some(expires_at.duration_since(UNIX_EPOCH).unwrap().as_secs())
+    }
+
+    #[test]
+    fn test_parse_expiry() {
+        assert_eq!(
+            unix_time("https://s3.example.com/a.zip?X-Amz-Date=20240102T030405Z&X-Amz-Expires=60&X-Amz-Signature=x"),
+            Some(1704164645 + 60)
+        );
+        assert_eq!(
+            unix_time("https://blob.example.com/a.zip?sv=1&se=2024-01-02T03%3A04%3A05Z&sig=x"),
+            Some(1704164645)
+        );
+        assert_eq!(
+            unix_time("https://example.com/a.zip?X-Amz-Expires=60"),
+            None
+        );
+        assert_eq!(unix_time("https://example.com/a.zip"), None);
+        assert_eq!(unix_time("https://example.com/a.zip?se=tomorrow"), None);
+    }
+
+    #[test]
+    fn test_expires_soon() {
+        assert!(
+            SignedUrl::new("https://example.com/?se=2020-01-01T00:00:00Z".to_string())
+                .expires_soon()
+        );
+        assert!(
+            !SignedUrl::new("https://example.com/?se=2999-01-01T00:00:00Z".to_string())
+                .expires_soon()
+        );
+        assert!(!SignedUrl::new("https://example.com/".to_string()).expires_soon());
+    }
+}
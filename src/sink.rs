@@ -0,0 +1,307 @@
+//! Where extracted files are written, so outputs other than the local filesystem can be added
+
+use std::{
+    fmt,
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+/// Destination for the files and directories of extracted artifacts
+///
+/// Called from blocking threads while extracting. Everything extraction does to the
+/// output goes through it, including symlinks, --shared-cache links and checks for
+/// existing files. Archives and files in the --shared-cache itself are on the local filesystem
+pub trait OutputSink: fmt::Debug + Send + Sync {
+    /// Create the directory and its parents if they don't exist
+    fn create_dir_all(&self, path: &Path) -> io::Result<()>;
+
+    /// Write the file. If `overwrite` is false, fail with `AlreadyExists` if it exists.
+    /// A file or symlink at the path is replaced, not written through
+    fn write_file(&self, path: &Path, content: &[u8], overwrite: bool) -> io::Result<()>;
+
+    /// Set the Unix permissions of the file or directory
+    fn set_mode(&self, path: &Path, mode: u32) -> io::Result<()>;
+
+    /// Check if there is a file, directory or symlink at the path, without following symlinks
+    fn exists(&self, path: &Path) -> bool;
+
+    /// Check if the path is a symlink, without following it
+    fn is_symlink(&self, path: &Path) -> bool;
+
+    /// Check if the path is a directory, following symlinks
+    fn is_dir(&self, path: &Path) -> bool;
+
+    /// Read the file, following symlinks
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>>;
+
+    /// Get the paths of the entries in the directory
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>>;
+
+    /// Remove the file or symlink, or the directory and everything in it
+    fn remove(&self, path: &Path) -> io::Result<()>;
+
+    /// Create a symlink at `path` pointing to `target`, which is relative to the
+    /// directory of `path`. `is_dir` is if the target is a directory
+    fn symlink(&self, target: &Path, path: &Path, is_dir: bool) -> io::Result<()>;
+
+    /// Create a hard link at `path` to `original` on the local filesystem, for --shared-cache
+    ///
+    /// Sinks that can't link to local files fail with `Unsupported`, and the content
+    /// is written instead
+    fn hard_link(&self, _original: &Path, _path: &Path) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+/// Write to the local filesystem
+#[derive(Debug, Default)]
+pub struct FsSink;
+
+impl OutputSink for FsSink {
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        fs::create_dir_all(path)
+    }
+
+    fn write_file(&self, path: &Path, content: &[u8], overwrite: bool) -> io::Result<()> {
+        // create_new fails if the file exists, even when another artifact
+        // is being extracted to the same place at the same time
+        let mut file = if overwrite {
+            // writing to the existing file would also change what it's linked to, like
+            // a file of another artifact for symlinks, or the --shared-cache for hard links
+            match fs::remove_file(path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+            File::create(path)?
+        } else {
+            File::create_new(path)?
+        };
+        file.write_all(content)
+    }
+
+    #[cfg(unix)]
+    fn set_mode(&self, path: &Path, mode: u32) -> io::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))
+    }
+
+    #[cfg(not(unix))]
+    fn set_mode(&self, _path: &Path, _mode: u32) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        fs::symlink_metadata(path).is_ok()
+    }
+
+    fn is_symlink(&self, path: &Path) -> bool {
+        path.is_symlink()
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        path.is_dir()
+    }
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        fs::read(path)
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        fs::read_dir(path)?.map(|entry| Ok(entry?.path())).collect()
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        if fs::symlink_metadata(path)?.is_dir() {
+            fs::remove_dir_all(path)
+        } else {
+            fs::remove_file(path)
+        }
+    }
+
+    #[cfg(unix)]
+    fn symlink(&self, target: &Path, path: &Path, _is_dir: bool) -> io::Result<()> {
+        std::os::unix::fs::symlink(target, path)
+    }
+
+    #[cfg(windows)]
+    fn symlink(&self, target: &Path, path: &Path, is_dir: bool) -> io::Result<()> {
+        if is_dir {
+            std::os::windows::fs::symlink_dir(target, path)
+        } else {
+            std::os::windows::fs::symlink_file(target, path)
+        }
+    }
+
+    fn hard_link(&self, original: &Path, path: &Path) -> io::Result<()> {
+        fs::hard_link(original, path)
+    }
+}
+
+/// Output kept in memory, for testing extraction without touching the filesystem
+#[cfg(test)]
+pub mod memory {
+    use std::{
+        collections::BTreeMap,
+        io,
+        path::{Component, Path, PathBuf},
+        sync::{Mutex, MutexGuard},
+    };
+
+    use super::OutputSink;
+
+    #[derive(Debug, Default)]
+    pub struct MemorySink {
+        entries: Mutex<BTreeMap<PathBuf, MemoryEntry>>,
+        /// Fail to create symlinks, like on Windows without the privilege
+        no_symlinks: bool,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum MemoryEntry {
+        Dir,
+        File { content: Vec<u8>, mode: Option<u32> },
+        Symlink(PathBuf),
+    }
+
+    impl MemorySink {
+        pub fn without_symlinks() -> Self {
+            Self {
+                no_symlinks: true,
+                ..Default::default()
+            }
+        }
+
+        /// Get everything written so far, by path
+        pub fn entries(&self) -> BTreeMap<PathBuf, MemoryEntry> {
+            self.lock().clone()
+        }
+
+        fn lock(&self) -> MutexGuard<'_, BTreeMap<PathBuf, MemoryEntry>> {
+            self.entries.lock().unwrap_or_else(|e| e.into_inner())
+        }
+
+        /// Follow symlinks to the path of the entry they point to
+        fn resolve(&self, path: &Path) -> PathBuf {
+            let entries = self.lock();
+            let mut path = path.to_path_buf();
+            // a limit, like the filesystem has, in case of a cycle
+            for _ in 0..40 {
+                let Some(MemoryEntry::Symlink(target)) = entries.get(&path) else {
+                    break;
+                };
+                let mut resolved = path.parent().unwrap_or(Path::new("")).to_path_buf();
+                for component in target.components() {
+                    match component {
+                        Component::ParentDir => {
+                            resolved.pop();
+                        }
+                        Component::CurDir => {}
+                        component => resolved.push(component),
+                    }
+                }
+                path = resolved;
+            }
+            path
+        }
+    }
+
+    impl OutputSink for MemorySink {
+        fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+            let mut entries = self.lock();
+            for dir in path.ancestors().filter(|dir| !dir.as_os_str().is_empty()) {
+                match entries.get(dir) {
+                    None => {
+                        entries.insert(dir.to_path_buf(), MemoryEntry::Dir);
+                    }
+                    Some(MemoryEntry::Dir) => {}
+                    Some(_) => return Err(io::ErrorKind::AlreadyExists.into()),
+                }
+            }
+            Ok(())
+        }
+
+        fn write_file(&self, path: &Path, content: &[u8], overwrite: bool) -> io::Result<()> {
+            let mut entries = self.lock();
+            match entries.get(path) {
+                Some(MemoryEntry::File { .. } | MemoryEntry::Symlink(_)) if overwrite => {}
+                Some(_) => return Err(io::ErrorKind::AlreadyExists.into()),
+                None => {}
+            }
+            let file = MemoryEntry::File {
+                content: content.to_vec(),
+                mode: None,
+            };
+            entries.insert(path.to_path_buf(), file);
+            Ok(())
+        }
+
+        fn set_mode(&self, path: &Path, new_mode: u32) -> io::Result<()> {
+            let path = self.resolve(path);
+            match self.lock().get_mut(&path) {
+                Some(MemoryEntry::File { mode, .. }) => {
+                    *mode = Some(new_mode);
+                    Ok(())
+                }
+                Some(_) => Ok(()),
+                None => Err(io::ErrorKind::NotFound.into()),
+            }
+        }
+
+        fn exists(&self, path: &Path) -> bool {
+            self.lock().contains_key(path)
+        }
+
+        fn is_symlink(&self, path: &Path) -> bool {
+            matches!(self.lock().get(path), Some(MemoryEntry::Symlink(_)))
+        }
+
+        fn is_dir(&self, path: &Path) -> bool {
+            let path = self.resolve(path);
+            matches!(self.lock().get(&path), Some(MemoryEntry::Dir))
+        }
+
+        fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+            let path = self.resolve(path);
+            match self.lock().get(&path) {
+                Some(MemoryEntry::File { content, .. }) => Ok(content.clone()),
+                _ => Err(io::ErrorKind::NotFound.into()),
+            }
+        }
+
+        fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+            let path = self.resolve(path);
+            let entries = self.lock();
+            let children = entries
+                .keys()
+                .filter(|entry| entry.parent() == Some(&path))
+                .cloned()
+                .collect();
+            Ok(children)
+        }
+
+        fn remove(&self, path: &Path) -> io::Result<()> {
+            let mut entries = self.lock();
+            if entries.remove(path).is_none() {
+                return Err(io::ErrorKind::NotFound.into());
+            }
+            entries.retain(|entry, _| !entry.starts_with(path));
+            Ok(())
+        }
+
+        fn symlink(&self, target: &Path, path: &Path, _is_dir: bool) -> io::Result<()> {
+            if self.no_symlinks {
+                return Err(io::ErrorKind::PermissionDenied.into());
+            }
+            let mut entries = self.lock();
+            if entries.contains_key(path) {
+                return Err(io::ErrorKind::AlreadyExists.into());
+            }
+            entries.insert(
+                path.to_path_buf(),
+                MemoryEntry::Symlink(target.to_path_buf()),
+            );
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use crate::output::info;
+
+/// Size of the pulled artifacts, reported with --output-size-report
+#[derive(Debug, Default)]
+pub struct SizeReport {
+    pub artifacts: Vec<ArtifactSize>,
+}
+
+#[derive(Debug)]
+pub struct ArtifactSize {
+    pub name: String,
+    /// Size of the downloaded archive
+    pub downloaded: u64,
+    /// Total size of the extracted files
+    pub extracted: u64,
+}
+
+impl SizeReport {
+    pub fn print(&mut self) {
+        self.artifacts.sort_by(|a, b| a.name.cmp(&b.name));
+        info!("---");
+        info!(
+            "{:<40} {:>12} {:>12}",
+            "artifact", "downloaded", "extracted"
+        );
+        for artifact in &self.artifacts {
+            Self::print_row(
+                &format!("`{}`", artifact.name),
+                artifact.downloaded,
+                artifact.extracted,
+            );
+        }
+        let downloaded = self.artifacts.iter().map(|a| a.downloaded).sum();
+        let extracted = self.artifacts.iter().map(|a| a.extracted).sum();
+        Self::print_row("total", downloaded, extracted);
+    }
+
+    /// Markdown table of the pulled artifacts, for --step-summary.
+    /// `skipped` are the names of artifacts not downloaded, and why
+    pub fn markdown(&mut self, repo: &str, rev: &str, skipped: &[(String, &str)]) -> String {
+        self.artifacts.sort_by(|a, b| a.name.cmp(&b.name));
+        let escape = |name: &str| name.replace('|', "\\|");
+        let mut markdown = format!("### magnesis\n\nPulled `{}` at `{}`\n\n", repo, rev);
+        markdown.push_str("| Artifact | Status | Downloaded | Extracted |\n");
+        markdown.push_str("| --- | --- | ---: | ---: |\n");
+        for artifact in &self.artifacts {
+            markdown.push_str(&format!(
+                "| {} | downloaded | {} | {} |\n",
+                escape(&artifact.name),
+                format_size(artifact.downloaded),
+                format_size(artifact.extracted)
+            ));
+        }
+        for (name, status) in skipped {
+            markdown.push_str(&format!("| {} | {} | | |\n", escape(name), status));
+        }
+        let downloaded = self.artifacts.iter().map(|a| a.downloaded).sum();
+        let extracted = self.artifacts.iter().map(|a| a.extracted).sum();
+        markdown.push_str(&format!(
+            "| **total** | | {} | {} |\n",
+            format_size(downloaded),
+            format_size(extracted)
+        ));
+        markdown.push('\n');
+        markdown
+    }
+
+    fn print_row(name: &str, downloaded: u64, extracted: u64) {
+        info!(
+            "{:<40} {:>12} {:>12}",
+            name,
+            format_size(downloaded),
+            format_size(extracted)
+        );
+    }
+}
+
+/// Format the number of bytes with a binary unit, like `1.50 MiB`
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.2} {}", size, UNITS[unit])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(0), "0 B");
+        assert_eq!(format_size(1023), "1023 B");
+        assert_eq!(format_size(1536), "1.50 KiB");
+        assert_eq!(format_size(5 * 1024 * 1024), "5.00 MiB");
+        assert_eq!(format_size(3 << 40), "3.00 TiB");
+        assert_eq!(format_size(2048 << 40), "2048.00 TiB");
+    }
+
+    #[test]
+    fn test_markdown() {
+        let mut sizes = SizeReport {
+            artifacts: vec![
+                ArtifactSize {
+                    name: "docs".to_string(),
+                    downloaded: 1024,
+                    extracted: 4096,
+                },
+                ArtifactSize {
+                    name: "app|linux".to_string(),
+                    downloaded: 100,
+                    extracted: 200,
+                },
+            ],
+        };
+        let skipped = [("old".to_string(), "deleted")];
+        let markdown = sizes.markdown("foo/bar", "abc", &skipped);
+        assert_eq!(
+            markdown,
+            "### magnesis\n\
+            \n\
+            Pulled `foo/bar` at `abc`\n\
+            \n\
+            | Artifact | Status | Downloaded | Extracted |\n\
+            | --- | --- | ---: | ---: |\n\
+            | app\\|linux | downloaded | 100 B | 200 B |\n\
+            | docs | downloaded | 1.00 KiB | 4.00 KiB |\n\
+            | old | deleted | | |\n\
+            | **total** | | 1.10 KiB | 4.20 KiB |\n\
+            \n"
+        );
+    }
+}
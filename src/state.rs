@@ -0,0 +1,188 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use error_stack::{report, Result, ResultExt};
+use tokio::fs;
+
+use crate::{artifact::Artifact, atomic_file, Error};
+
+/// State of a pull, saved in the --state-dir so an interrupted pull can be continued
+///
+/// The resolved revision and the artifacts found for it are saved before anything is
+/// downloaded, so continuing doesn't list again. The state is deleted when the pull finishes.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct State {
+    pub repo: String,
+    /// What the artifacts were pulled for, as given on the command line
+    pub selection: Selection,
+    /// The resolved revision
+    pub rev: String,
+    /// All artifacts found for the revision, before --name, --index and other filters
+    pub artifacts: Vec<Artifact>,
+    /// IDs of the artifacts that are downloaded
+    pub complete: BTreeSet<u64>,
+    /// Number of pulls that started downloading each artifact, by ID
+    #[serde(default)]
+    pub attempts: BTreeMap<u64, u32>,
+}
+
+/// Options that decide which revision and artifacts are found
+#[derive(Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Selection {
+    pub rev: String,
+    pub pr: Option<u64>,
+    pub run: Option<u64>,
+    pub check_suite: Option<u64>,
+}
+
+impl State {
+    const FILE_NAME: &'static str = "state.json";
+
+    fn path(dir: &Path) -> PathBuf {
+        dir.join(Self::FILE_NAME)
+    }
+
+    /// Load the state from the directory, if there is one
+    pub async fn load(dir: &Path) -> Result<Option<Self>, Error> {
+        let path = Self::path(dir);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let bytes = fs::read(&path)
+            .await
+            .change_context(Error::State)
+            .attach_printable_lazy(|| format!("path: {}", path.display()))?;
+        let state = serde_json::from_slice(&bytes)
+            .change_context(Error::State)
+            .attach_printable_lazy(|| format!("path: {}", path.display()))?;
+        Ok(Some(state))
+    }
+
+    /// Check the state is from a pull of the same repo, revision and run
+    pub fn check_same_pull(&self, repo: &str, selection: &Selection) -> Result<(), Error> {
+        if self.repo != repo {
+            return Err(report!(Error::State))
+                .attach_printable(format!("the existing state is for `{}`", self.repo))
+                .attach_printable("use another --state-dir, or delete it to start over");
+        }
+        if &self.selection != selection {
+            return Err(report!(Error::State))
+                .attach_printable(format!(
+                    "the existing state is for {}, not {}",
+                    self.selection, selection
+                ))
+                .attach_printable("use another --state-dir, or delete it to start over");
+        }
+        Ok(())
+    }
+
+    /// Count another attempt at downloading the artifact, returning the number so far
+    pub fn add_attempt(&mut self, id: u64) -> u32 {
+        let attempts = self.attempts.entry(id).or_default();
+        *attempts += 1;
+        *attempts
+    }
+
+    /// Save the state to the directory
+    pub async fn save(&self, dir: &Path) -> Result<(), Error> {
+        let path = Self::path(dir);
+        let json = serde_json::to_vec(self).change_context(Error::State)?;
+        let write = async {
+            fs::create_dir_all(dir).await?;
+            atomic_file::write(&path, json).await
+        };
+        write
+            .await
+            .change_context(Error::State)
+            .attach_printable_lazy(|| format!("path: {}", path.display()))?;
+        Ok(())
+    }
+
+    /// Delete the state after the pull is done
+    pub async fn remove(dir: &Path) -> Result<(), Error> {
+        let path = Self::path(dir);
+        fs::remove_file(&path)
+            .await
+            .change_context(Error::State)
+            .attach_printable_lazy(|| format!("path: {}", path.display()))
+    }
+}
+
+impl fmt::Display for Selection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(pr) = self.pr {
+            write!(f, "pull request #{}", pr)
+        } else if let Some(run) = self.run {
+            write!(f, "workflow run `{}`", run)
+        } else if let Some(check_suite) = self.check_suite {
+            write!(f, "check suite `{}`", check_suite)
+        } else {
+            write!(f, "revision `{}`", self.rev)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_util::{temp_files, TempDir};
+
+    fn selection(rev: &str) -> Selection {
+        Selection {
+            rev: rev.to_string(),
+            pr: None,
+            run: None,
+            check_suite: None,
+        }
+    }
+
+    fn state() -> State {
+        State {
+            repo: "foo/bar".to_string(),
+            selection: selection("main"),
+            rev: "abc".to_string(),
+            artifacts: vec![],
+            complete: BTreeSet::from([1, 3]),
+            attempts: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_check_same_pull() {
+        let state = state();
+        assert!(state.check_same_pull("foo/bar", &selection("main")).is_ok());
+        assert!(state
+            .check_same_pull("foo/other", &selection("main"))
+            .is_err());
+        assert!(state
+            .check_same_pull("foo/bar", &selection("v1.0"))
+            .is_err());
+        let mut pr = selection("main");
+        pr.pr = Some(1);
+        assert!(state.check_same_pull("foo/bar", &pr).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_save_load() {
+        let dir = TempDir::new();
+        let state_dir = dir.join("state");
+        assert!(State::load(&state_dir).await.unwrap().is_none());
+
+        let mut state = state();
+        assert_eq!(state.add_attempt(2), 1);
+        state.save(&state_dir).await.unwrap();
+        let mut loaded = State::load(&state_dir).await.unwrap().unwrap();
+        assert_eq!(loaded.rev, "abc");
+        assert_eq!(loaded.selection, state.selection);
+        assert_eq!(loaded.complete, state.complete);
+        assert_eq!(loaded.add_attempt(2), 2);
+        assert!(temp_files(&state_dir).is_empty());
+
+        State::remove(&state_dir).await.unwrap();
+        assert!(State::load(&state_dir).await.unwrap().is_none());
+    }
+}
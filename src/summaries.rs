@@ -0,0 +1,170 @@
+//! Saving the summaries of the jobs in the workflow runs, for --with-summaries
+//!
+//! The API has no endpoint for the Markdown written to `GITHUB_STEP_SUMMARY`,
+//! so the output of the check run of each job is saved instead.
+//! Summaries that can't be fetched are skipped with a warning.
+
+use std::{collections::BTreeSet, path::Path};
+
+use error_stack::{Result, ResultExt};
+use tokio::fs;
+
+use crate::{
+    github::{get_check_run_output, get_run_jobs, Api, CheckRunOutput, Job},
+    output::{progress, warning},
+    Error,
+};
+
+/// Save the summaries of the jobs in the runs into the directory.
+/// Returns the number of summaries saved
+pub async fn save_summaries(
+    api: &Api,
+    repo: &str,
+    run_ids: &BTreeSet<u64>,
+    dir: &Path,
+) -> Result<usize, Error> {
+    let mut count = 0;
+    for &run_id in run_ids {
+        progress!("getting job summaries of run {}", run_id);
+        let jobs = match get_run_jobs(api, repo, run_id).await {
+            Ok(jobs) => jobs,
+            Err(err) => {
+                warning!("could not get the jobs of run {}: {}", run_id, err);
+                continue;
+            }
+        };
+        count += save_run_summaries(api, run_id, &jobs, dir).await?;
+    }
+    Ok(count)
+}
+
+/// Save the summaries of the jobs of one run, as `<run id>-<job name>.md`
+async fn save_run_summaries(
+    api: &Api,
+    run_id: u64,
+    jobs: &[Job],
+    dir: &Path,
+) -> Result<usize, Error> {
+    let mut count = 0;
+    for job in jobs {
+        let Some(url) = &job.check_run_url else {
+            continue;
+        };
+        let output = match get_check_run_output(api, url).await {
+            Ok(output) => output,
+            Err(err) => {
+                warning!("could not get the summary of job `{}`: {}", job.name, err);
+                continue;
+            }
+        };
+        let Some(markdown) = summary_markdown(&output) else {
+            continue;
+        };
+        fs::create_dir_all(dir)
+            .await
+            .change_context(Error::Summaries)
+            .attach_printable_lazy(|| format!("path: {}", dir.display()))?;
+        let path = dir.join(format!("{}-{}.md", run_id, file_name(&job.name)));
+        fs::write(&path, markdown)
+            .await
+            .change_context(Error::Summaries)
+            .attach_printable_lazy(|| format!("path: {}", path.display()))?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Markdown of the output, or `None` if the job didn't write a summary
+fn summary_markdown(output: &CheckRunOutput) -> Option<String> {
+    let parts = [&output.summary, &output.text]
+        .into_iter()
+        .flatten()
+        .filter(|part| !part.trim().is_empty())
+        .collect::<Vec<_>>();
+    if parts.is_empty() {
+        return None;
+    }
+    let mut markdown = String::new();
+    if let Some(title) = output.title.as_deref().filter(|title| !title.is_empty()) {
+        markdown.push_str(&format!("# {}\n\n", title));
+    }
+    for part in parts {
+        markdown.push_str(part.trim_end());
+        markdown.push('\n');
+    }
+    Some(markdown)
+}
+
+/// Job names can have any character, like `build (ubuntu, 1.80)`
+fn file_name(job_name: &str) -> String {
+    job_name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        github::ApiOptions,
+        test_util::{MockServer, Response, TempDir},
+    };
+
+    #[test]
+    fn test_file_name() {
+        assert_eq!(file_name("build (ubuntu, 1.80)"), "build__ubuntu__1.80_");
+        assert_eq!(file_name("../test"), ".._test");
+    }
+
+    #[tokio::test]
+    async fn test_save_run_summaries() {
+        let server = MockServer::start(|request| match request.path.as_str() {
+            "/check-runs/1" => Response::json(
+                r#"{"output":{"title":"Build","summary":"**passed**","text":"details"}}"#,
+            ),
+            "/check-runs/2" => {
+                Response::json(r#"{"output":{"title":null,"summary":null,"text":""}}"#)
+            }
+            _ => Response::new(404),
+        })
+        .await;
+        let job = |name: &str, check_run_url: Option<String>| Job {
+            name: name.to_string(),
+            started_at: None,
+            completed_at: None,
+            check_run_url,
+        };
+        let jobs = [
+            job("build (linux)", Some(server.url("/check-runs/1"))),
+            // no summary
+            job("lint", Some(server.url("/check-runs/2"))),
+            // not available
+            job("test", Some(server.url("/check-runs/3"))),
+            job("queued", None),
+        ];
+        let api = Api::new("token".to_string(), ApiOptions::default()).unwrap();
+        let dir = TempDir::new();
+        let out = dir.join("summaries");
+
+        let count = save_run_summaries(&api, 7, &jobs, &out).await.unwrap();
+        assert_eq!(count, 1);
+        assert_eq!(
+            std::fs::read_to_string(out.join("7-build__linux_.md")).unwrap(),
+            "# Build\n\n**passed**\ndetails\n"
+        );
+        assert_eq!(std::fs::read_dir(&out).unwrap().count(), 1);
+        let requests = server.requests();
+        assert_eq!(requests.len(), 3);
+        assert!(requests
+            .iter()
+            .all(|request| request.header("authorization") == Some("Bearer token")));
+    }
+}
@@ -0,0 +1,378 @@
+//! Helpers shared by the tests
+
+use std::{
+    fs,
+    io::{Cursor, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    net::TcpListener,
+    task::JoinHandle,
+};
+use tokio_native_tls::TlsAcceptor;
+
+use crate::{
+    artifact::Artifact,
+    github::{Api, ApiOptions},
+};
+
+/// Directory under the system temp directory, removed when dropped
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new() -> Self {
+        // tests in the same process run in parallel
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "magnesis-test-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+
+    pub fn join(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.0.join(path)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Get the temporary files left in the directory by a write that didn't finish
+pub fn temp_files(dir: &Path) -> Vec<PathBuf> {
+    fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "tmp"))
+        .collect()
+}
+
+/// Create a zip archive with the files
+pub fn zip_file(files: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+    for (name, content) in files {
+        writer
+            .start_file(*name, zip::write::SimpleFileOptions::default())
+            .unwrap();
+        writer.write_all(content).unwrap();
+    }
+    writer.finish().unwrap().into_inner()
+}
+
+/// Artifacts with the names, with ids from 0 and nothing else set
+pub fn artifacts(names: &[&str]) -> Vec<Artifact> {
+    names
+        .iter()
+        .enumerate()
+        .map(|(id, name)| {
+            serde_json::from_value(serde_json::json!({
+                "id": id,
+                "name": name,
+                "archive_download_url": "",
+                "workflow_run": { "head_sha": "abc" },
+            }))
+            .unwrap()
+        })
+        .collect()
+}
+
+/// Self-signed certificate for `localhost` and `127.0.0.1`, in PEM format
+pub struct SelfSignedCert {
+    pub pem: Vec<u8>,
+    pub key_pem: Vec<u8>,
+}
+
+pub fn self_signed_cert() -> SelfSignedCert {
+    use openssl::{
+        asn1::Asn1Time,
+        bn::BigNum,
+        hash::MessageDigest,
+        pkey::PKey,
+        rsa::Rsa,
+        x509::{extension::SubjectAlternativeName, X509Builder, X509NameBuilder},
+    };
+
+    let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+    let mut name = X509NameBuilder::new().unwrap();
+    name.append_entry_by_text("CN", "localhost").unwrap();
+    let name = name.build();
+    let mut builder = X509Builder::new().unwrap();
+    builder.set_version(2).unwrap();
+    builder
+        .set_serial_number(&BigNum::from_u32(1).unwrap().to_asn1_integer().unwrap())
+        .unwrap();
+    builder.set_subject_name(&name).unwrap();
+    builder.set_issuer_name(&name).unwrap();
+    builder.set_pubkey(&key).unwrap();
+    builder
+        .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+        .unwrap();
+    builder
+        .set_not_after(&Asn1Time::days_from_now(1).unwrap())
+        .unwrap();
+    let san = SubjectAlternativeName::new()
+        .dns("localhost")
+        .ip("127.0.0.1")
+        .build(&builder.x509v3_context(None, None))
+        .unwrap();
+    builder.append_extension(san).unwrap();
+    builder.sign(&key, MessageDigest::sha256()).unwrap();
+    SelfSignedCert {
+        pem: builder.build().to_pem().unwrap(),
+        key_pem: key.private_key_to_pem_pkcs8().unwrap(),
+    }
+}
+
+/// HTTP server on localhost that answers every request with the handler
+///
+/// The requests it got are kept, so tests can check what was sent
+pub struct MockServer {
+    base_url: String,
+    requests: Arc<Mutex<Vec<Request>>>,
+    task: JoinHandle<()>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub method: String,
+    /// Path and query
+    pub path: String,
+    /// Headers, with lowercase names
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Response {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Response {
+    pub fn new(status: u16) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    pub fn json(body: impl Into<String>) -> Self {
+        Self::new(200)
+            .header("Content-Type", "application/json")
+            .body(body.into())
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = body.into();
+        self
+    }
+}
+
+impl MockServer {
+    pub async fn start(handler: impl Fn(&Request) -> Response + Send + Sync + 'static) -> Self {
+        Self::start_internal(None, handler).await
+    }
+
+    /// Start the server with HTTPS, using the certificate from [`self_signed_cert`]
+    pub async fn start_tls(
+        cert: &SelfSignedCert,
+        handler: impl Fn(&Request) -> Response + Send + Sync + 'static,
+    ) -> Self {
+        let identity =
+            tokio_native_tls::native_tls::Identity::from_pkcs8(&cert.pem, &cert.key_pem).unwrap();
+        let acceptor = tokio_native_tls::native_tls::TlsAcceptor::new(identity).unwrap();
+        Self::start_internal(Some(acceptor.into()), handler).await
+    }
+
+    async fn start_internal(
+        tls: Option<TlsAcceptor>,
+        handler: impl Fn(&Request) -> Response + Send + Sync + 'static,
+    ) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let handler = Arc::new(handler);
+        let base_url = match tls {
+            Some(_) => format!("https://localhost:{}", address.port()),
+            None => format!("http://{}", address),
+        };
+        let tls = tls.map(Arc::new);
+        let task = {
+            let requests = Arc::clone(&requests);
+            tokio::spawn(async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    let requests = Arc::clone(&requests);
+                    let handler = Arc::clone(&handler);
+                    let tls = tls.clone();
+                    tokio::spawn(async move {
+                        let respond = |request: &Request| {
+                            let response = handler(request);
+                            requests.lock().unwrap().push(request.clone());
+                            response
+                        };
+                        match tls {
+                            Some(tls) => {
+                                // clients that don't trust the certificate fail the handshake
+                                let Ok(stream) = tls.accept(stream).await else {
+                                    return;
+                                };
+                                serve(stream, respond).await;
+                            }
+                            None => serve(stream, respond).await,
+                        }
+                    });
+                }
+            })
+        };
+        Self {
+            base_url,
+            requests,
+            task,
+        }
+    }
+
+    /// Get the URL of the path on the server
+    pub fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url, path)
+    }
+
+    pub fn requests(&self) -> Vec<Request> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+/// Client for the API mocked by the server
+pub fn mock_api(server: &MockServer) -> Arc<Api> {
+    let options = ApiOptions {
+        api_url: Some(server.url("")),
+        ..Default::default()
+    };
+    Arc::new(Api::new("token".to_string(), options).unwrap())
+}
+
+/// Counts the requests a handler is answering at the same time, to check they are concurrent
+///
+/// Tests using it need the multi-threaded runtime, since the handler blocks
+#[derive(Debug, Default)]
+pub struct InFlight {
+    current: AtomicUsize,
+    max: AtomicUsize,
+}
+
+impl InFlight {
+    /// Answer the request slowly, so requests sent at the same time overlap
+    pub fn answer(&self, respond: impl FnOnce() -> Response) -> Response {
+        let current = self.current.fetch_add(1, Ordering::SeqCst) + 1;
+        self.max.fetch_max(current, Ordering::SeqCst);
+        std::thread::sleep(Duration::from_millis(200));
+        let response = respond();
+        self.current.fetch_sub(1, Ordering::SeqCst);
+        response
+    }
+
+    /// Most requests answered at the same time
+    pub fn max(&self) -> usize {
+        self.max.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn serve<S>(stream: S, respond: impl FnOnce(&Request) -> Response)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let Some((request, mut stream)) = read_request(stream).await else {
+        return;
+    };
+    let response = respond(&request);
+    let _ = write_response(&mut stream, &response).await;
+}
+
+async fn read_request<S>(stream: S) -> Option<(Request, S)>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line).await.ok()?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next()?.to_string();
+    let path = parts.next()?.to_string();
+    let mut headers = Vec::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).await.ok()?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        let (name, value) = line.split_once(':')?;
+        headers.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+    }
+    let length = headers
+        .iter()
+        .find(|(name, _)| name == "content-length")
+        .and_then(|(_, value)| value.parse().ok())
+        .unwrap_or(0);
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).await.ok()?;
+    let request = Request {
+        method,
+        path,
+        headers,
+        body,
+    };
+    Some((request, reader.into_inner()))
+}
+
+async fn write_response(
+    stream: &mut (impl AsyncWrite + Unpin),
+    response: &Response,
+) -> std::io::Result<()> {
+    let mut head = format!(
+        "HTTP/1.1 {} Mock\r\nContent-Length: {}\r\nConnection: close\r\n",
+        response.status,
+        response.body.len()
+    );
+    for (name, value) in &response.headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(&response.body).await?;
+    stream.shutdown().await
+}
@@ -0,0 +1,68 @@
+use std::{
+    future::Future,
+    time::{Duration, Instant},
+};
+
+use crate::output::info;
+
+/// Time spent in each phase, reported with --trace-timings
+#[derive(Debug, Default)]
+pub struct Timings {
+    pub repo: Duration,
+    pub rev: Duration,
+    pub list: Duration,
+    pub filter: Duration,
+    pub artifacts: Vec<ArtifactTiming>,
+}
+
+#[derive(Debug)]
+pub struct ArtifactTiming {
+    pub name: String,
+    pub download: Duration,
+    pub extract: Duration,
+}
+
+impl Timings {
+    pub fn print(&self) {
+        info!("---");
+        info!("{:<40} {:>10}", "phase", "time");
+        Self::print_row("resolve repo", self.repo);
+        Self::print_row("resolve rev", self.rev);
+        Self::print_row("list artifacts", self.list);
+        Self::print_row("filter artifacts", self.filter);
+        for artifact in &self.artifacts {
+            Self::print_row(&format!("download `{}`", artifact.name), artifact.download);
+            Self::print_row(&format!("extract `{}`", artifact.name), artifact.extract);
+        }
+    }
+
+    fn print_row(phase: &str, duration: Duration) {
+        info!("{:<40} {:>9.02}s", phase, duration.as_secs_f64());
+    }
+}
+
+/// Run the future and measure how long it took
+pub async fn timed<F: Future>(future: F) -> (F::Output, Duration) {
+    let start = Instant::now();
+    let output = future.await;
+    (output, start.elapsed())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_timed() {
+        let (output, elapsed) = timed(async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            42
+        })
+        .await;
+        assert_eq!(output, 42);
+        assert!(elapsed >= Duration::from_millis(10));
+
+        let (_, elapsed) = timed(async {}).await;
+        assert!(elapsed < Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,164 @@
+use std::str::FromStr;
+
+/// A GitHub web URL, like one copied from the browser
+///
+/// Supports repo, workflow run (`/actions/runs/ID`) and pull request (`/pull/NUMBER`) URLs.
+/// Anything else after the repo is ignored
+#[derive(Debug, Clone)]
+pub struct GitHubUrl {
+    pub repo: String,
+    pub run: Option<u64>,
+    pub pr: Option<u64>,
+}
+
+impl FromStr for GitHubUrl {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let invalid = || format!("`{}` is not a GitHub URL", s);
+        let rest = s
+            .strip_prefix("https://")
+            .or_else(|| s.strip_prefix("http://"))
+            .ok_or_else(invalid)?;
+        let rest = rest.strip_prefix("www.").unwrap_or(rest);
+        let path = rest.strip_prefix("github.com/").ok_or_else(invalid)?;
+        let path = path.split(['?', '#']).next().unwrap_or_default();
+
+        let mut segments = path.split('/').filter(|segment| !segment.is_empty());
+        let (Some(owner), Some(repo)) = (segments.next(), segments.next()) else {
+            return Err(format!("`{}` does not contain the owner and repo", s));
+        };
+        let repo = repo.strip_suffix(".git").unwrap_or(repo);
+        let mut url = Self {
+            repo: format!("{}/{}", owner, repo),
+            run: None,
+            pr: None,
+        };
+
+        let parse_number = |number: Option<&str>| {
+            number
+                .and_then(|number| number.parse().ok())
+                .ok_or_else(|| format!("invalid number in `{}`", s))
+        };
+        match (segments.next(), segments.next()) {
+            (Some("actions"), Some("runs")) => url.run = Some(parse_number(segments.next())?),
+            (Some("pull"), number) => url.pr = Some(parse_number(number)?),
+            _ => {}
+        }
+
+        Ok(url)
+    }
+}
+
+/// Parse the value of --repo, which is either `OWNER/REPO` or a GitHub URL
+pub fn parse_repo(s: &str) -> std::result::Result<String, String> {
+    if s.starts_with("https://") || s.starts_with("http://") {
+        return Ok(s.parse::<GitHubUrl>()?.repo);
+    }
+    Ok(s.to_string())
+}
+
+/// Prefix of download URLs to replace, for --download-rewrite
+#[derive(Debug, Clone)]
+pub struct UrlRewrite {
+    pub from: String,
+    pub to: String,
+}
+
+impl UrlRewrite {
+    /// Replace the prefix of the URL, if it has it
+    pub fn apply(&self, url: &str) -> Option<String> {
+        let rest = url.strip_prefix(&self.from)?;
+        Some(format!("{}{}", self.to, rest))
+    }
+}
+
+impl FromStr for UrlRewrite {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((from, to)) if !from.is_empty() => Ok(Self {
+                from: from.to_string(),
+                to: to.to_string(),
+            }),
+            _ => Err(format!("expected `FROM=TO`, got `{}`", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_github_url() {
+        let url: GitHubUrl = "https://github.com/Pistonite/magnesis".parse().unwrap();
+        assert_eq!(url.repo, "Pistonite/magnesis");
+        assert_eq!((url.run, url.pr), (None, None));
+
+        let url: GitHubUrl = "https://www.github.com/Pistonite/magnesis.git/"
+            .parse()
+            .unwrap();
+        assert_eq!(url.repo, "Pistonite/magnesis");
+
+        let url: GitHubUrl = "https://github.com/Pistonite/magnesis/actions/runs/123/job/4?pr=1"
+            .parse()
+            .unwrap();
+        assert_eq!((url.run, url.pr), (Some(123), None));
+
+        let url: GitHubUrl = "http://github.com/Pistonite/magnesis/pull/45/files#diff"
+            .parse()
+            .unwrap();
+        assert_eq!((url.run, url.pr), (None, Some(45)));
+
+        let url: GitHubUrl = "https://github.com/Pistonite/magnesis/tree/main"
+            .parse()
+            .unwrap();
+        assert_eq!((url.run, url.pr), (None, None));
+    }
+
+    #[test]
+    fn test_github_url_invalid() {
+        assert!("github.com/Pistonite/magnesis"
+            .parse::<GitHubUrl>()
+            .is_err());
+        assert!("https://gitlab.com/Pistonite/magnesis"
+            .parse::<GitHubUrl>()
+            .is_err());
+        assert!("https://github.com/Pistonite".parse::<GitHubUrl>().is_err());
+        assert!("https://github.com/Pistonite/magnesis/pull/abc"
+            .parse::<GitHubUrl>()
+            .is_err());
+        assert!("https://github.com/Pistonite/magnesis/actions/runs"
+            .parse::<GitHubUrl>()
+            .is_err());
+    }
+
+    #[test]
+    fn test_parse_repo() {
+        assert_eq!(
+            parse_repo("Pistonite/magnesis"),
+            Ok("Pistonite/magnesis".to_string())
+        );
+        assert_eq!(
+            parse_repo("https://github.com/Pistonite/magnesis/pull/1"),
+            Ok("Pistonite/magnesis".to_string())
+        );
+        assert!(parse_repo("https://example.com/a/b").is_err());
+    }
+
+    #[test]
+    fn test_url_rewrite() {
+        let rewrite: UrlRewrite = "https://api.github.com=http://localhost:8080"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            rewrite.apply("https://api.github.com/repos/a/b/actions/artifacts/1/zip"),
+            Some("http://localhost:8080/repos/a/b/actions/artifacts/1/zip".to_string())
+        );
+        assert_eq!(rewrite.apply("https://example.com/a.zip"), None);
+        assert!("no-separator".parse::<UrlRewrite>().is_err());
+        assert!("=http://localhost".parse::<UrlRewrite>().is_err());
+    }
+}
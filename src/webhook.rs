@@ -0,0 +1,127 @@
+//! Posting the status of the pull to a URL, for --progress-webhook
+
+use std::time::Duration;
+
+use reqwest::{header, Client};
+
+use crate::output::warning;
+
+/// How long to wait for the webhook, so a slow endpoint doesn't hold up the pull
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct Webhook {
+    client: Client,
+    url: String,
+}
+
+/// Status posted to the webhook as JSON
+#[derive(Debug, serde::Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum WebhookEvent<'a> {
+    /// Before downloading
+    Start {
+        repo: &'a str,
+        rev: &'a str,
+        total: usize,
+    },
+    /// After each artifact is downloaded
+    Artifact {
+        id: u64,
+        name: &'a str,
+        completed: usize,
+        total: usize,
+    },
+    /// After everything is downloaded
+    End { downloaded: usize },
+}
+
+impl Webhook {
+    pub fn new(url: String) -> Self {
+        // not the API client, so the token isn't sent to the webhook
+        let client = Client::builder()
+            .timeout(TIMEOUT)
+            .build()
+            .unwrap_or_default();
+        Self { client, url }
+    }
+
+    /// Post the event, printing a warning if it fails
+    pub async fn post(&self, event: &WebhookEvent<'_>) {
+        let body = serde_json::to_vec(event).unwrap_or_default();
+        let result = self
+            .client
+            .post(&self.url)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(body)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+        if let Err(e) = result {
+            warning!("failed to post progress to webhook: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_util::{MockServer, Response};
+
+    #[tokio::test]
+    async fn test_post() {
+        let server = MockServer::start(|request| match request.path.as_str() {
+            "/hook" => Response::new(204),
+            _ => Response::new(500),
+        })
+        .await;
+        let webhook = Webhook::new(server.url("/hook"));
+        webhook
+            .post(&WebhookEvent::Start {
+                repo: "foo/bar",
+                rev: "abc",
+                total: 2,
+            })
+            .await;
+        for (completed, name) in ["app", "docs"].into_iter().enumerate() {
+            webhook
+                .post(&WebhookEvent::Artifact {
+                    id: completed as u64,
+                    name,
+                    completed: completed + 1,
+                    total: 2,
+                })
+                .await;
+        }
+        webhook.post(&WebhookEvent::End { downloaded: 2 }).await;
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 4);
+        let events = requests
+            .iter()
+            .map(|request| {
+                assert_eq!(request.method, "POST");
+                assert_eq!(request.header("content-type"), Some("application/json"));
+                assert_eq!(request.header("authorization"), None);
+                serde_json::from_slice::<serde_json::Value>(&request.body).unwrap()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            events[0],
+            serde_json::json!({ "event": "start", "repo": "foo/bar", "rev": "abc", "total": 2 })
+        );
+        assert_eq!(events[2]["event"], "artifact");
+        assert_eq!(events[2]["name"], "docs");
+        assert_eq!(events[2]["completed"], 2);
+        assert_eq!(
+            events[3],
+            serde_json::json!({ "event": "end", "downloaded": 2 })
+        );
+
+        // a failing webhook only warns
+        Webhook::new(server.url("/broken"))
+            .post(&WebhookEvent::End { downloaded: 0 })
+            .await;
+        assert_eq!(server.requests().len(), 5);
+    }
+}